@@ -2,16 +2,16 @@ import pyacdc as acdc
 import matplotlib.pyplot as plt
 import numpy as np
 
-ac = acdc.LinearAcSweep(1e3,6e7,10001)
+ac = acdc.LinearAcSweep.decade(1000, 1e3, 6e7)
 
 ## Notch filter
-ac.add_resistor(1, 0, 22)
-ac.add_capacitor(2, 1, 303e-12)
-ac.add_inductor(3, 1, 30e-6)
-ac.add_resistor(2, 3, 0.6)
+ac.add_resistor("r1", "1", "0", 22)
+ac.add_capacitor("c1", "2", "1", 303e-12)
+ac.add_inductor("l1", "3", "1", 30e-6)
+ac.add_resistor("r2", "2", "3", 0.6)
 
 ## Source voltage
-ac.add_independent_voltage_source(2, 0, 5, 0)
+ac.add_independent_voltage_source("v1", "2", "0", 5)
 freq, magnitude, phase = ac.solve()
 
 m = np.array(magnitude[0]) / 5
@@ -1,10 +1,20 @@
-use libacdc::{dc, ac};
-use pyo3::{prelude::*, types::PyComplex};
+use libacdc::{dc, ac, error::AcdcError};
+use pyo3::{prelude::*, types::PyComplex, exceptions::{PyRuntimeError, PyValueError}};
 use num::Complex;
 
+/// Convert a library error into a Python exception
+fn to_py_err(error: AcdcError) -> PyErr {
+    match error {
+	AcdcError::Parse(_) | AcdcError::InvalidTopology(_) | AcdcError::InvalidAnalysis(_) => {
+	    PyValueError::new_err(error.to_string())
+	}
+	_ => PyRuntimeError::new_err(error.to_string()),
+    }
+}
+
 #[pyclass]
 struct LinearDcAnalysis {
-    dc: Option<dc::LinearDcAnalysis<f64>>,
+    dc: dc::LinearDcAnalysis,
 }
 
 #[pymethods]
@@ -12,40 +22,74 @@ impl LinearDcAnalysis {
     #[new]
     fn new() -> Self {
         Self {
-	    dc: Some(dc::LinearDcAnalysis::new())
+	    dc: dc::LinearDcAnalysis::new()
 	}
     }
 
+    #[pyo3(signature = (name, term_1, term_2, resistance, group2 = false))]
     pub fn add_resistor(
 	&mut self,
-	term_1: usize,
-	term_2: usize,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
 	resistance: f64,
-	current_edge: Option<usize>,
-    ) {
-	if let Some(ref mut dc) = self.dc {
-	    dc.add_resistor(term_1, term_2, current_edge, resistance)
-	}
+	group2: bool,
+    ) -> PyResult<()> {
+	self.dc
+	    .add_resistor(name, term_1, term_2, group2, resistance)
+	    .map_err(to_py_err)
     }
 
     pub fn add_independent_voltage_source(
 	&mut self,
-	term_pos: usize,
-	term_neg: usize,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
 	voltage: f64,
-	current_edge: usize,
-    ) {
-	if let Some(ref mut dc) = self.dc {
-	    dc.add_independent_voltage_source(term_pos, term_neg, current_edge, voltage)
-	}
+    ) -> PyResult<()> {
+	self.dc
+	    .add_independent_voltage_source(name, term_pos, term_neg, voltage)
+	    .map_err(to_py_err)
     }
 
-    pub fn solve(&mut self) -> (Vec<f64>, Vec<f64>) {
+    #[pyo3(signature = (name, term_pos, term_neg, current, group2 = false))]
+    pub fn add_independent_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	current: f64,
+	group2: bool,
+    ) -> PyResult<()> {
+	self.dc
+	    .add_independent_current_source(name, term_pos, term_neg, group2, current)
+	    .map_err(to_py_err)
+    }
 
-	match self.dc.take() {
-	    Some(dc) => dc.solve(),
-	    None => panic!("You already solved the system"),
-	}
+    pub fn add_ideal_op_amp(
+	&mut self,
+	name: &str,
+	in_pos: &str,
+	in_neg: &str,
+	out: &str,
+    ) -> PyResult<()> {
+	self.dc
+	    .add_ideal_op_amp(name, in_pos, in_neg, out)
+	    .map_err(to_py_err)
+    }
+
+    /// Change the value of the named element
+    pub fn set_value(&mut self, name: &str, value: f64) -> PyResult<()> {
+	self.dc.set_value(name, value).map_err(to_py_err)
+    }
+
+    /// Returns the voltage at each node except ground, in the order
+    /// the nodes were added, and the current in each edge
+    pub fn solve(&self) -> PyResult<(Vec<f64>, Vec<f64>)> {
+	let solution = self.dc.solve().map_err(to_py_err)?;
+	let voltages = solution.voltages().skip(1).map(|(_, v)| v).collect();
+	let currents = solution.currents().map(|(_, i)| i).collect();
+	Ok((voltages, currents))
     }
 }
 
@@ -57,59 +101,158 @@ struct LinearAcSweep {
 #[pymethods]
 impl LinearAcSweep {
     #[new]
-    fn new(f_start: f64, f_end: f64, num_steps: usize) -> Self {
-        Self {
-	    ac_sweep: ac::LinearAcSweep::new(f_start, f_end, num_steps),
+    fn new(f_start: f64, f_end: f64, num_steps: usize) -> PyResult<Self> {
+	let ac_sweep = ac::LinearAcSweep::new(f_start, f_end, num_steps).map_err(to_py_err)?;
+	Ok(Self { ac_sweep })
+    }
+
+    /// Sweep with points_per_decade logarithmically spaced points
+    #[staticmethod]
+    fn decade(points_per_decade: usize, f_start: f64, f_end: f64) -> PyResult<Self> {
+	let ac_sweep = ac::LinearAcSweep::sweep(ac::SweepType::Decade, points_per_decade, f_start, f_end)
+	    .map_err(to_py_err)?;
+	Ok(Self { ac_sweep })
+    }
+
+    /// Sweep with points_per_octave logarithmically spaced points
+    #[staticmethod]
+    fn octave(points_per_octave: usize, f_start: f64, f_end: f64) -> PyResult<Self> {
+	let ac_sweep = ac::LinearAcSweep::sweep(ac::SweepType::Octave, points_per_octave, f_start, f_end)
+	    .map_err(to_py_err)?;
+	Ok(Self { ac_sweep })
+    }
+
+    /// Sweep over a list of frequencies
+    #[staticmethod]
+    fn from_frequencies(frequencies: Vec<f64>) -> Self {
+	Self {
+	    ac_sweep: ac::LinearAcSweep::from_frequencies(frequencies),
 	}
     }
 
+    #[pyo3(signature = (name, term_1, term_2, resistance, group2 = false))]
     pub fn add_resistor(
 	&mut self,
-	term_1: usize,
-	term_2: usize,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
 	resistance: f64,
-	current_edge: Option<usize>,
-    ) {
-	self.ac_sweep.add_resistor(term_1, term_2, current_edge, resistance)
+	group2: bool,
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_resistor(name, term_1, term_2, group2, resistance)
+	    .map_err(to_py_err)
     }
 
-
+    #[pyo3(signature = (name, term_1, term_2, capacitance, group2 = false))]
     pub fn add_capacitor(
 	&mut self,
-	term_1: usize,
-	term_2: usize,
-	capacitor: f64,
-	current_edge: Option<usize>,
-    ) {
-	self.ac_sweep.add_capacitor(term_1, term_2, current_edge, capacitor)
+	name: &str,
+	term_1: &str,
+	term_2: &str,
+	capacitance: f64,
+	group2: bool,
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_capacitor(name, term_1, term_2, group2, capacitance)
+	    .map_err(to_py_err)
     }
 
     pub fn add_inductor(
 	&mut self,
-	term_1: usize,
-	term_2: usize,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
 	inductance: f64,
-	current_edge: Option<usize>,
-    ) {
-	self.ac_sweep.add_inductor(term_1, term_2, current_edge, inductance)
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_inductor(name, term_1, term_2, inductance)
+	    .map_err(to_py_err)
+    }
+
+    pub fn add_ideal_op_amp(
+	&mut self,
+	name: &str,
+	in_pos: &str,
+	in_neg: &str,
+	out: &str,
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_ideal_op_amp(name, in_pos, in_neg, out)
+	    .map_err(to_py_err)
+    }
+
+    pub fn add_mutual_inductance(
+	&mut self,
+	name: &str,
+	inductor_1: &str,
+	inductor_2: &str,
+	coupling: f64,
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_mutual_inductance(name, inductor_1, inductor_2, coupling)
+	    .map_err(to_py_err)
+    }
+
+    pub fn add_ideal_transformer(
+	&mut self,
+	name: &str,
+	pri_pos: &str,
+	pri_neg: &str,
+	sec_pos: &str,
+	sec_neg: &str,
+	ratio: f64,
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_ideal_transformer(name, pri_pos, pri_neg, sec_pos, sec_neg, ratio)
+	    .map_err(to_py_err)
     }
 
     pub fn add_independent_voltage_source(
 	&mut self,
-	term_pos: usize,
-	term_neg: usize,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
 	voltage: f64,
-	current_edge: usize,
-    ) {
-	self.ac_sweep.add_independent_voltage_source(term_pos, term_neg, current_edge, voltage)
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_independent_voltage_source(name, term_pos, term_neg, voltage)
+	    .map_err(to_py_err)
+    }
+
+    #[pyo3(signature = (name, term_pos, term_neg, current, group2 = false))]
+    pub fn add_independent_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	current: f64,
+	group2: bool,
+    ) -> PyResult<()> {
+	self.ac_sweep
+	    .circuit_mut()
+	    .add_independent_current_source(name, term_pos, term_neg, group2, current)
+	    .map_err(to_py_err)
+    }
+
+    /// Change the value of the named element
+    pub fn set_value(&mut self, name: &str, value: f64) -> PyResult<()> {
+	self.ac_sweep.circuit_mut().set_value(name, value).map_err(to_py_err)
     }
 
-    pub fn solve(&mut self) -> (Vec<f64>, Vec<Vec<f64>>, Vec<Vec<f64>>) {
-	let (freq, voltages_with_freq, ..) = self.ac_sweep.solve();
+    pub fn solve(&self) -> PyResult<(Vec<f64>, Vec<Vec<f64>>, Vec<Vec<f64>>)> {
+	let solution = self.ac_sweep.solve().map_err(to_py_err)?;
 
 	let mut magnitude = Vec::new();
 	let mut phase = Vec::new();
-	for vector in voltages_with_freq {
+	for (_, vector) in solution.voltages().skip(1) {
 	    let mut mag_vec = Vec::new();
 	    let mut phase_vec = Vec::new();
 	    for v in vector {
@@ -120,7 +263,7 @@ impl LinearAcSweep {
 	    phase.push(phase_vec);
 	}
 	
-	(freq, magnitude, phase)
+	Ok((solution.frequencies().to_vec(), magnitude, phase))
     }
 }
 
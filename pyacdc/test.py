@@ -1,9 +1,9 @@
 import pyacdc as acdc
 
 dc = acdc.LinearDcAnalysis()
-dc.add_resistor(1, 0, 100)
-dc.add_resistor(2, 1, 100)
-dc.add_independent_voltage_source(2, 0, 5, 0)
+dc.add_resistor("r1", "1", "0", 100)
+dc.add_resistor("r2", "2", "1", 100)
+dc.add_independent_voltage_source("v1", "2", "0", 5)
 voltages, currents = dc.solve()
 
 print(f"Voltages: {voltages}")
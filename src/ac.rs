@@ -1,273 +1,640 @@
 //! AC analysis
+//!
+//! [LinearAcAnalysis] and [LinearAcSweep] solve a [LinearAcCircuit]
+//! of linear elements added by hand. [SmallSignalAcSweep] is the
+//! SPICE `.ac` analysis of a [Circuit], which may contain nonlinear
+//! devices: the DC operating point is found first, and every device
+//! is linearised about it.
 
-use crate::mna::Mna;
+use crate::{
+    circuit::Circuit,
+    dc::DcSolution,
+    error::AcdcError,
+    mna::Mna,
+    newton::{self, NewtonOptions},
+    node_map::NodeMap,
+    solution::{find_edge, node_name, Solution},
+    waveform::SourceWaveform,
+};
 use num::Complex;
 use std::f64::consts::PI;
 
-pub struct LinearAcAnalysis {
-    omega: f64,
-    mna: Mna<Complex<f64>>,
+/// Node voltage and edge current phasors at one frequency
+pub type AcSolution = Solution<Complex<f64>>;
+
+/// Linear elements for an AC analysis, kept as devices of a [Circuit]
+/// and stamped each time the analysis is solved, so that they can be
+/// changed and solved again. [LinearAcAnalysis] and [LinearAcSweep]
+/// each hold one.
+///
+/// Elements are named, as in a [Circuit]: an element whose current is
+/// in group 2 has it under the element name, and an element is found
+/// by name to change its value.
+pub struct LinearAcCircuit {
+    circuit: Circuit,
 }
 
-impl LinearAcAnalysis {
-    /// New AC analysis at radial frequency omega
-    pub fn new(omega: f64) -> Self {
+impl LinearAcCircuit {
+    pub fn new() -> Self {
 	Self {
-	    omega,
-	    mna: Mna::new(),
+	    circuit: Circuit::new(),
 	}
     }
 
+    /// Map between node/edge names and the indices used in the solution
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
     pub fn add_resistor(
 	&mut self,
-	term_1: usize,
-	term_2: usize,
-	current_edge: Option<usize>,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
+	group2: bool,
 	resistance: f64,
-    ) {
-	let r = Complex::new(resistance, 0.0);
-	self.mna.add_impedance(term_1, term_2, current_edge, r);
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_resistor(name, term_1, term_2, group2, resistance)
     }
 
     pub fn add_capacitor(
 	&mut self,
-	term_1: usize,
-	term_2: usize,
-	current_edge: Option<usize>,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
+	group2: bool,
 	capacitance: f64,
-    ) {
-	let s = Complex::i() * self.omega;
-	let x = 1.0 / (s * capacitance);
-	self.mna.add_impedance(term_1, term_2, current_edge, x);
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_capacitor(name, term_1, term_2, group2, capacitance)
     }
 
+    /// Add an inductor, whose current is in group 2
     pub fn add_inductor(
 	&mut self,
-	term_1: usize,
-	term_2: usize,
-	current_edge: Option<usize>,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
 	inductance: f64,
-    ) {
-	let s = Complex::i() * self.omega;
-	let x = s * inductance;
-	self.mna.add_impedance(term_1, term_2, current_edge, x);
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_inductor(name, term_1, term_2, inductance)
     }
-    
+
+    /// Add a voltage source with an AC value of voltage
     pub fn add_independent_voltage_source(
-    	&mut self,
-    	term_pos: usize,
-    	term_neg: usize,
-    	current_edge: usize,
-    	voltage: f64,
-    ) {
-	let v = Complex::new(voltage, 0.0);
-    	self.mna.add_independent_voltage_source(term_pos, term_neg, current_edge, v);
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	voltage: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_voltage_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ac_waveform(voltage),
+	)
+    }
+
+    /// Add a current source with an AC value of current, driving
+    /// current out of term_pos, through the source, into term_neg
+    pub fn add_independent_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	group2: bool,
+	current: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_current_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    group2,
+	    ac_waveform(current),
+	)
+    }
+
+    /// Add a voltage source at the AC magnitude and phase of its waveform
+    pub fn add_independent_voltage_source_waveform(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	waveform: &SourceWaveform,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_voltage_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    waveform.clone(),
+	)
+    }
+
+    /// Add a current source at the AC magnitude and phase of its waveform
+    pub fn add_independent_current_source_waveform(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	group2: bool,
+	waveform: &SourceWaveform,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_current_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    group2,
+	    waveform.clone(),
+	)
+    }
+
+    /// Add a voltage source whose voltage is gain * (V(ctrl_pos) - V(ctrl_neg))
+    pub fn add_voltage_controlled_voltage_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_pos: &str,
+	ctrl_neg: &str,
+	gain: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_voltage_controlled_voltage_source(
+	    name, term_pos, term_neg, ctrl_pos, ctrl_neg, gain,
+	)
+    }
+
+    /// Add a voltage source whose voltage is transresistance times the
+    /// current through the element ctrl_element, which must be in
+    /// group 2
+    pub fn add_current_controlled_voltage_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_element: &str,
+	transresistance: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_current_controlled_voltage_source(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_element,
+	    transresistance,
+	)
+    }
+
+    /// Add a current source driving transconductance * (V(ctrl_pos) - V(ctrl_neg))
+    /// out of term_pos, through the source, into term_neg
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_voltage_controlled_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_pos: &str,
+	ctrl_neg: &str,
+	group2: bool,
+	transconductance: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_voltage_controlled_current_source(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_pos,
+	    ctrl_neg,
+	    group2,
+	    transconductance,
+	)
+    }
+
+    /// Add a current source driving gain times the current through the
+    /// element ctrl_element (which must be in group 2) out of term_pos,
+    /// through the source, into term_neg
+    pub fn add_current_controlled_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_element: &str,
+	group2: bool,
+	gain: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_current_controlled_current_source(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_element,
+	    group2,
+	    gain,
+	)
+    }
+
+    /// Add a mutual inductance k * sqrt(L1 * L2) between two
+    /// inductors, which must already be in the circuit, for coupling
+    /// coefficient k
+    pub fn add_mutual_inductance(
+	&mut self,
+	name: &str,
+	inductor_1: &str,
+	inductor_2: &str,
+	coupling: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit
+	    .add_mutual_inductance(name, inductor_1, inductor_2, coupling)
     }
 
-    pub fn solve(self) -> (Vec<Complex<f64>>, Vec<Complex<f64>>) {
-	self.mna.solve()
+    /// Add an ideal transformer with turns ratio (primary turns) /
+    /// (secondary turns). The primary current is in group 2.
+    pub fn add_ideal_transformer(
+	&mut self,
+	name: &str,
+	pri_pos: &str,
+	pri_neg: &str,
+	sec_pos: &str,
+	sec_neg: &str,
+	ratio: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit
+	    .add_ideal_transformer(name, pri_pos, pri_neg, sec_pos, sec_neg, ratio)
+    }
+
+    /// Add an ideal op-amp, whose output (relative to ground) holds
+    /// in_pos and in_neg at the same voltage. The output current is in
+    /// group 2.
+    pub fn add_ideal_op_amp(
+	&mut self,
+	name: &str,
+	in_pos: &str,
+	in_neg: &str,
+	out: &str,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_ideal_op_amp(name, in_pos, in_neg, out)
+    }
+
+    /// Change the value of the named element: the resistance,
+    /// capacitance or inductance, the AC value of a source (which
+    /// becomes real), the gain, the mutual inductance or the turns
+    /// ratio
+    pub fn set_value(&mut self, name: &str, value: f64) -> Result<(), AcdcError> {
+	self.circuit
+	    .set_value(name, value, |waveform| waveform.set_ac(value, 0.0))
+    }
+
+    /// Solve the circuit at radial frequency omega
+    fn solve(&self, omega: f64) -> Result<AcSolution, AcdcError> {
+	let mut mna = Mna::new();
+	for device in self.circuit.devices().iter() {
+	    device.stamp_ac(&mut mna, omega)?;
+	}
+	Ok(Solution::new(self.node_map().clone(), mna.solve()?))
     }
-    
 }
 
-enum Impedance {
-    Resistor(f64),
-    Capacitor(f64),
-    Inductor(f64),
+/// Waveform of a source with a real AC value (and no DC value)
+fn ac_waveform(value: f64) -> SourceWaveform {
+    let mut waveform = SourceWaveform::default();
+    waveform.set_ac(value, 0.0);
+    waveform
 }
 
-impl Impedance {
-    fn impedance(&self, freq_hz: f64) -> Complex<f64> {
-	match self {
-	    Impedance::Resistor(r) => Complex::new(*r, 0.0),
-	    Impedance::Capacitor(c) => {
-		let omega = 2.0 * PI * freq_hz;
-		let s = Complex::i() * omega;		
-		1.0 / (s * c)
-	    },
-	    Impedance::Inductor(l) => {
-		let omega = 2.0 * PI * freq_hz;
-		let s = Complex::i() * omega;		
-		s * l		
-	    }
+/// AC analysis of a [LinearAcCircuit] at a single frequency
+pub struct LinearAcAnalysis {
+    omega: f64,
+    circuit: LinearAcCircuit,
+}
+
+impl LinearAcAnalysis {
+    /// New AC analysis at radial frequency omega
+    pub fn new(omega: f64) -> Self {
+	Self {
+	    omega,
+	    circuit: LinearAcCircuit::new(),
 	}
     }
+
+    /// Radial frequency of the analysis
+    pub fn omega(&self) -> f64 {
+	self.omega
+    }
+
+    pub fn set_omega(&mut self, omega: f64) {
+	self.omega = omega;
+    }
+
+    pub fn circuit(&self) -> &LinearAcCircuit {
+	&self.circuit
+    }
+
+    /// The circuit, to add elements or change their values
+    pub fn circuit_mut(&mut self) -> &mut LinearAcCircuit {
+	&mut self.circuit
+    }
+
+    /// Map between node/edge names and the indices used in the solution
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
+    pub fn solve(&self) -> Result<AcSolution, AcdcError> {
+	self.circuit.solve(self.omega)
+    }
 }
 
-enum Element {
-    Impedance {
-	term_1: usize,
-	term_2: usize,
-	current_edge: Option<usize>,
-	/// Closure to calculate the impedance at
-	/// a particular frequency
-	impedance: Impedance,
-    },
-    VoltageSource {
-	term_pos: usize,
-	term_neg: usize,
-	current_edge: usize,
-	voltage: f64,
-    },
+/// Spacing of the frequencies in an AC sweep, as in SPICE `.ac`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SweepType {
+    /// A total number of linearly spaced points
+    Linear,
+    /// A number of logarithmically spaced points per decade
+    Decade,
+    /// A number of logarithmically spaced points per octave
+    Octave,
 }
 
-pub struct LinearAcSweep {
+/// Frequencies (in Hz) from f_start to f_end inclusive. For a
+/// logarithmic sweep, num_points is the number of points per decade or
+/// octave.
+pub fn sweep_frequencies(
+    sweep: SweepType,
+    num_points: usize,
     f_start: f64,
     f_end: f64,
-    num_steps: usize,
+) -> Result<Vec<f64>, AcdcError> {
+    if num_points == 0 || !(f_end >= f_start) || f_start < 0.0 {
+	return Err(AcdcError::InvalidAnalysis(format!(
+	    "invalid sweep of {} points from {} Hz to {} Hz",
+	    num_points, f_start, f_end
+	)));
+    }
+    let base = match sweep {
+	SweepType::Linear => {
+	    if num_points == 1 || f_start == f_end {
+		return Ok(vec![f_start]);
+	    }
+	    let df = (f_end - f_start) / (num_points - 1) as f64;
+	    return Ok((0..num_points).map(|n| f_start + n as f64 * df).collect());
+	}
+	SweepType::Decade => 10.0f64,
+	SweepType::Octave => 2.0f64,
+    };
+    if f_start == 0.0 {
+	return Err(AcdcError::InvalidAnalysis(String::from(
+	    "logarithmic sweep cannot start at 0 Hz",
+	)));
+    }
+    // Points at a fixed ratio from f_start, finishing exactly at f_end
+    let ratio = base.powf(1.0 / num_points as f64);
+    let mut f = vec![f_start];
+    let mut n = 1;
+    loop {
+	let next = f_start * ratio.powi(n);
+	if next >= f_end * (1.0 - 1e-9) {
+	    break;
+	}
+	f.push(next);
+	n += 1;
+    }
+    if f_end > f_start {
+	f.push(f_end);
+    }
+    Ok(f)
+}
+
+/// AC sweep of a [LinearAcCircuit] over a list of frequencies
+pub struct LinearAcSweep {
     f: Vec<f64>,
-    elements: Vec<Element>,
+    circuit: LinearAcCircuit,
 }
 
 impl LinearAcSweep {
-    pub fn new(f_start: f64, f_end: f64, num_steps: usize) -> Self {
+    /// New sweep of num_steps linearly spaced frequencies (in Hz),
+    /// from f_start to f_end inclusive
+    pub fn new(f_start: f64, f_end: f64, num_steps: usize) -> Result<Self, AcdcError> {
+	Self::sweep(SweepType::Linear, num_steps, f_start, f_end)
+    }
+
+    /// New sweep from f_start to f_end inclusive, where num_points is
+    /// the total number of points (for a linear sweep) or the number of
+    /// points per decade or octave (for a logarithmic sweep)
+    pub fn sweep(
+	sweep: SweepType,
+	num_points: usize,
+	f_start: f64,
+	f_end: f64,
+    ) -> Result<Self, AcdcError> {
+	let f = sweep_frequencies(sweep, num_points, f_start, f_end)?;
+	Ok(Self::from_frequencies(f))
+    }
+
+    /// New sweep over a list of frequencies (in Hz), in the order given
+    pub fn from_frequencies(f: Vec<f64>) -> Self {
 	Self {
-	    f_start,
-	    f_end,
-	    num_steps,
-	    f: (0..num_steps)
-		.map(|n| f_start + (n as f64) * (f_end - f_start) / num_steps as f64)
-		.collect(),
-	    elements: Vec::new(),
+	    f,
+	    circuit: LinearAcCircuit::new(),
 	}
     }
 
-    pub fn add_resistor(
-	&mut self,
-	term_1: usize,
-	term_2: usize,
-	current_edge: Option<usize>,
-	resistance: f64,
-    ) {
-	let element = Element::Impedance {
-	    term_1,
-	    term_2,
-	    current_edge,
-	    impedance: Impedance::Resistor(resistance),
-	};
-	self.elements.push(element);
+    /// Frequencies (in Hz) of the sweep
+    pub fn frequencies(&self) -> &[f64] {
+	&self.f
     }
 
-    pub fn add_capacitor(
-	&mut self,
-	term_1: usize,
-	term_2: usize,
-	current_edge: Option<usize>,
-	capacitance: f64,
-    ) {
-	let element = Element::Impedance {
-	    term_1,
-	    term_2,
-	    current_edge,
-	    impedance: Impedance::Capacitor(capacitance),
-	};
-	self.elements.push(element);
+    pub fn circuit(&self) -> &LinearAcCircuit {
+	&self.circuit
     }
 
-    pub fn add_inductor(
-	&mut self,
-	term_1: usize,
-	term_2: usize,
-	current_edge: Option<usize>,
-	inductance: f64,
-    ) {
-	let element = Element::Impedance {
-	    term_1,
-	    term_2,
-	    current_edge,
-	    impedance: Impedance::Inductor(inductance),
-	};
-	self.elements.push(element);
-    }
-    
-    pub fn add_independent_voltage_source(
-    	&mut self,
-    	term_pos: usize,
-    	term_neg: usize,
-    	current_edge: usize,
-    	voltage: f64,
-    ) {
-	let source = Element::VoltageSource {
-	    term_pos,
-	    term_neg,
-	    current_edge,
-	    voltage,
-	};
-	self.elements.push(source);
+    /// The circuit, to add elements or change their values
+    pub fn circuit_mut(&mut self) -> &mut LinearAcCircuit {
+	&mut self.circuit
     }
 
-    pub fn solve(&self) -> (Vec<f64>, Vec<Vec<Complex<f64>>>, Vec<Vec<Complex<f64>>>) {
+    /// Map between node/edge names and the indices used in the solution
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
 
-	let mut voltages_with_frequency = Vec::new();
-	let mut currents_with_frequency = Vec::new();
-	
+    pub fn solve(&self) -> Result<AcSweepSolution, AcdcError> {
+	let mut sweep = AcSweepSolution::new(self.circuit.node_map().clone());
 	for freq_hz in self.f.iter() {
+	    let solution = self.circuit.solve(2.0 * PI * freq_hz)?;
+	    sweep.push(*freq_hz, &solution);
+	}
+	Ok(sweep)
+    }
+}
 
-	    // Make new modal analysis matrix
-	    let mut mna = Mna::new();
+/// AC sweep of a circuit that may contain nonlinear devices. The DC
+/// operating point is found first, and the small-signal model of every
+/// device, linearised about it, is solved at each frequency. Only
+/// sources with an AC magnitude excite the circuit; the others are
+/// held at zero.
+pub struct SmallSignalAcSweep {
+    circuit: Circuit,
+    options: NewtonOptions,
+    f: Vec<f64>,
+}
 
-	    // Add all the elements
-	    for elem in self.elements.iter() {
-		match elem {
-		    Element::Impedance {
-			term_1,
-			term_2,
-			current_edge,
-			impedance
-		    } => {
-			mna.add_impedance(*term_1, *term_2, *current_edge,
-					 impedance.impedance(*freq_hz));
-		    },
-		    Element::VoltageSource {
-			term_pos,
-			term_neg,
-			current_edge,
-			voltage
-		    } => {
-    			mna.add_independent_voltage_source(*term_pos, *term_neg, *current_edge,
-							   voltage.into());
-		    }
+impl SmallSignalAcSweep {
+    /// New sweep from f_start to f_end (inclusive, in Hz). For a
+    /// logarithmic sweep, num_points is the number of points per
+    /// decade or octave; for a linear sweep, it is the total.
+    pub fn new(
+	circuit: Circuit,
+	sweep: SweepType,
+	num_points: usize,
+	f_start: f64,
+	f_end: f64,
+    ) -> Result<Self, AcdcError> {
+	let f = sweep_frequencies(sweep, num_points, f_start, f_end)?;
+	Ok(Self::from_frequencies(circuit, f))
+    }
+
+    /// New sweep over a list of frequencies (in Hz), in the order given
+    pub fn from_frequencies(circuit: Circuit, f: Vec<f64>) -> Self {
+	Self {
+	    circuit,
+	    options: NewtonOptions::default(),
+	    f,
+	}
+    }
+
+    /// Frequencies (in Hz) of the sweep
+    pub fn frequencies(&self) -> &[f64] {
+	&self.f
+    }
+
+    pub fn circuit(&self) -> &Circuit {
+	&self.circuit
+    }
+
+    /// Map between node/edge names and the indices used in the solution
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
+    /// Newton-Raphson tolerances and limits for the operating point
+    pub fn options(&self) -> &NewtonOptions {
+	&self.options
+    }
+
+    pub fn set_options(&mut self, options: NewtonOptions) {
+	self.options = options;
+    }
+
+    /// Find the DC operating point, leaving every device linearised
+    /// about it. Iteration starts with every node voltage and edge
+    /// current at zero.
+    pub fn operating_point(&mut self) -> Result<DcSolution, AcdcError> {
+	let solution = newton::solve_circuit(
+	    &mut self.circuit,
+	    (Vec::new(), Vec::new()),
+	    &self.options,
+	    |circuit, mna, x| {
+		for device in circuit.devices() {
+		    device.stamp_dc(mna, x)?;
 		}
+		Ok(())
+	    },
+	)?;
+	Ok(Solution::new(self.circuit.node_map().clone(), solution))
+    }
+
+    /// Find the operating point, then solve the small-signal circuit
+    /// at each frequency
+    pub fn solve(&mut self) -> Result<AcSweepSolution, AcdcError> {
+	self.operating_point()?;
+	let node_map = self.circuit.node_map().clone();
+	let mut sweep = AcSweepSolution::new(node_map.clone());
+	for freq_hz in self.f.iter() {
+	    let mut mna = Mna::new();
+	    let omega = 2.0 * PI * freq_hz;
+	    for device in self.circuit.devices() {
+		device.stamp_ac(&mut mna, omega)?;
 	    }
+	    let solution = Solution::new(node_map.clone(), mna.solve()?);
+	    sweep.push(*freq_hz, &solution);
+	}
+	Ok(sweep)
+    }
+}
 
-	    // Solve the system at this frequency
-	    let (voltages, currents) = mna.solve();
+/// Node voltage and edge current phasors over an AC sweep, by name
+#[derive(Debug, Clone)]
+pub struct AcSweepSolution {
+    node_map: NodeMap,
+    frequencies: Vec<f64>,
+    /// Voltage at each node (including ground) at each frequency
+    voltages: Vec<Vec<Complex<f64>>>,
+    /// Current in each edge at each frequency
+    currents: Vec<Vec<Complex<f64>>>,
+}
 
-	    voltages_with_frequency.push(voltages);
-	    currents_with_frequency.push(currents);
+impl AcSweepSolution {
+    fn new(node_map: NodeMap) -> Self {
+	let voltages = vec![Vec::new(); node_map.num_nodes()];
+	let currents = vec![Vec::new(); node_map.num_edges()];
+	Self {
+	    node_map,
+	    frequencies: Vec::new(),
+	    voltages,
+	    currents,
 	}
+    }
 
-	
-	
-	// Convert to vectors of voltage with frequency at each node
-	let mut v = Vec::new();
-	let num_voltage_nodes = voltages_with_frequency[0].len();
-
-	// Loop over voltages
-	for n in 0..num_voltage_nodes {
-	    let mut v_at_one_frequency = Vec::new();
-	    // Loop over frequencies
-	    for m in 0..self.f.len() {
-		v_at_one_frequency.push(voltages_with_frequency[m][n])
-	    }
-	    v.push(v_at_one_frequency);
+    fn push(&mut self, freq_hz: f64, solution: &AcSolution) {
+	self.frequencies.push(freq_hz);
+	for (v, (_, value)) in self.voltages.iter_mut().zip(solution.voltages()) {
+	    v.push(value);
 	}
-	
-	let mut i = Vec::new();
-	let num_current_nodes = currents_with_frequency[0].len();
-
-	// Loop over currents
-	for n in 0..num_current_nodes {
-	    let mut i_at_one_frequency = Vec::new();
-	    // Loop over frequencies
-	    for m in 0..self.f.len() {
-		i_at_one_frequency.push(currents_with_frequency[m][n])
-	    }
-	    i.push(i_at_one_frequency);
+	for (i, (_, value)) in self.currents.iter_mut().zip(solution.currents()) {
+	    i.push(value);
 	}
+    }
+
+    /// Frequencies of the sweep, in Hz
+    pub fn frequencies(&self) -> &[f64] {
+	&self.frequencies
+    }
+
+    /// Voltage at the named node at each frequency, where ground is
+    /// at zero
+    pub fn v(&self, node: &str) -> Option<&[Complex<f64>]> {
+	let n = self.node_map.find_node(node)?;
+	Some(&self.voltages[n])
+    }
+
+    /// Voltage between two named nodes, V(pos) - V(neg), at each
+    /// frequency
+    pub fn v_diff(&self, pos: &str, neg: &str) -> Option<Vec<Complex<f64>>> {
+	let (pos, neg) = (self.v(pos)?, self.v(neg)?);
+	Some(pos.iter().zip(neg).map(|(p, n)| p - n).collect())
+    }
+
+    /// Current in the named edge at each frequency, which for a
+    /// circuit read from a netlist is the name of its element
+    pub fn i(&self, name: &str) -> Option<&[Complex<f64>]> {
+	let e = find_edge(&self.node_map, name)?;
+	Some(&self.currents[e])
+    }
+
+    /// Name and voltages of every node, starting with ground
+    pub fn voltages(&self) -> impl Iterator<Item = (&str, &[Complex<f64>])> + '_ {
+	self.voltages
+	    .iter()
+	    .enumerate()
+	    .map(|(n, v)| (node_name(&self.node_map, n), v.as_slice()))
+    }
+
+    /// Name and currents of every edge
+    pub fn currents(&self) -> impl Iterator<Item = (&str, &[Complex<f64>])> + '_ {
+	self.currents
+	    .iter()
+	    .enumerate()
+	    .map(|(e, i)| (self.node_map.edge_name(e).as_str(), i.as_slice()))
+    }
 
-	
-	    
-	(self.f.to_vec(), v, i)
+    /// Map between node/edge names and the indices used by the analysis
+    pub fn node_map(&self) -> &NodeMap {
+	&self.node_map
     }
 }
@@ -0,0 +1,584 @@
+//! Circuit description
+//!
+//! A [Circuit] is a list of named devices connected between named
+//! nodes. Analyses that need to rebuild the system more than once
+//! (such as transient analysis) work from a circuit rather than
+//! stamping elements directly.
+//!
+//! Elements that can be in either group 1 or group 2 take a group2
+//! flag. If it is set, the element current is in group 2 under the
+//! element name. Voltage sources, inductors, the primaries of ideal
+//! transformers and the outputs of ideal op-amps are always in group 2.
+//!
+//! Devices with internal nodes (such as the node between a diode
+//! junction and its series resistance) name them after the device,
+//! for example `d1#anode`, `q1#base` or `m1#drain`.
+
+use crate::{
+    device::{
+	bjt::{Bjt, BjtModel},
+	diode::{Diode, DiodeModel},
+	mosfet::{Mosfet, MosfetModel},
+	linear::{
+	    Capacitor, Cccs, Ccvs, CurrentSource, IdealOpAmp, IdealTransformer, Inductor,
+	    MutualInductance, Resistor, Vccs, Vcvs, VoltageSource,
+	},
+	Device, NOMINAL_TEMPERATURE,
+    },
+    error::AcdcError,
+    node_map::NodeMap,
+    waveform::SourceWaveform,
+};
+
+pub struct Circuit {
+    node_map: NodeMap,
+    devices: Vec<Box<dyn Device>>,
+    /// Temperature of every device (in kelvin)
+    temperature: f64,
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+	Self {
+	    node_map: NodeMap::new(),
+	    devices: Vec::new(),
+	    temperature: NOMINAL_TEMPERATURE,
+	}
+    }
+
+    /// Map between node/edge names and the indices used by the devices
+    pub fn node_map(&self) -> &NodeMap {
+	&self.node_map
+    }
+
+    pub fn devices(&self) -> &[Box<dyn Device>] {
+	&self.devices
+    }
+
+    pub fn devices_mut(&mut self) -> &mut [Box<dyn Device>] {
+	&mut self.devices
+    }
+
+    /// The device with a name
+    pub fn device(&self, name: &str) -> Option<&dyn Device> {
+	self.devices.iter().find(|d| d.name() == name).map(|d| d.as_ref())
+    }
+
+    pub fn device_mut(&mut self, name: &str) -> Option<&mut dyn Device> {
+	for device in self.devices.iter_mut() {
+	    if device.name() == name {
+		return Some(device.as_mut());
+	    }
+	}
+	None
+    }
+
+    /// Index of a node, allocating a new one if required (for use
+    /// when constructing devices outside this module)
+    pub fn node_index(&mut self, node_name: &str) -> Result<usize, AcdcError> {
+	self.node_map.node_index(node_name)
+    }
+
+    /// Index of an edge, allocating a new one if required
+    pub fn edge_index(&mut self, edge_name: &str) -> usize {
+	self.node_map.edge_index(edge_name)
+    }
+
+    /// Add a device. Device names must be unique.
+    pub fn add_device(&mut self, mut device: Box<dyn Device>) -> Result<(), AcdcError> {
+	if self.devices.iter().any(|d| d.name() == device.name()) {
+	    return Err(AcdcError::InvalidTopology(format!(
+		"duplicate device name {}",
+		device.name()
+	    )));
+	}
+	if self.temperature != NOMINAL_TEMPERATURE {
+	    device.set_temperature(self.temperature);
+	}
+	self.devices.push(device);
+	Ok(())
+    }
+
+    /// Temperature of the devices (in kelvin)
+    pub fn temperature(&self) -> f64 {
+	self.temperature
+    }
+
+    /// Set the temperature of every device (in kelvin)
+    pub fn set_temperature(&mut self, temperature: f64) {
+	self.temperature = temperature;
+	for device in self.devices.iter_mut() {
+	    device.set_temperature(temperature);
+	}
+    }
+
+    /// Set the DC value of the named independent source, returning
+    /// its previous value
+    pub fn set_source_value(&mut self, name: &str, value: f64) -> Result<f64, AcdcError> {
+	let waveform = self
+	    .device_mut(name)
+	    .and_then(|d| d.waveform_mut())
+	    .ok_or_else(|| {
+		AcdcError::InvalidAnalysis(format!("no independent source named {}", name))
+	    })?;
+	let previous = waveform.dc_value();
+	waveform.set_dc(value);
+	Ok(previous)
+    }
+
+    /// Change the value of the named device for a linear analysis: an
+    /// independent source has its waveform changed by set_source, and
+    /// any other device its first value (see [Device::params]), such
+    /// as a resistance or a gain
+    pub(crate) fn set_value<F>(
+	&mut self,
+	name: &str,
+	value: f64,
+	set_source: F,
+    ) -> Result<(), AcdcError>
+    where
+	F: FnOnce(&mut SourceWaveform),
+    {
+	let device = self
+	    .device_mut(name)
+	    .ok_or_else(|| AcdcError::InvalidAnalysis(format!("no element named {}", name)))?;
+	if let Some(waveform) = device.waveform_mut() {
+	    set_source(waveform);
+	    return Ok(());
+	}
+	match device.params().first() {
+	    Some((param, _)) => {
+		device.set_param(param, value);
+		Ok(())
+	    }
+	    None => Err(AcdcError::InvalidAnalysis(format!(
+		"{} has no value to change",
+		name
+	    ))),
+	}
+    }
+
+    /// The edge for an element whose current may be in group 2
+    fn optional_edge(&mut self, name: &str, group2: bool) -> Option<usize> {
+	if group2 {
+	    Some(self.node_map.edge_index(name))
+	} else {
+	    None
+	}
+    }
+
+    pub fn add_resistor(
+	&mut self,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
+	group2: bool,
+	resistance: f64,
+    ) -> Result<(), AcdcError> {
+	let term_1 = self.node_map.node_index(term_1)?;
+	let term_2 = self.node_map.node_index(term_2)?;
+	let current_edge = self.optional_edge(name, group2);
+	self.add_device(Box::new(Resistor::new(
+	    name,
+	    term_1,
+	    term_2,
+	    current_edge,
+	    resistance,
+	)))
+    }
+
+    pub fn add_capacitor(
+	&mut self,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
+	group2: bool,
+	capacitance: f64,
+    ) -> Result<(), AcdcError> {
+	let term_1 = self.node_map.node_index(term_1)?;
+	let term_2 = self.node_map.node_index(term_2)?;
+	let current_edge = self.optional_edge(name, group2);
+	self.add_device(Box::new(Capacitor::new(
+	    name,
+	    term_1,
+	    term_2,
+	    current_edge,
+	    capacitance,
+	)))
+    }
+
+    pub fn add_inductor(
+	&mut self,
+	name: &str,
+	term_1: &str,
+	term_2: &str,
+	inductance: f64,
+    ) -> Result<(), AcdcError> {
+	let term_1 = self.node_map.node_index(term_1)?;
+	let term_2 = self.node_map.node_index(term_2)?;
+	let current_edge = self.node_map.edge_index(name);
+	self.add_device(Box::new(Inductor::new(
+	    name,
+	    term_1,
+	    term_2,
+	    current_edge,
+	    inductance,
+	)))
+    }
+
+    /// Add a mutual inductance k * sqrt(L1 * L2) between two
+    /// inductors, which must already be in the circuit, for coupling
+    /// coefficient k
+    pub fn add_mutual_inductance(
+	&mut self,
+	name: &str,
+	inductor_1: &str,
+	inductor_2: &str,
+	coupling: f64,
+    ) -> Result<(), AcdcError> {
+	let (edge_1, l1) = self.inductor(name, inductor_1)?;
+	let (edge_2, l2) = self.inductor(name, inductor_2)?;
+	let inductance = coupling * (l1 * l2).sqrt();
+	self.add_device(Box::new(MutualInductance::new(name, edge_1, edge_2, inductance)))
+    }
+
+    /// Current edge and inductance of an inductor that name couples
+    fn inductor(&self, name: &str, inductor: &str) -> Result<(usize, f64), AcdcError> {
+	let device = self.device(inductor).ok_or_else(|| {
+	    AcdcError::InvalidTopology(format!("{} couples unknown inductor {}", name, inductor))
+	})?;
+	device.inductance().ok_or_else(|| {
+	    AcdcError::InvalidTopology(format!(
+		"{} couples {}, which is not an inductor",
+		name, inductor
+	    ))
+	})
+    }
+
+    /// Add an ideal transformer with turns ratio (primary turns) /
+    /// (secondary turns). The primary current is in group 2.
+    pub fn add_ideal_transformer(
+	&mut self,
+	name: &str,
+	pri_pos: &str,
+	pri_neg: &str,
+	sec_pos: &str,
+	sec_neg: &str,
+	ratio: f64,
+    ) -> Result<(), AcdcError> {
+	let pri_pos = self.node_map.node_index(pri_pos)?;
+	let pri_neg = self.node_map.node_index(pri_neg)?;
+	let sec_pos = self.node_map.node_index(sec_pos)?;
+	let sec_neg = self.node_map.node_index(sec_neg)?;
+	let current_edge = self.node_map.edge_index(name);
+	self.add_device(Box::new(IdealTransformer::new(
+	    name,
+	    pri_pos,
+	    pri_neg,
+	    sec_pos,
+	    sec_neg,
+	    current_edge,
+	    ratio,
+	)))
+    }
+
+    /// Add an ideal op-amp, whose output (relative to ground) holds
+    /// in_pos and in_neg at the same voltage. The output current is in
+    /// group 2.
+    pub fn add_ideal_op_amp(
+	&mut self,
+	name: &str,
+	in_pos: &str,
+	in_neg: &str,
+	out: &str,
+    ) -> Result<(), AcdcError> {
+	let in_pos = self.node_map.node_index(in_pos)?;
+	let in_neg = self.node_map.node_index(in_neg)?;
+	let out = self.node_map.node_index(out)?;
+	let current_edge = self.node_map.edge_index(name);
+	self.add_device(Box::new(IdealOpAmp::new(name, in_pos, in_neg, out, current_edge)))
+    }
+
+    pub fn add_independent_voltage_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	voltage: f64,
+    ) -> Result<(), AcdcError> {
+	self.add_independent_voltage_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    SourceWaveform::dc(voltage),
+	)
+    }
+
+    /// Add a voltage source whose value depends on the analysis
+    pub fn add_independent_voltage_source_waveform(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	waveform: SourceWaveform,
+    ) -> Result<(), AcdcError> {
+	let term_pos = self.node_map.node_index(term_pos)?;
+	let term_neg = self.node_map.node_index(term_neg)?;
+	let current_edge = self.node_map.edge_index(name);
+	self.add_device(Box::new(VoltageSource::new(
+	    name,
+	    term_pos,
+	    term_neg,
+	    current_edge,
+	    waveform,
+	)))
+    }
+
+    /// Add a current source driving current out of term_pos, through
+    /// the source, into term_neg
+    pub fn add_independent_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	group2: bool,
+	current: f64,
+    ) -> Result<(), AcdcError> {
+	self.add_independent_current_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    group2,
+	    SourceWaveform::dc(current),
+	)
+    }
+
+    /// Add a current source whose value depends on the analysis
+    pub fn add_independent_current_source_waveform(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	group2: bool,
+	waveform: SourceWaveform,
+    ) -> Result<(), AcdcError> {
+	let term_pos = self.node_map.node_index(term_pos)?;
+	let term_neg = self.node_map.node_index(term_neg)?;
+	let current_edge = self.optional_edge(name, group2);
+	self.add_device(Box::new(CurrentSource::new(
+	    name,
+	    term_pos,
+	    term_neg,
+	    current_edge,
+	    waveform,
+	)))
+    }
+
+    /// Add a junction diode, where area scales the model currents and
+    /// capacitances
+    pub fn add_diode(
+	&mut self,
+	name: &str,
+	anode: &str,
+	cathode: &str,
+	model: &DiodeModel,
+	area: f64,
+    ) -> Result<(), AcdcError> {
+	let anode_index = self.node_map.node_index(anode)?;
+	let cathode = self.node_map.node_index(cathode)?;
+	let internal = if model.rs > 0.0 {
+	    self.node_map.node_index(&format!("{}#anode", name))?
+	} else {
+	    anode_index
+	};
+	self.add_device(Box::new(Diode::new(
+	    name,
+	    anode_index,
+	    cathode,
+	    internal,
+	    model.clone(),
+	    area,
+	)))
+    }
+
+    /// Add a bipolar transistor. Internal nodes are added for the
+    /// model's terminal resistances.
+    pub fn add_bjt(
+	&mut self,
+	name: &str,
+	collector: &str,
+	base: &str,
+	emitter: &str,
+	model: &BjtModel,
+	area: f64,
+    ) -> Result<(), AcdcError> {
+	let mut terminals = [0; 3];
+	let mut internal = [0; 3];
+	for (k, (node, suffix, r)) in [
+	    (collector, "collector", model.rc),
+	    (base, "base", model.rb),
+	    (emitter, "emitter", model.re),
+	]
+	.into_iter()
+	.enumerate()
+	{
+	    terminals[k] = self.node_map.node_index(node)?;
+	    internal[k] = if r > 0.0 {
+		self.node_map.node_index(&format!("{}#{}", name, suffix))?
+	    } else {
+		terminals[k]
+	    };
+	}
+	self.add_device(Box::new(Bjt::new(
+	    name,
+	    terminals,
+	    internal,
+	    model.clone(),
+	    area,
+	)))
+    }
+
+    /// Add a MOSFET with channel width w and length l. Internal nodes
+    /// are added for the model's drain and source resistances.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_mosfet(
+	&mut self,
+	name: &str,
+	drain: &str,
+	gate: &str,
+	source: &str,
+	bulk: &str,
+	model: &MosfetModel,
+	w: f64,
+	l: f64,
+    ) -> Result<(), AcdcError> {
+	let terminals = [
+	    self.node_map.node_index(drain)?,
+	    self.node_map.node_index(gate)?,
+	    self.node_map.node_index(source)?,
+	    self.node_map.node_index(bulk)?,
+	];
+	let drain_internal = if model.rd > 0.0 {
+	    self.node_map.node_index(&format!("{}#drain", name))?
+	} else {
+	    terminals[0]
+	};
+	let source_internal = if model.rs > 0.0 {
+	    self.node_map.node_index(&format!("{}#source", name))?
+	} else {
+	    terminals[2]
+	};
+	self.add_device(Box::new(Mosfet::new(
+	    name,
+	    terminals,
+	    [drain_internal, source_internal],
+	    model.clone(),
+	    w,
+	    l,
+	)))
+    }
+
+    /// Add a voltage source whose voltage is gain * (V(ctrl_pos) - V(ctrl_neg))
+    pub fn add_voltage_controlled_voltage_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_pos: &str,
+	ctrl_neg: &str,
+	gain: f64,
+    ) -> Result<(), AcdcError> {
+	let term_pos = self.node_map.node_index(term_pos)?;
+	let term_neg = self.node_map.node_index(term_neg)?;
+	let ctrl_pos = self.node_map.node_index(ctrl_pos)?;
+	let ctrl_neg = self.node_map.node_index(ctrl_neg)?;
+	let current_edge = self.node_map.edge_index(name);
+	self.add_device(Box::new(Vcvs::new(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_pos,
+	    ctrl_neg,
+	    current_edge,
+	    gain,
+	)))
+    }
+
+    /// Add a voltage source whose voltage is transresistance times the
+    /// current through the element ctrl_element, which must be in group 2
+    pub fn add_current_controlled_voltage_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_element: &str,
+	transresistance: f64,
+    ) -> Result<(), AcdcError> {
+	let term_pos = self.node_map.node_index(term_pos)?;
+	let term_neg = self.node_map.node_index(term_neg)?;
+	let ctrl_edge = self.node_map.edge_index(ctrl_element);
+	let current_edge = self.node_map.edge_index(name);
+	self.add_device(Box::new(Ccvs::new(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_edge,
+	    current_edge,
+	    transresistance,
+	)))
+    }
+
+    /// Add a current source driving transconductance * (V(ctrl_pos) - V(ctrl_neg))
+    /// out of term_pos, through the source, into term_neg
+    pub fn add_voltage_controlled_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_pos: &str,
+	ctrl_neg: &str,
+	group2: bool,
+	transconductance: f64,
+    ) -> Result<(), AcdcError> {
+	let term_pos = self.node_map.node_index(term_pos)?;
+	let term_neg = self.node_map.node_index(term_neg)?;
+	let ctrl_pos = self.node_map.node_index(ctrl_pos)?;
+	let ctrl_neg = self.node_map.node_index(ctrl_neg)?;
+	let current_edge = self.optional_edge(name, group2);
+	self.add_device(Box::new(Vccs::new(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_pos,
+	    ctrl_neg,
+	    current_edge,
+	    transconductance,
+	)))
+    }
+
+    /// Add a current source driving gain times the current through the
+    /// element ctrl_element (which must be in group 2) out of term_pos,
+    /// through the source, into term_neg
+    pub fn add_current_controlled_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_element: &str,
+	group2: bool,
+	gain: f64,
+    ) -> Result<(), AcdcError> {
+	let term_pos = self.node_map.node_index(term_pos)?;
+	let term_neg = self.node_map.node_index(term_neg)?;
+	let ctrl_edge = self.node_map.edge_index(ctrl_element);
+	let current_edge = self.optional_edge(name, group2);
+	self.add_device(Box::new(Cccs::new(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_edge,
+	    current_edge,
+	    gain,
+	)))
+    }
+}
@@ -1,49 +1,690 @@
 //! DC analysis
+//!
+//! The operating point of a linear circuit is found by stamping its
+//! devices once ([LinearDcAnalysis]), and that of a circuit with
+//! nonlinear devices by Newton-Raphson iteration
+//! ([NonlinearDcAnalysis]). A [DcSweep] finds the operating point at
+//! each value of a source, the temperature or a parameter, optionally
+//! nested inside a sweep of a second variable.
+//!
+//! An operating point is returned as a [DcSolution], giving the
+//! voltages and currents by name. The small-signal gain, input and
+//! output resistance from a source to an output (as in SPICE `.tf`)
+//! are found by [LinearDcAnalysis::transfer_function].
 
-use crate::{mna::Mna, node_map::NodeMap};
-use csuperlu::c::value_type::ValueType;
-use num;
+use crate::{
+    ac::{sweep_frequencies, SweepType},
+    circuit::Circuit,
+    device::Unknowns,
+    error::AcdcError,
+    mna::{Mna, Probe},
+    newton::{self, NewtonOptions},
+    node_map::NodeMap,
+    solution::{find_edge, OutputVariable, Solution},
+    waveform::SourceWaveform,
+};
 
-pub struct LinearDcAnalysis<P: ValueType + num::Float> {
-    node_map: NodeMap,
-    mna: Mna<P>,
+/// Node voltages and edge currents at a DC operating point
+pub type DcSolution<P = f64> = Solution<P>;
+
+/// Small-signal DC transfer function from an independent source to an
+/// output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferFunction<P> {
+    /// Change in the output per unit change in the source
+    pub gain: P,
+    /// Resistance seen by the source, looking into the circuit
+    pub input_resistance: P,
+    /// Resistance seen looking back into the output: between the
+    /// output nodes, or in series with the output edge
+    pub output_resistance: P,
 }
 
-impl<P: ValueType + num::Float> LinearDcAnalysis<P> {
+/// DC analysis of a linear circuit. Its devices are stamped each time
+/// the analysis is solved, so it can be solved repeatedly, changed,
+/// and solved again.
+///
+/// Elements are named, as in a [Circuit]: an element whose current is
+/// in group 2 has it under the element name, and an element is found
+/// by name to change its value.
+pub struct LinearDcAnalysis {
+    circuit: Circuit,
+}
+
+impl LinearDcAnalysis {
     pub fn new() -> Self {
 	Self {
-	    node_map: NodeMap::new(),
-	    mna: Mna::new(),
+	    circuit: Circuit::new(),
 	}
     }
 
     pub fn add_resistor(
 	&mut self,
+	name: &str,
 	term_1: &str,
 	term_2: &str,
-	current_edge: Option<usize>,
-	resistor: P,
-    ) {
-	let term_1 = self.node_map.node_index(term_1);
-	let term_2 = self.node_map.node_index(term_2);
-	self.mna.add_impedance(term_1, term_2, current_edge, resistor);
+	group2: bool,
+	resistance: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_resistor(name, term_1, term_2, group2, resistance)
     }
 
     pub fn add_independent_voltage_source(
 	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	voltage: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_voltage_source(name, term_pos, term_neg, voltage)
+    }
+
+    /// Add a current source driving current out of term_pos, through
+    /// the source, into term_neg. Set group2 to report the source
+    /// current in the solution.
+    pub fn add_independent_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	group2: bool,
+	current: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_current_source(name, term_pos, term_neg, group2, current)
+    }
+
+    /// Add a voltage source at the DC value of its waveform
+    pub fn add_independent_voltage_source_waveform(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	waveform: &SourceWaveform,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_voltage_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    waveform.clone(),
+	)
+    }
+
+    /// Add a current source at the DC value of its waveform
+    pub fn add_independent_current_source_waveform(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	group2: bool,
+	waveform: &SourceWaveform,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_independent_current_source_waveform(
+	    name,
+	    term_pos,
+	    term_neg,
+	    group2,
+	    waveform.clone(),
+	)
+    }
+
+    /// Add a voltage source whose voltage is gain * (V(ctrl_pos) - V(ctrl_neg))
+    pub fn add_voltage_controlled_voltage_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_pos: &str,
+	ctrl_neg: &str,
+	gain: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_voltage_controlled_voltage_source(
+	    name, term_pos, term_neg, ctrl_pos, ctrl_neg, gain,
+	)
+    }
+
+    /// Add a voltage source whose voltage is transresistance times the
+    /// current through the element ctrl_element, which must be in
+    /// group 2 (for example, a voltage source)
+    pub fn add_current_controlled_voltage_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_element: &str,
+	transresistance: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_current_controlled_voltage_source(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_element,
+	    transresistance,
+	)
+    }
+
+    /// Add a current source driving transconductance * (V(ctrl_pos) - V(ctrl_neg))
+    /// out of term_pos, through the source, into term_neg
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_voltage_controlled_current_source(
+	&mut self,
+	name: &str,
 	term_pos: &str,
 	term_neg: &str,
-	current_edge: &str,
-	voltage: P,
-    ) {
-	let term_pos = self.node_map.node_index(term_pos);
-	let term_neg = self.node_map.node_index(term_neg);
-	let current_edge = self.node_map.edge_index(current_edge);
-	self.mna.add_independent_voltage_source(term_pos, term_neg, current_edge, voltage);
+	ctrl_pos: &str,
+	ctrl_neg: &str,
+	group2: bool,
+	transconductance: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_voltage_controlled_current_source(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_pos,
+	    ctrl_neg,
+	    group2,
+	    transconductance,
+	)
+    }
+
+    /// Add a current source driving gain times the current through the
+    /// element ctrl_element (which must be in group 2) out of term_pos,
+    /// through the source, into term_neg
+    pub fn add_current_controlled_current_source(
+	&mut self,
+	name: &str,
+	term_pos: &str,
+	term_neg: &str,
+	ctrl_element: &str,
+	group2: bool,
+	gain: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_current_controlled_current_source(
+	    name,
+	    term_pos,
+	    term_neg,
+	    ctrl_element,
+	    group2,
+	    gain,
+	)
+    }
+
+    /// Add an ideal transformer with turns ratio (primary turns) /
+    /// (secondary turns). The primary current is in group 2.
+    pub fn add_ideal_transformer(
+	&mut self,
+	name: &str,
+	pri_pos: &str,
+	pri_neg: &str,
+	sec_pos: &str,
+	sec_neg: &str,
+	ratio: f64,
+    ) -> Result<(), AcdcError> {
+	self.circuit
+	    .add_ideal_transformer(name, pri_pos, pri_neg, sec_pos, sec_neg, ratio)
+    }
+
+    /// Add an ideal op-amp, whose output (relative to ground) holds
+    /// in_pos and in_neg at the same voltage. The output current is in
+    /// group 2.
+    pub fn add_ideal_op_amp(
+	&mut self,
+	name: &str,
+	in_pos: &str,
+	in_neg: &str,
+	out: &str,
+    ) -> Result<(), AcdcError> {
+	self.circuit.add_ideal_op_amp(name, in_pos, in_neg, out)
+    }
+
+    /// Map between node/edge names and the indices used in the solution
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
+    /// Change the value of the named element: the resistance, the
+    /// source value, the gain or the turns ratio
+    pub fn set_value(&mut self, name: &str, value: f64) -> Result<(), AcdcError> {
+	self.circuit
+	    .set_value(name, value, |waveform| waveform.set_dc(value))
+    }
+
+    /// Stamp every device. Linear devices do not depend on the
+    /// solution they are stamped about.
+    fn stamp(&self) -> Result<Mna<f64>, AcdcError> {
+	let mut mna = Mna::new();
+	let x = Unknowns::new(&[], &[]);
+	for device in self.circuit.devices().iter() {
+	    device.stamp_dc(&mut mna, &x)?;
+	}
+	Ok(mna)
+    }
+
+    pub fn solve(&self) -> Result<DcSolution, AcdcError> {
+	let mna = self.stamp()?;
+	Ok(Solution::new(self.node_map().clone(), mna.solve()?))
+    }
+
+    /// Gain from the independent source named input to the output,
+    /// with the resistance seen by the source and at the output. One
+    /// adjoint solve for the output and one for the input, sharing a
+    /// factorization, give all three.
+    pub fn transfer_function(
+	&self,
+	output: &OutputVariable,
+	input: &str,
+    ) -> Result<TransferFunction<f64>, AcdcError> {
+	let output_probe = output.probe(self.node_map())?;
+	let source = self
+	    .circuit
+	    .device(input)
+	    .ok_or_else(|| AcdcError::InvalidAnalysis(format!("no element named {}", input)))?;
+	// The source current for a voltage source, or the voltage across
+	// a current source in the direction it drives current
+	let input_probe = source.source_probe().ok_or_else(|| {
+	    AcdcError::InvalidAnalysis(format!("{} is not an independent source", input))
+	})?;
+
+	let solutions = self
+	    .stamp()?
+	    .solve_adjoint_probes(&[output_probe, input_probe])?;
+	let (y, z) = (&solutions[0], &solutions[1]);
+
+	// Value at node n (excluding ground) or edge e of an adjoint
+	// solution
+	let at = |(nodes, _): &(Vec<f64>, Vec<f64>), n: usize| match n {
+	    0 => 0.0,
+	    n => nodes.get(n - 1).copied().unwrap_or(0.0),
+	};
+	let at_edge = |(_, edges): &(Vec<f64>, Vec<f64>), e: usize| {
+	    edges.get(e).copied().unwrap_or(0.0)
+	};
+	// Change in the adjoint output per unit change in the source,
+	// from the change in the residual that the source value makes
+	// (see the sens module)
+	let mut derivative = Mna::new();
+	source.stamp_dc_derivative("dc", &mut derivative, &Unknowns::new(&[], &[]))?;
+	let (nodes, edges) = derivative.residual((&[], &[]))?;
+	let response = |(y_nodes, y_edges): &(Vec<f64>, Vec<f64>)| {
+	    -y_nodes.iter().zip(nodes.iter()).map(|(y, r)| y * r).sum::<f64>()
+		- y_edges.iter().zip(edges.iter()).map(|(y, r)| y * r).sum::<f64>()
+	};
+	// A voltage source current flows into term_pos through the
+	// source, so it delivers minus that current to the circuit
+	let input_resistance = match input_probe {
+	    Probe::Current(_) => -1.0 / response(z),
+	    Probe::Voltage(..) => response(z),
+	};
+	// A unit current injected between the output nodes, or a unit
+	// voltage in series with the output edge
+	let output_resistance = match output_probe {
+	    Probe::Voltage(pos, neg) => at(y, pos) - at(y, neg),
+	    Probe::Current(e) => -1.0 / at_edge(y, e),
+	};
+	Ok(TransferFunction {
+	    gain: response(y),
+	    input_resistance,
+	    output_resistance,
+	})
+    }
+}
+
+/// DC operating point of a circuit that may contain nonlinear
+/// devices, found by Newton-Raphson iteration
+pub struct NonlinearDcAnalysis {
+    circuit: Circuit,
+    options: NewtonOptions,
+}
+
+impl NonlinearDcAnalysis {
+    pub fn new(circuit: Circuit) -> Self {
+	Self {
+	    circuit,
+	    options: NewtonOptions::default(),
+	}
+    }
+
+    pub fn circuit(&self) -> &Circuit {
+	&self.circuit
     }
 
-    pub fn solve(self) -> (Vec<P>, Vec<P>) {
-	self.mna.solve()
+    /// Map between node/edge names and the indices used in the solution
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
+    pub fn options(&self) -> &NewtonOptions {
+	&self.options
+    }
+
+    pub fn set_options(&mut self, options: NewtonOptions) {
+	self.options = options;
+    }
+
+    /// Iteration starts with every node voltage and edge current at
+    /// zero
+    pub fn solve(&mut self) -> Result<DcSolution, AcdcError> {
+	let solution = newton::solve_circuit(
+	    &mut self.circuit,
+	    (Vec::new(), Vec::new()),
+	    &self.options,
+	    |circuit, mna, x| {
+		for device in circuit.devices() {
+		    device.stamp_dc(mna, x)?;
+		}
+		Ok(())
+	    },
+	)?;
+	Ok(Solution::new(self.circuit.node_map().clone(), solution))
+    }
+}
+
+/// Values taken by the variable of a DC sweep
+#[derive(Debug, Clone, PartialEq)]
+pub enum SweepRange {
+    /// From start to stop inclusive, in increments of step (which is
+    /// negative if stop is below start)
+    Linear { start: f64, stop: f64, step: f64 },
+    /// From start to stop inclusive, where num_points is the total
+    /// number of points (for a linear sweep) or the number of points
+    /// per decade or octave
+    Points {
+	sweep: SweepType,
+	num_points: usize,
+	start: f64,
+	stop: f64,
+    },
+    /// The values given, in order
+    List(Vec<f64>),
+}
+
+impl SweepRange {
+    pub fn values(&self) -> Result<Vec<f64>, AcdcError> {
+	match self {
+	    SweepRange::Linear { start, stop, step } => {
+		let span = stop - start;
+		if !(span / step >= 0.0) || *step == 0.0 {
+		    return Err(AcdcError::InvalidAnalysis(format!(
+			"cannot sweep from {} to {} in steps of {}",
+			start, stop, step
+		    )));
+		}
+		// Allow for rounding in the number of steps
+		let num_steps = (span / step + 1e-9).floor() as usize;
+		Ok((0..=num_steps).map(|n| start + n as f64 * step).collect())
+	    }
+	    SweepRange::Points {
+		sweep: SweepType::Linear,
+		num_points,
+		start,
+		stop,
+	    } => match num_points {
+		0 => Err(AcdcError::InvalidAnalysis(String::from(
+		    "sweep must have at least one point",
+		))),
+		1 => Ok(vec![*start]),
+		n => {
+		    let step = (stop - start) / (n - 1) as f64;
+		    Ok((0..*n).map(|k| start + k as f64 * step).collect())
+		}
+	    },
+	    SweepRange::Points {
+		sweep,
+		num_points,
+		start,
+		stop,
+	    } => {
+		if !(*start > 0.0) || !(stop >= start) {
+		    return Err(AcdcError::InvalidAnalysis(format!(
+			"logarithmic sweep from {} to {} must be positive and increasing",
+			start, stop
+		    )));
+		}
+		sweep_frequencies(*sweep, *num_points, *start, *stop)
+	    }
+	    SweepRange::List(values) => {
+		if values.is_empty() {
+		    return Err(AcdcError::InvalidAnalysis(String::from(
+			"sweep must have at least one point",
+		    )));
+		}
+		Ok(values.clone())
+	    }
+	}
+    }
+}
+
+/// Variable swept by a DC sweep
+pub enum SweepVariable {
+    /// DC value of the named independent source
+    Source(String),
+    /// Temperature of every device, in Celsius (as in SPICE)
+    Temperature,
+    /// A parameter of the circuit description (such as a netlist
+    /// `.param`), set by building the circuit again with each value.
+    /// Every circuit built must have the same nodes and edges.
+    Parameter {
+	name: String,
+	build: Box<dyn Fn(f64) -> Result<Circuit, AcdcError>>,
+    },
+}
+
+impl SweepVariable {
+    pub fn name(&self) -> &str {
+	match self {
+	    SweepVariable::Source(name) => name,
+	    SweepVariable::Temperature => "temp",
+	    SweepVariable::Parameter { name, .. } => name,
+	}
+    }
+}
+
+/// Node voltages and edge currents over one DC sweep of a variable
+#[derive(Debug, Clone)]
+pub struct DcSweepWaveforms {
+    node_map: NodeMap,
+    /// Name of the swept variable
+    variable: String,
+    /// Value of the swept variable at each point
+    values: Vec<f64>,
+    /// Name and value of the outer variable, in a nested sweep
+    outer: Option<(String, f64)>,
+    /// Voltage at each node (including ground) at each point
+    voltages: Vec<Vec<f64>>,
+    /// Current in each edge at each point
+    currents: Vec<Vec<f64>>,
+}
+
+impl DcSweepWaveforms {
+    fn new(node_map: NodeMap, variable: &str, outer: Option<(String, f64)>) -> Self {
+	let voltages = vec![Vec::new(); node_map.num_nodes()];
+	let currents = vec![Vec::new(); node_map.num_edges()];
+	Self {
+	    node_map,
+	    variable: String::from(variable),
+	    values: Vec::new(),
+	    outer,
+	    voltages,
+	    currents,
+	}
+    }
+
+    fn push(&mut self, value: f64, (voltages, currents): &(Vec<f64>, Vec<f64>)) {
+	let x = Unknowns::new(voltages, currents);
+	self.values.push(value);
+	for (n, v) in self.voltages.iter_mut().enumerate() {
+	    v.push(x.v(n));
+	}
+	for (e, i) in self.currents.iter_mut().enumerate() {
+	    i.push(x.i(e));
+	}
+    }
+
+    /// Name of the swept variable
+    pub fn variable(&self) -> &str {
+	&self.variable
+    }
+
+    /// Value of the swept variable at each point
+    pub fn values(&self) -> &[f64] {
+	&self.values
+    }
+
+    /// Name and value of the outer variable, if the sweep is nested
+    pub fn outer(&self) -> Option<(&str, f64)> {
+	self.outer.as_ref().map(|(name, value)| (name.as_str(), *value))
+    }
+
+    /// Voltage at the named node at each point, where ground is at
+    /// zero
+    pub fn v(&self, node: &str) -> Option<&[f64]> {
+	let n = self.node_map.find_node(node)?;
+	Some(&self.voltages[n])
+    }
+
+    /// Voltage between two named nodes, V(pos) - V(neg), at each point
+    pub fn v_diff(&self, pos: &str, neg: &str) -> Option<Vec<f64>> {
+	let (pos, neg) = (self.v(pos)?, self.v(neg)?);
+	Some(pos.iter().zip(neg).map(|(p, n)| p - n).collect())
+    }
+
+    /// Current in the named edge at each point, which for a circuit
+    /// read from a netlist is the name of its element
+    pub fn i(&self, name: &str) -> Option<&[f64]> {
+	let e = find_edge(&self.node_map, name)?;
+	Some(&self.currents[e])
+    }
+
+    pub fn node_map(&self) -> &NodeMap {
+	&self.node_map
+    }
+}
+
+/// DC sweep: the operating point of a circuit at each value of a
+/// variable, optionally for each value of a second, outer variable
+/// (giving a family of curves)
+pub struct DcSweep {
+    circuit: Circuit,
+    options: NewtonOptions,
+    /// Swept variables and their values, innermost first
+    sweeps: Vec<(SweepVariable, Vec<f64>)>,
+}
+
+impl DcSweep {
+    pub fn new(
+	circuit: Circuit,
+	variable: SweepVariable,
+	range: &SweepRange,
+    ) -> Result<Self, AcdcError> {
+	Ok(Self {
+	    circuit,
+	    options: NewtonOptions::default(),
+	    sweeps: vec![(variable, range.values()?)],
+	})
+    }
+
+    /// Sweep a second variable, running the whole sweep of the first
+    /// at each of its values
+    pub fn set_outer_sweep(
+	&mut self,
+	variable: SweepVariable,
+	range: &SweepRange,
+    ) -> Result<(), AcdcError> {
+	let values = range.values()?;
+	self.sweeps.truncate(1);
+	self.sweeps.push((variable, values));
+	Ok(())
+    }
+
+    pub fn circuit(&self) -> &Circuit {
+	&self.circuit
+    }
+
+    /// Map between node/edge names and the indices used in the solution
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
+    pub fn options(&self) -> &NewtonOptions {
+	&self.options
+    }
+
+    pub fn set_options(&mut self, options: NewtonOptions) {
+	self.options = options;
+    }
+
+    /// Set every swept variable to its value at one point of the sweep
+    /// (innermost first), where previous is the last point set
+    fn set_point(&mut self, point: &[f64], previous: Option<&[f64]>) -> Result<(), AcdcError> {
+	let changed = |k: usize| !matches!(previous, Some(previous) if previous[k] == point[k]);
+	// Building the circuit again resets the other variables, so it
+	// comes first
+	let mut rebuilt = false;
+	for (k, (variable, _)) in self.sweeps.iter().enumerate() {
+	    if let SweepVariable::Parameter { build, .. } = variable {
+		if changed(k) {
+		    self.circuit = build(point[k])?;
+		    rebuilt = true;
+		}
+	    }
+	}
+	for (k, (variable, _)) in self.sweeps.iter().enumerate() {
+	    if !rebuilt && !changed(k) {
+		continue;
+	    }
+	    match variable {
+		SweepVariable::Source(name) => {
+		    self.circuit.set_source_value(name, point[k])?;
+		}
+		SweepVariable::Temperature => self.circuit.set_temperature(point[k] + 273.15),
+		SweepVariable::Parameter { .. } => (),
+	    }
+	}
+	Ok(())
+    }
+
+    /// Returns the waveforms of the sweep, or one set for each value
+    /// of the outer variable of a nested sweep. Each operating point
+    /// starts from the last, and the circuit is left at the final
+    /// point.
+    pub fn solve(&mut self) -> Result<Vec<DcSweepWaveforms>, AcdcError> {
+	let inner_values = self.sweeps[0].1.clone();
+	let outer_values: Vec<Option<f64>> = match self.sweeps.get(1) {
+	    Some((_, values)) => values.iter().copied().map(Some).collect(),
+	    None => vec![None],
+	};
+	let mut curves = Vec::new();
+	let mut previous: Option<Vec<f64>> = None;
+	let mut solution = (Vec::new(), Vec::new());
+	for outer_value in outer_values {
+	    let outer = match (self.sweeps.get(1), outer_value) {
+		(Some((variable, _)), Some(value)) => Some((String::from(variable.name()), value)),
+		_ => None,
+	    };
+	    let mut curve: Option<DcSweepWaveforms> = None;
+	    for value in inner_values.iter().copied() {
+		let point: Vec<f64> = std::iter::once(value).chain(outer_value).collect();
+		self.set_point(&point, previous.as_deref())?;
+		solution = newton::solve_circuit(
+		    &mut self.circuit,
+		    solution,
+		    &self.options,
+		    |circuit, mna, x| {
+			for device in circuit.devices() {
+			    device.stamp_dc(mna, x)?;
+			}
+			Ok(())
+		    },
+		)?;
+		let variable = self.sweeps[0].0.name();
+		curve
+		    .get_or_insert_with(|| {
+			DcSweepWaveforms::new(self.circuit.node_map().clone(), variable, outer.clone())
+		    })
+		    .push(value, &solution);
+		previous = Some(point);
+	    }
+	    curves.extend(curve);
+	}
+	Ok(curves)
     }
-    
 }
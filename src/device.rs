@@ -0,0 +1,320 @@
+//! Circuit devices
+//!
+//! A device is anything that can stamp itself into a modified nodal
+//! analysis system. Devices are kept in a
+//! [Circuit](crate::circuit::Circuit) and stamped whenever the system
+//! is rebuilt (for example, each time a linear analysis is solved, or
+//! at every timestep of a transient analysis).
+
+use num::Complex;
+
+use crate::{
+    error::AcdcError,
+    mna::{Mna, Probe},
+    transient::TimeStep,
+    waveform::SourceWaveform,
+};
+
+pub mod bjt;
+pub mod diode;
+pub mod linear;
+pub mod mosfet;
+
+/// Temperature at which model parameters are given (27 C), in kelvin
+pub const NOMINAL_TEMPERATURE: f64 = 300.15;
+
+/// Conductance added across every pn junction to help convergence
+pub const GMIN: f64 = 1e-12;
+
+/// Boltzmann constant (J/K)
+pub const BOLTZMANN: f64 = 1.380649e-23;
+
+/// Elementary charge (C)
+pub const CHARGE: f64 = 1.602176634e-19;
+
+/// Permittivity of free space (F/m)
+pub const EPSILON_0: f64 = 8.854187817e-12;
+
+/// Thermal voltage kT/q at a temperature in kelvin
+pub fn thermal_voltage(temperature: f64) -> f64 {
+    BOLTZMANN * temperature / CHARGE
+}
+
+/// Factor by which a saturation current grows from the nominal
+/// temperature to temperature (in kelvin), for a junction with energy
+/// gap eg (eV), temperature exponent xti and emission coefficient n
+pub fn saturation_current_factor(temperature: f64, eg: f64, xti: f64, n: f64) -> f64 {
+    let ratio = temperature / NOMINAL_TEMPERATURE;
+    let exponent = xti * ratio.ln() + (ratio - 1.0) * eg / thermal_voltage(temperature);
+    (exponent / n).exp()
+}
+
+/// Power spectral density (A^2/Hz) of the thermal noise current of a
+/// resistance at a temperature in kelvin (zero for a short circuit)
+pub fn thermal_noise(resistance: f64, temperature: f64) -> f64 {
+    if resistance == 0.0 {
+	return 0.0;
+    }
+    4.0 * BOLTZMANN * temperature / resistance.abs()
+}
+
+/// Power spectral density (A^2/Hz) of the shot noise of a current
+pub fn shot_noise(current: f64) -> f64 {
+    2.0 * CHARGE * current.abs()
+}
+
+/// Power spectral density (A^2/Hz) of flicker noise kf * |I|^af / f,
+/// at frequency f (in Hz)
+pub fn flicker_noise(kf: f64, af: f64, current: f64, frequency: f64) -> f64 {
+    if kf == 0.0 || frequency == 0.0 {
+	return 0.0;
+    }
+    kf * current.abs().powf(af) / frequency
+}
+
+/// Noise current source of a device, between two nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseSource {
+    /// What the noise comes from, as in SPICE (such as `rb` for the
+    /// thermal noise of a base resistance, `ic` for collector shot
+    /// noise, or `1overf` for flicker noise)
+    pub name: &'static str,
+    pub term_1: usize,
+    pub term_2: usize,
+    /// Power spectral density (A^2/Hz)
+    pub density: f64,
+}
+
+impl NoiseSource {
+    pub fn new(name: &'static str, term_1: usize, term_2: usize, density: f64) -> Self {
+	Self {
+	    name,
+	    term_1,
+	    term_2,
+	    density,
+	}
+    }
+}
+
+/// Add a Norton branch (see [Mna::add_norton]) between two nodes of a
+/// device, which does nothing if they are the same node (such as a
+/// transistor with its base connected to its collector)
+pub(crate) fn add_branch<P>(
+    mna: &mut Mna<P>,
+    t1: usize,
+    t2: usize,
+    y: P,
+    i: P,
+) -> Result<(), AcdcError>
+where
+    P: csuperlu::c::value_type::ValueType + std::ops::Neg<Output = P>,
+{
+    if t1 == t2 {
+	return Ok(());
+    }
+    mna.add_norton(t1, t2, None, y, i)
+}
+
+/// Polarity of a transistor: NPN or n-channel, or PNP or p-channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    N,
+    P,
+}
+
+impl Polarity {
+    /// Sign applied to terminal voltages and currents, so that a
+    /// p-type device can use the n-type equations
+    pub fn sign(self) -> f64 {
+	match self {
+	    Polarity::N => 1.0,
+	    Polarity::P => -1.0,
+	}
+    }
+}
+
+/// Node voltages and edge currents of a solution, indexed in the
+/// same way as the [NodeMap](crate::node_map::NodeMap)
+#[derive(Debug, Clone, Copy)]
+pub struct Unknowns<'a> {
+    /// Voltages of the non-ground nodes (node n is at position n-1)
+    voltages: &'a [f64],
+    currents: &'a [f64],
+}
+
+impl<'a> Unknowns<'a> {
+    pub fn new(voltages: &'a [f64], currents: &'a [f64]) -> Self {
+	Self {
+	    voltages,
+	    currents,
+	}
+    }
+
+    /// Voltage at node n. Ground, and any node that is not yet part of
+    /// the solution, is at 0 V.
+    pub fn v(&self, n: usize) -> f64 {
+	if n == 0 {
+	    0.0
+	} else {
+	    self.voltages.get(n - 1).copied().unwrap_or(0.0)
+	}
+    }
+
+    /// Current in edge e, or zero if the edge is not yet part of the
+    /// solution
+    pub fn i(&self, e: usize) -> f64 {
+	self.currents.get(e).copied().unwrap_or(0.0)
+    }
+}
+
+pub trait Device {
+    /// Name of the device instance (e.g. `c1`)
+    fn name(&self) -> &str;
+
+    /// Whether the device must be linearised about the solution, so
+    /// that the system needs Newton-Raphson iteration
+    fn is_nonlinear(&self) -> bool {
+	false
+    }
+
+    /// Called with each new Newton-Raphson iterate x. A nonlinear
+    /// device records the voltages it will be linearised about in its
+    /// next stamp, limiting their change from the last iterate if
+    /// required, and returns true if it limited them.
+    fn limit(&mut self, x: &Unknowns) -> bool {
+	let _ = x;
+	false
+    }
+
+    /// Stamp the DC model of the device, where capacitors are open
+    /// circuits and inductors are short circuits. A nonlinear device
+    /// stamps its linearisation.
+    fn stamp_dc(&self, mna: &mut Mna<f64>, x: &Unknowns) -> Result<(), AcdcError>;
+
+    /// Stamp the small-signal model of the device at radial frequency
+    /// omega, linearised about the last solution the device was given
+    /// (normally the DC operating point). Independent sources stamp
+    /// their AC value.
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, omega: f64) -> Result<(), AcdcError>;
+
+    /// Stamp the DC model of the device for the operating point at the
+    /// start of a transient analysis. Independent sources take their
+    /// transient value at time zero, so that the first step does not
+    /// jump from their DC value.
+    fn stamp_initial(&self, mna: &mut Mna<f64>, x: &Unknowns) -> Result<(), AcdcError> {
+	self.stamp_dc(mna, x)
+    }
+
+    /// Stamp the companion model of the device for the timestep
+    /// ending at step.time. Devices without energy storage use their
+    /// DC model.
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	let _ = step;
+	self.stamp_dc(mna, x)
+    }
+
+    /// Set the internal state of the device from the initial solution
+    /// of a transient analysis
+    fn initialise_transient(&mut self, x: &Unknowns) {
+	let _ = x;
+    }
+
+    /// Times before stop at which the device changes abruptly (such as
+    /// the corners of a pulse), where a transient step must finish
+    fn breakpoints(&self, stop: f64) -> Vec<f64> {
+	let _ = stop;
+	Vec::new()
+    }
+
+    /// Update the internal state of the device once the solution x at
+    /// the end of the timestep has been accepted
+    fn accept_transient(&mut self, x: &Unknowns, step: &TimeStep) {
+	let _ = (x, step);
+    }
+
+    /// Set the temperature of the device (in kelvin). Devices are
+    /// created at the nominal temperature, and those whose behaviour
+    /// does not depend on temperature ignore it.
+    fn set_temperature(&mut self, temperature: f64) {
+	let _ = temperature;
+    }
+
+    /// The waveform of an independent source, so that an analysis can
+    /// change its value, or None for any other device
+    fn waveform_mut(&mut self) -> Option<&mut SourceWaveform> {
+	None
+    }
+
+    /// For an independent source, the quantity its value does not fix:
+    /// the current through a voltage source, or the voltage across a
+    /// current source in the direction it drives current. None for any
+    /// other device.
+    fn source_probe(&self) -> Option<Probe> {
+	None
+    }
+
+    /// For an inductor, its current edge and inductance, so that a
+    /// mutual inductance can couple it. None for any other device.
+    fn inductance(&self) -> Option<(usize, f64)> {
+	None
+    }
+
+    /// Values of the device that a sensitivity analysis can vary, by
+    /// name: the element value of a linear device, or those instance
+    /// and model parameters of a semiconductor device whose derivative
+    /// it can stamp. Devices without such values have none.
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	Vec::new()
+    }
+
+    /// Stamp the derivative of the DC model of the device with respect
+    /// to one of the values named by [params](Self::params), so that
+    /// the residual of the system at x is the derivative of the
+    /// residual of the device there. A nonlinear device holds the
+    /// point it is linearised about.
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	let _ = (param, mna, x);
+	Ok(())
+    }
+
+    /// Stamp the derivative of the small-signal model of the device at
+    /// radial frequency omega with respect to one of the values named
+    /// by [params](Self::params), as in
+    /// [stamp_dc_derivative](Self::stamp_dc_derivative)
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	let _ = (param, mna, omega);
+	Ok(())
+    }
+
+    /// Set one of the values named by [params](Self::params), keeping
+    /// the temperature and the point the device is linearised about,
+    /// and return false if the device has no such value
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	let _ = (name, value);
+	false
+    }
+
+    /// Noise current sources of the device at frequency f (in Hz) and
+    /// temperature (in kelvin), linearised about the last solution the
+    /// device was given (normally the DC operating point). Noiseless
+    /// devices have none.
+    fn noise_sources(&self, frequency: f64, temperature: f64) -> Vec<NoiseSource> {
+	let _ = (frequency, temperature);
+	Vec::new()
+    }
+}
@@ -0,0 +1,638 @@
+//! Bipolar junction transistor
+//!
+//! SPICE Gummel-Poon transistor. The collector current is the
+//! transport current between two exponential junctions, divided by
+//! the normalised base charge, which models the Early effect (vaf,
+//! var) and high-level injection (ikf, ikr). Each junction also has
+//! a non-ideal leakage current, a depletion capacitance and a
+//! diffusion (transit time) capacitance. The terminal resistances
+//! are placed between the terminals and internal nodes.
+//!
+//! The equations are written for an NPN transistor; a PNP transistor
+//! uses them with its junction voltages and currents negated.
+
+use num::Complex;
+
+use crate::{
+    error::AcdcError,
+    mna::Mna,
+    newton::{pnjlim, vcrit},
+    transient::{StateHistory, TimeStep},
+};
+
+use super::{
+    add_branch,
+    diode::{depletion_charge, junction_current},
+    flicker_noise, saturation_current_factor, shot_noise, thermal_noise, thermal_voltage, Device,
+    NoiseSource, Polarity, Unknowns, NOMINAL_TEMPERATURE,
+};
+
+/// Transistor model parameters, as given on a `.model name NPN` or
+/// `.model name PNP` card
+#[derive(Debug, Clone, PartialEq)]
+pub struct BjtModel {
+    pub polarity: Polarity,
+    /// Transport saturation current (A)
+    pub is: f64,
+    /// Ideal maximum forward beta
+    pub bf: f64,
+    /// Ideal maximum reverse beta
+    pub br: f64,
+    /// Forward current emission coefficient
+    pub nf: f64,
+    /// Reverse current emission coefficient
+    pub nr: f64,
+    /// Base-emitter leakage saturation current (A)
+    pub ise: f64,
+    /// Base-emitter leakage emission coefficient
+    pub ne: f64,
+    /// Base-collector leakage saturation current (A)
+    pub isc: f64,
+    /// Base-collector leakage emission coefficient
+    pub nc: f64,
+    /// Forward Early voltage (V), infinite if there is no Early effect
+    pub vaf: f64,
+    /// Reverse Early voltage (V)
+    pub var: f64,
+    /// Corner for forward beta high current roll-off (A)
+    pub ikf: f64,
+    /// Corner for reverse beta high current roll-off (A)
+    pub ikr: f64,
+    /// Base resistance
+    pub rb: f64,
+    /// Collector resistance
+    pub rc: f64,
+    /// Emitter resistance
+    pub re: f64,
+    /// Zero-bias base-emitter depletion capacitance (F)
+    pub cje: f64,
+    /// Base-emitter built-in potential (V)
+    pub vje: f64,
+    /// Base-emitter junction grading coefficient
+    pub mje: f64,
+    /// Zero-bias base-collector depletion capacitance (F)
+    pub cjc: f64,
+    /// Base-collector built-in potential (V)
+    pub vjc: f64,
+    /// Base-collector junction grading coefficient
+    pub mjc: f64,
+    /// Coefficient for the forward-bias depletion capacitance
+    pub fc: f64,
+    /// Ideal forward transit time (s)
+    pub tf: f64,
+    /// Ideal reverse transit time (s)
+    pub tr: f64,
+    /// Energy gap (eV), for the temperature dependence of is
+    pub eg: f64,
+    /// Saturation current temperature exponent
+    pub xti: f64,
+    /// Flicker noise coefficient
+    pub kf: f64,
+    /// Flicker noise exponent
+    pub af: f64,
+}
+
+impl BjtModel {
+    /// Model with the SPICE default parameters
+    pub fn new(polarity: Polarity) -> Self {
+	Self {
+	    polarity,
+	    is: 1e-16,
+	    bf: 100.0,
+	    br: 1.0,
+	    nf: 1.0,
+	    nr: 1.0,
+	    ise: 0.0,
+	    ne: 1.5,
+	    isc: 0.0,
+	    nc: 2.0,
+	    vaf: f64::INFINITY,
+	    var: f64::INFINITY,
+	    ikf: f64::INFINITY,
+	    ikr: f64::INFINITY,
+	    rb: 0.0,
+	    rc: 0.0,
+	    re: 0.0,
+	    cje: 0.0,
+	    vje: 0.75,
+	    mje: 0.33,
+	    cjc: 0.0,
+	    vjc: 0.75,
+	    mjc: 0.33,
+	    fc: 0.5,
+	    tf: 0.0,
+	    tr: 0.0,
+	    eg: 1.11,
+	    xti: 3.0,
+	    kf: 0.0,
+	    af: 1.0,
+	}
+    }
+
+    /// Set a parameter by its SPICE name (or one of its aliases),
+    /// returning false if the name is not a transistor parameter
+    pub fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "is" => self.is = value,
+	    "bf" => self.bf = value,
+	    "br" => self.br = value,
+	    "nf" => self.nf = value,
+	    "nr" => self.nr = value,
+	    "ise" => self.ise = value,
+	    "ne" => self.ne = value,
+	    "isc" => self.isc = value,
+	    "nc" => self.nc = value,
+	    "vaf" | "va" => self.vaf = value,
+	    "var" | "vb" => self.var = value,
+	    "ikf" | "ik" => self.ikf = value,
+	    "ikr" => self.ikr = value,
+	    "rb" => self.rb = value,
+	    "rc" => self.rc = value,
+	    "re" => self.re = value,
+	    "cje" => self.cje = value,
+	    "vje" | "pe" => self.vje = value,
+	    "mje" | "me" => self.mje = value,
+	    "cjc" => self.cjc = value,
+	    "vjc" | "pc" => self.vjc = value,
+	    "mjc" | "mc" => self.mjc = value,
+	    "fc" => self.fc = value,
+	    "tf" => self.tf = value,
+	    "tr" => self.tr = value,
+	    "eg" => self.eg = value,
+	    "xti" => self.xti = value,
+	    "kf" => self.kf = value,
+	    "af" => self.af = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    /// Every parameter that [set_param](Self::set_param) accepts, by
+    /// its SPICE name, with its value
+    pub fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![
+	    ("is", self.is),
+	    ("bf", self.bf),
+	    ("br", self.br),
+	    ("nf", self.nf),
+	    ("nr", self.nr),
+	    ("ise", self.ise),
+	    ("ne", self.ne),
+	    ("isc", self.isc),
+	    ("nc", self.nc),
+	    ("vaf", self.vaf),
+	    ("var", self.var),
+	    ("ikf", self.ikf),
+	    ("ikr", self.ikr),
+	    ("rb", self.rb),
+	    ("rc", self.rc),
+	    ("re", self.re),
+	    ("cje", self.cje),
+	    ("vje", self.vje),
+	    ("mje", self.mje),
+	    ("cjc", self.cjc),
+	    ("vjc", self.vjc),
+	    ("mjc", self.mjc),
+	    ("fc", self.fc),
+	    ("tf", self.tf),
+	    ("tr", self.tr),
+	    ("eg", self.eg),
+	    ("xti", self.xti),
+	    ("kf", self.kf),
+	    ("af", self.af),
+	]
+    }
+}
+
+/// Currents, conductances, charges and capacitances of the hybrid-pi
+/// model about a pair of junction voltages (for an NPN transistor),
+/// or their derivatives
+#[derive(Default)]
+struct OperatingPoint {
+    /// Base current through the base-emitter junction
+    ib_e: f64,
+    /// Base current through the base-collector junction
+    ib_c: f64,
+    /// Transport current from collector to emitter
+    it: f64,
+    gpi: f64,
+    gmu: f64,
+    gm: f64,
+    go: f64,
+    qbe: f64,
+    cpi: f64,
+    qbc: f64,
+    cmu: f64,
+}
+
+/// Current and conductance of a leakage junction with saturation
+/// current is (which may be zero)
+fn leakage_current(is: f64, vte: f64, v: f64) -> (f64, f64) {
+    if is == 0.0 {
+	return (0.0, 0.0);
+    }
+    let e = (v / vte).exp();
+    (is * (e - 1.0), is * e / vte)
+}
+
+pub struct Bjt {
+    name: String,
+    /// Collector, base and emitter terminals
+    terminals: [usize; 3],
+    /// Collector, base and emitter sides of the junctions (the same as
+    /// the terminals where there is no terminal resistance)
+    internal: [usize; 3],
+    model: BjtModel,
+    area: f64,
+    /// Saturation currents (at the device temperature), roll-off
+    /// corners and capacitances scaled by area
+    is: f64,
+    ise: f64,
+    isc: f64,
+    ikf: f64,
+    ikr: f64,
+    cje: f64,
+    cjc: f64,
+    /// Collector, base and emitter resistances scaled by area
+    resistances: [f64; 3],
+    /// Thermal voltage
+    vt: f64,
+    /// Device temperature (K)
+    temperature: f64,
+    /// Junction voltages that the device is linearised about (for an
+    /// NPN transistor)
+    vbe: f64,
+    vbc: f64,
+    /// Junction charge histories
+    state_be: StateHistory,
+    state_bc: StateHistory,
+}
+
+impl Bjt {
+    /// New transistor. The internal nodes are only used where the
+    /// model has a terminal resistance, and should otherwise be the
+    /// terminals.
+    pub fn new(
+	name: &str,
+	terminals: [usize; 3],
+	internal: [usize; 3],
+	model: BjtModel,
+	area: f64,
+    ) -> Self {
+	let mut bjt = Self {
+	    name: String::from(name),
+	    terminals,
+	    internal,
+	    is: 0.0,
+	    ise: 0.0,
+	    isc: 0.0,
+	    ikf: model.ikf * area,
+	    ikr: model.ikr * area,
+	    cje: model.cje * area,
+	    cjc: model.cjc * area,
+	    resistances: [model.rc / area, model.rb / area, model.re / area],
+	    model,
+	    area,
+	    vt: 0.0,
+	    temperature: NOMINAL_TEMPERATURE,
+	    vbe: 0.0,
+	    vbc: 0.0,
+	    state_be: StateHistory::default(),
+	    state_bc: StateHistory::default(),
+	};
+	bjt.set_temperature(NOMINAL_TEMPERATURE);
+	bjt
+    }
+
+    /// Base-emitter and base-collector voltages in the solution x
+    /// (negated for a PNP transistor)
+    fn junction_voltages(&self, x: &Unknowns) -> (f64, f64) {
+	let [c, b, e] = self.internal;
+	let sign = self.model.polarity.sign();
+	(sign * (x.v(b) - x.v(e)), sign * (x.v(b) - x.v(c)))
+    }
+
+    fn operating_point(&self, vbe: f64, vbc: f64) -> OperatingPoint {
+	let m = &self.model;
+	let (cbe, gbe) = junction_current(self.is, m.nf * self.vt, f64::INFINITY, vbe);
+	let (cbc, gbc) = junction_current(self.is, m.nr * self.vt, f64::INFINITY, vbc);
+	let (cben, gben) = leakage_current(self.ise, m.ne * self.vt, vbe);
+	let (cbcn, gbcn) = leakage_current(self.isc, m.nc * self.vt, vbc);
+
+	// Normalised base charge
+	let q1 = 1.0 / (1.0 - vbc / m.vaf - vbe / m.var);
+	let arg = (1.0 + 4.0 * (cbe / self.ikf + cbc / self.ikr)).max(0.0);
+	let sqarg = if arg == 0.0 { 1.0 } else { arg.sqrt() };
+	let qb = q1 * (1.0 + sqarg) / 2.0;
+	let dqb_dvbe = q1 * (qb / m.var + gbe / (self.ikf * sqarg));
+	let dqb_dvbc = q1 * (qb / m.vaf + gbc / (self.ikr * sqarg));
+
+	let it = (cbe - cbc) / qb;
+	let go = (gbc + (cbe - cbc) * dqb_dvbc / qb) / qb;
+	let gm = (gbe - (cbe - cbc) * dqb_dvbe / qb) / qb - go;
+
+	let (qje, cje) = depletion_charge(self.cje, m.vje, m.mje, m.fc, vbe);
+	let (qjc, cjc) = depletion_charge(self.cjc, m.vjc, m.mjc, m.fc, vbc);
+	OperatingPoint {
+	    ib_e: cbe / m.bf + cben,
+	    ib_c: cbc / m.br + cbcn,
+	    it,
+	    gpi: gbe / m.bf + gben,
+	    gmu: gbc / m.br + gbcn,
+	    gm,
+	    go,
+	    qbe: m.tf * cbe + qje,
+	    cpi: m.tf * gbe + cje,
+	    qbc: m.tr * cbc + qjc,
+	    cmu: m.tr * gbc + cjc,
+	}
+    }
+
+    /// Derivatives of the hybrid-pi model about vbe and vbc, and of the
+    /// conductances of the terminal resistances, with respect to one
+    /// of the values named by [params](Device::params)
+    fn slopes(&self, param: &str) -> (OperatingPoint, [f64; 3]) {
+	let m = &self.model;
+	let (cbe, gbe) = junction_current(self.is, m.nf * self.vt, f64::INFINITY, self.vbe);
+	let (cbc, gbc) = junction_current(self.is, m.nr * self.vt, f64::INFINITY, self.vbc);
+	let mut slopes = OperatingPoint::default();
+	// Derivatives of the collector, base and emitter resistances
+	let mut resistances = [0.0; 3];
+	match param {
+	    "bf" => {
+		slopes.ib_e = -cbe / (m.bf * m.bf);
+		slopes.gpi = -gbe / (m.bf * m.bf);
+	    }
+	    "br" => {
+		slopes.ib_c = -cbc / (m.br * m.br);
+		slopes.gmu = -gbc / (m.br * m.br);
+	    }
+	    "rc" => resistances[0] = 1.0 / self.area,
+	    "rb" => resistances[1] = 1.0 / self.area,
+	    "re" => resistances[2] = 1.0 / self.area,
+	    "cje" => {
+		let (_, c) = depletion_charge(1.0, m.vje, m.mje, m.fc, self.vbe);
+		slopes.cpi = self.area * c;
+	    }
+	    "cjc" => {
+		let (_, c) = depletion_charge(1.0, m.vjc, m.mjc, m.fc, self.vbc);
+		slopes.cmu = self.area * c;
+	    }
+	    "tf" => slopes.cpi = gbe,
+	    "tr" => slopes.cmu = gbc,
+	    _ => (),
+	}
+	let conductances = [0, 1, 2].map(|k| {
+	    let r = self.resistances[k];
+	    if resistances[k] == 0.0 {
+		0.0
+	    } else {
+		-resistances[k] / (r * r)
+	    }
+	});
+	(slopes, conductances)
+    }
+
+    /// Stamp the linearisation of the transistor about vbe and vbc,
+    /// given its operating point there and the conductances of its
+    /// terminal resistances (or the derivatives of both)
+    fn stamp_linearised(
+	&self,
+	mna: &mut Mna<f64>,
+	op: &OperatingPoint,
+	conductances: [f64; 3],
+    ) -> Result<(), AcdcError> {
+	let sign = self.model.polarity.sign();
+	let vce = self.vbe - self.vbc;
+	self.stamp_hybrid_pi(
+	    mna,
+	    conductances,
+	    [op.gpi, op.gmu, op.go, op.gm],
+	    [
+		sign * (op.ib_e - op.gpi * self.vbe),
+		sign * (op.ib_c - op.gmu * self.vbc),
+		sign * (op.it - op.gm * self.vbe - op.go * vce),
+	    ],
+	)
+    }
+
+    /// Stamp the small-signal model of the transistor at radial
+    /// frequency omega, as in [stamp_linearised](Self::stamp_linearised)
+    fn stamp_small_signal(
+	&self,
+	mna: &mut Mna<Complex<f64>>,
+	op: &OperatingPoint,
+	conductances: [f64; 3],
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	let zero = Complex::new(0.0, 0.0);
+	self.stamp_hybrid_pi(
+	    mna,
+	    conductances.map(|g| Complex::new(g, 0.0)),
+	    [
+		Complex::new(op.gpi, omega * op.cpi),
+		Complex::new(op.gmu, omega * op.cmu),
+		Complex::new(op.go, 0.0),
+		Complex::new(op.gm, 0.0),
+	    ],
+	    [zero, zero, zero],
+	)
+    }
+
+    /// Stamp the terminal resistances, with the given conductances,
+    /// and the hybrid-pi model, with the given admittances across each
+    /// junction and between collector and emitter, and the current
+    /// through each of them when every voltage is zero
+    fn stamp_hybrid_pi<P>(
+	&self,
+	mna: &mut Mna<P>,
+	conductances: [P; 3],
+	[y_pi, y_mu, go, gm]: [P; 4],
+	[i_be, i_bc, i_ce]: [P; 3],
+    ) -> Result<(), AcdcError>
+    where
+	P: csuperlu::c::value_type::ValueType + std::ops::Neg<Output = P>,
+    {
+	for ((terminal, internal), g) in self
+	    .terminals
+	    .iter()
+	    .zip(self.internal.iter())
+	    .zip(conductances)
+	{
+	    add_branch(mna, *terminal, *internal, g, P::zero())?;
+	}
+
+	let [c, b, e] = self.internal;
+	add_branch(mna, b, e, y_pi, i_be)?;
+	add_branch(mna, b, c, y_mu, i_bc)?;
+	add_branch(mna, c, e, go, i_ce)?;
+	if c != e && b != e {
+	    mna.add_voltage_controlled_current_source(c, e, b, e, None, gm)?;
+	}
+	Ok(())
+    }
+}
+
+impl Device for Bjt {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn is_nonlinear(&self) -> bool {
+	true
+    }
+
+    /// Scales the saturation currents (the leakage currents by their
+    /// emission coefficients) and the thermal voltage
+    fn set_temperature(&mut self, temperature: f64) {
+	self.temperature = temperature;
+	let m = &self.model;
+	let factor = saturation_current_factor(temperature, m.eg, m.xti, 1.0);
+	self.is = m.is * self.area * factor;
+	self.ise = m.ise * self.area * factor.powf(1.0 / m.ne);
+	self.isc = m.isc * self.area * factor.powf(1.0 / m.nc);
+	self.vt = thermal_voltage(temperature);
+    }
+
+    /// The model parameters that the base currents, the terminal
+    /// resistances and the capacitances scale with
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	let m = &self.model;
+	vec![
+	    ("bf", m.bf),
+	    ("br", m.br),
+	    ("rb", m.rb),
+	    ("rc", m.rc),
+	    ("re", m.re),
+	    ("cje", m.cje),
+	    ("cjc", m.cjc),
+	    ("tf", m.tf),
+	    ("tr", m.tr),
+	]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	if name == "area" {
+	    self.area = value;
+	} else if !self.model.set_param(name, value) {
+	    return false;
+	}
+	let (m, area) = (&self.model, self.area);
+	self.ikf = m.ikf * area;
+	self.ikr = m.ikr * area;
+	self.cje = m.cje * area;
+	self.cjc = m.cjc * area;
+	self.resistances = [m.rc / area, m.rb / area, m.re / area];
+	self.set_temperature(self.temperature);
+	true
+    }
+
+    fn limit(&mut self, x: &Unknowns) -> bool {
+	let (vbe, vbc) = self.junction_voltages(x);
+	let vte = self.model.nf * self.vt;
+	let vtc = self.model.nr * self.vt;
+	let (vbe, limited_be) = pnjlim(vbe, self.vbe, vte, vcrit(self.is, vte));
+	let (vbc, limited_bc) = pnjlim(vbc, self.vbc, vtc, vcrit(self.is, vtc));
+	self.vbe = vbe;
+	self.vbc = vbc;
+	limited_be || limited_bc
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	let op = self.operating_point(self.vbe, self.vbc);
+	self.stamp_linearised(mna, &op, self.resistances.map(|r| 1.0 / r))
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, omega: f64) -> Result<(), AcdcError> {
+	let op = self.operating_point(self.vbe, self.vbc);
+	self.stamp_small_signal(mna, &op, self.resistances.map(|r| 1.0 / r), omega)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	let (slopes, conductances) = self.slopes(param);
+	self.stamp_linearised(mna, &slopes, conductances)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	let (slopes, conductances) = self.slopes(param);
+	self.stamp_small_signal(mna, &slopes, conductances, omega)
+    }
+
+    /// Thermal noise of the terminal resistances, shot noise of the
+    /// collector and base currents, and flicker noise of the base
+    /// current
+    fn noise_sources(&self, frequency: f64, temperature: f64) -> Vec<NoiseSource> {
+	let mut sources = Vec::new();
+	for ((terminal, internal), (r, name)) in self
+	    .terminals
+	    .iter()
+	    .zip(self.internal.iter())
+	    .zip(self.resistances.into_iter().zip(["rc", "rb", "re"]))
+	{
+	    if terminal != internal {
+		let density = thermal_noise(r, temperature);
+		sources.push(NoiseSource::new(name, *terminal, *internal, density));
+	    }
+	}
+
+	let op = self.operating_point(self.vbe, self.vbc);
+	let ic = op.it - op.ib_c;
+	let ib = op.ib_e + op.ib_c;
+	let [c, b, e] = self.internal;
+	let m = &self.model;
+	sources.push(NoiseSource::new("ic", c, e, shot_noise(ic)));
+	sources.push(NoiseSource::new("ib", b, e, shot_noise(ib)));
+	sources.push(NoiseSource::new("1overf", b, e, flicker_noise(m.kf, m.af, ib, frequency)));
+	sources
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	self.stamp_dc(mna, x)?;
+	// Charging currents of each junction, linearised about vbe and vbc
+	let op = self.operating_point(self.vbe, self.vbc);
+	let sign = self.model.polarity.sign();
+	let [c, b, e] = self.internal;
+	for (t, state, q, cap, v) in [
+	    (e, &self.state_be, op.qbe, op.cpi, self.vbe),
+	    (c, &self.state_bc, op.qbc, op.cmu, self.vbc),
+	] {
+	    let (a0, history) = step.integrate(state);
+	    let geq = a0 * cap;
+	    add_branch(mna, b, t, geq, sign * (a0 * q + history - geq * v))?;
+	}
+	Ok(())
+    }
+
+    fn initialise_transient(&mut self, x: &Unknowns) {
+	let (vbe, vbc) = self.junction_voltages(x);
+	let op = self.operating_point(vbe, vbc);
+	self.vbe = vbe;
+	self.vbc = vbc;
+	self.state_be = StateHistory::new(op.qbe, 0.0);
+	self.state_bc = StateHistory::new(op.qbc, 0.0);
+    }
+
+    fn accept_transient(&mut self, x: &Unknowns, step: &TimeStep) {
+	let (vbe, vbc) = self.junction_voltages(x);
+	let op = self.operating_point(vbe, vbc);
+	let (a0, history) = step.integrate(&self.state_be);
+	self.state_be.accept(op.qbe, a0 * op.qbe + history);
+	let (a0, history) = step.integrate(&self.state_bc);
+	self.state_bc.accept(op.qbc, a0 * op.qbc + history);
+    }
+}
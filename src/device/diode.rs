@@ -0,0 +1,446 @@
+//! Junction diode
+//!
+//! SPICE level 1 diode: an exponential junction with reverse
+//! breakdown, a depletion capacitance and a diffusion (transit time)
+//! capacitance, in series with an ohmic resistance. The series
+//! resistance is placed between the anode and an internal node.
+
+use num::Complex;
+
+use crate::{
+    error::AcdcError,
+    mna::Mna,
+    newton::{pnjlim, vcrit},
+    transient::{StateHistory, TimeStep},
+};
+
+use super::{
+    add_branch, flicker_noise, saturation_current_factor, shot_noise, thermal_noise, thermal_voltage, Device,
+    NoiseSource, Unknowns, GMIN, NOMINAL_TEMPERATURE,
+};
+
+/// Diode model parameters, as given on a `.model name D` card
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiodeModel {
+    /// Saturation current (A)
+    pub is: f64,
+    /// Emission coefficient
+    pub n: f64,
+    /// Ohmic series resistance
+    pub rs: f64,
+    /// Zero-bias junction capacitance (F)
+    pub cjo: f64,
+    /// Junction potential (V)
+    pub vj: f64,
+    /// Junction grading coefficient
+    pub m: f64,
+    /// Coefficient for the forward-bias depletion capacitance
+    pub fc: f64,
+    /// Transit time (s)
+    pub tt: f64,
+    /// Reverse breakdown voltage (V), infinite if there is no breakdown
+    pub bv: f64,
+    /// Current at the breakdown voltage (A)
+    pub ibv: f64,
+    /// Energy gap (eV), for the temperature dependence of is
+    pub eg: f64,
+    /// Saturation current temperature exponent
+    pub xti: f64,
+    /// Flicker noise coefficient
+    pub kf: f64,
+    /// Flicker noise exponent
+    pub af: f64,
+}
+
+impl Default for DiodeModel {
+    fn default() -> Self {
+	Self {
+	    is: 1e-14,
+	    n: 1.0,
+	    rs: 0.0,
+	    cjo: 0.0,
+	    vj: 1.0,
+	    m: 0.5,
+	    fc: 0.5,
+	    tt: 0.0,
+	    bv: f64::INFINITY,
+	    ibv: 1e-3,
+	    eg: 1.11,
+	    xti: 3.0,
+	    kf: 0.0,
+	    af: 1.0,
+	}
+    }
+}
+
+impl DiodeModel {
+    /// Set a parameter by its SPICE name, returning false if the name is
+    /// not a diode parameter
+    pub fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "is" => self.is = value,
+	    "n" => self.n = value,
+	    "rs" => self.rs = value,
+	    "cjo" | "cj0" => self.cjo = value,
+	    "vj" => self.vj = value,
+	    "m" => self.m = value,
+	    "fc" => self.fc = value,
+	    "tt" => self.tt = value,
+	    "bv" => self.bv = value,
+	    "ibv" => self.ibv = value,
+	    "eg" => self.eg = value,
+	    "xti" => self.xti = value,
+	    "kf" => self.kf = value,
+	    "af" => self.af = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    /// Every parameter that [set_param](Self::set_param) accepts, by
+    /// its SPICE name, with its value
+    pub fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![
+	    ("is", self.is),
+	    ("n", self.n),
+	    ("rs", self.rs),
+	    ("cjo", self.cjo),
+	    ("vj", self.vj),
+	    ("m", self.m),
+	    ("fc", self.fc),
+	    ("tt", self.tt),
+	    ("bv", self.bv),
+	    ("ibv", self.ibv),
+	    ("eg", self.eg),
+	    ("xti", self.xti),
+	    ("kf", self.kf),
+	    ("af", self.af),
+	]
+    }
+}
+
+/// Current and conductance of a pn junction (including breakdown)
+/// with saturation current is, at junction voltage vd. vte is the
+/// thermal voltage times the emission coefficient, and xbv the
+/// voltage at which breakdown takes over.
+pub(crate) fn junction_current(is: f64, vte: f64, xbv: f64, vd: f64) -> (f64, f64) {
+    if vd >= -3.0 * vte {
+	let e = (vd / vte).exp();
+	(is * (e - 1.0) + GMIN * vd, is * e / vte + GMIN)
+    } else if vd >= -xbv {
+	// Smooth approach to -is in reverse bias
+	let arg = (3.0 * vte / (vd * std::f64::consts::E)).powi(3);
+	(-is * (1.0 + arg) + GMIN * vd, 3.0 * is * arg / vd + GMIN)
+    } else {
+	let e = (-(xbv + vd) / vte).exp();
+	(-is * e + GMIN * vd, is * e / vte + GMIN)
+    }
+}
+
+/// Derivatives of the current and conductance of a junction (see
+/// [junction_current]) with respect to its saturation current, where
+/// xbv moves with is so that the current at the breakdown voltage is
+/// held (as it is in a diode)
+pub(crate) fn junction_current_slope(vte: f64, xbv: f64, vd: f64) -> (f64, f64) {
+    if vd >= -3.0 * vte {
+	let e = (vd / vte).exp();
+	(e - 1.0, e / vte)
+    } else if vd >= -xbv {
+	let arg = (3.0 * vte / (vd * std::f64::consts::E)).powi(3);
+	(-(1.0 + arg), 3.0 * arg / vd)
+    } else {
+	(0.0, 0.0)
+    }
+}
+
+/// Depletion charge and capacitance of a junction with zero-bias
+/// capacitance cj0, potential vj, grading m and forward-bias
+/// coefficient fc, at junction voltage v
+pub fn depletion_charge(cj0: f64, vj: f64, m: f64, fc: f64, v: f64) -> (f64, f64) {
+    if cj0 == 0.0 {
+	return (0.0, 0.0);
+    }
+    let v_fc = fc * vj;
+    if v < v_fc {
+	let arg = 1.0 - v / vj;
+	let sarg = arg.powf(-m);
+	(vj * cj0 * (1.0 - arg * sarg) / (1.0 - m), cj0 * sarg)
+    } else {
+	// Linear extrapolation of the capacitance above fc * vj
+	let f1 = vj * (1.0 - (1.0 - fc).powf(1.0 - m)) / (1.0 - m);
+	let f2 = (1.0 - fc).powf(1.0 + m);
+	let f3 = 1.0 - fc * (1.0 + m);
+	let c = cj0 / f2;
+	let q = cj0 * f1 + c * (f3 * (v - v_fc) + m / (2.0 * vj) * (v * v - v_fc * v_fc));
+	(q, c * (f3 + m * v / vj))
+    }
+}
+
+pub struct Diode {
+    name: String,
+    anode: usize,
+    cathode: usize,
+    /// Anode side of the junction (the anode itself if there is no
+    /// series resistance)
+    internal: usize,
+    model: DiodeModel,
+    area: f64,
+    /// Saturation current scaled by area, at the device temperature
+    is: f64,
+    /// Zero-bias capacitance scaled by area
+    cjo: f64,
+    /// Series resistance scaled by area
+    rs: f64,
+    /// Emission coefficient times thermal voltage, at the device
+    /// temperature
+    vte: f64,
+    /// Voltage at which the breakdown current takes over
+    xbv: f64,
+    /// Device temperature (K)
+    temperature: f64,
+    /// Junction voltage that the device is linearised about
+    vd: f64,
+    /// Junction charge history
+    state: StateHistory,
+}
+
+impl Diode {
+    /// New diode. The internal node is only used if the model has a
+    /// series resistance, and should otherwise be the anode.
+    pub fn new(
+	name: &str,
+	anode: usize,
+	cathode: usize,
+	internal: usize,
+	model: DiodeModel,
+	area: f64,
+    ) -> Self {
+	let mut diode = Self {
+	    name: String::from(name),
+	    anode,
+	    cathode,
+	    internal,
+	    cjo: model.cjo * area,
+	    rs: model.rs / area,
+	    model,
+	    area,
+	    is: 0.0,
+	    vte: 0.0,
+	    xbv: 0.0,
+	    temperature: NOMINAL_TEMPERATURE,
+	    vd: 0.0,
+	    state: StateHistory::default(),
+	};
+	diode.set_temperature(NOMINAL_TEMPERATURE);
+	diode
+    }
+
+    /// Junction voltage in the solution x
+    fn junction_voltage(&self, x: &Unknowns) -> f64 {
+	x.v(self.internal) - x.v(self.cathode)
+    }
+
+    /// Junction current and conductance at the linearisation point
+    fn current(&self) -> (f64, f64) {
+	junction_current(self.is, self.vte, self.xbv, self.vd)
+    }
+
+    /// Junction charge and capacitance at junction voltage vd, given
+    /// the junction current and conductance there
+    fn charge(&self, vd: f64, id: f64, gd: f64) -> (f64, f64) {
+	let m = &self.model;
+	let (qj, cj) = depletion_charge(self.cjo, m.vj, m.m, m.fc, vd);
+	(m.tt * id + qj, m.tt * gd + cj)
+    }
+
+    /// Derivatives, with respect to one of the values named by
+    /// [params](Device::params), of the series conductance, and of the
+    /// junction current, conductance and capacitance at the
+    /// linearisation point
+    fn slopes(&self, param: &str) -> [f64; 4] {
+	let m = &self.model;
+	let factor = saturation_current_factor(self.temperature, m.eg, m.xti, m.n);
+	// Derivatives of is (at the device temperature), rs, cjo and tt,
+	// as scaled by area
+	let (dis, drs, dcjo, dtt) = match param {
+	    "area" => (m.is * factor, -m.rs / (self.area * self.area), m.cjo, 0.0),
+	    "is" => (self.area * factor, 0.0, 0.0, 0.0),
+	    "rs" => (0.0, 1.0 / self.area, 0.0, 0.0),
+	    "cjo" => (0.0, 0.0, self.area, 0.0),
+	    "tt" => (0.0, 0.0, 0.0, 1.0),
+	    _ => (0.0, 0.0, 0.0, 0.0),
+	};
+	let dgs = if self.internal == self.anode {
+	    0.0
+	} else {
+	    -drs / (self.rs * self.rs)
+	};
+	let (di_dis, dg_dis) = junction_current_slope(self.vte, self.xbv, self.vd);
+	let (_, gd) = self.current();
+	let (_, cj) = depletion_charge(1.0, m.vj, m.m, m.fc, self.vd);
+	let dg = dg_dis * dis;
+	[dgs, di_dis * dis, dg, dtt * gd + m.tt * dg + cj * dcjo]
+    }
+
+    fn stamp_series_resistance<P>(&self, mna: &mut Mna<P>, r: P) -> Result<(), AcdcError>
+    where
+	P: csuperlu::c::value_type::ValueType + std::ops::Neg<Output = P>,
+    {
+	if self.internal != self.anode {
+	    mna.add_impedance(self.anode, self.internal, None, r)?;
+	}
+	Ok(())
+    }
+}
+
+impl Device for Diode {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn is_nonlinear(&self) -> bool {
+	true
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+	self.temperature = temperature;
+	let m = &self.model;
+	self.is = m.is * self.area * saturation_current_factor(temperature, m.eg, m.xti, m.n);
+	self.vte = m.n * thermal_voltage(temperature);
+	self.xbv = if m.bv.is_finite() {
+	    m.bv - self.vte * (m.ibv / self.is).ln()
+	} else {
+	    f64::INFINITY
+	};
+    }
+
+    /// The area, and the model parameters that the junction current,
+    /// the series resistance and the capacitance scale with
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	let m = &self.model;
+	vec![
+	    ("area", self.area),
+	    ("is", m.is),
+	    ("rs", m.rs),
+	    ("cjo", m.cjo),
+	    ("tt", m.tt),
+	]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	if name == "area" {
+	    self.area = value;
+	} else if !self.model.set_param(name, value) {
+	    return false;
+	}
+	self.cjo = self.model.cjo * self.area;
+	self.rs = self.model.rs / self.area;
+	self.set_temperature(self.temperature);
+	true
+    }
+
+    fn limit(&mut self, x: &Unknowns) -> bool {
+	let v_new = self.junction_voltage(x);
+	let v_old = self.vd;
+	let vcrit = vcrit(self.is, self.vte);
+	let (vd, limited) = if v_new < (-self.xbv + 10.0 * self.vte).min(0.0) {
+	    // Limit the voltage beyond breakdown in the same way
+	    let (v, limited) = pnjlim(-(v_new + self.xbv), -(v_old + self.xbv), self.vte, vcrit);
+	    (-(v + self.xbv), limited)
+	} else {
+	    pnjlim(v_new, v_old, self.vte, vcrit)
+	};
+	self.vd = vd;
+	limited
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	self.stamp_series_resistance(mna, self.rs)?;
+	let (id, gd) = self.current();
+	mna.add_norton(self.internal, self.cathode, None, gd, id - gd * self.vd)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, omega: f64) -> Result<(), AcdcError> {
+	self.stamp_series_resistance(mna, Complex::new(self.rs, 0.0))?;
+	let (id, gd) = self.current();
+	let (_, cd) = self.charge(self.vd, id, gd);
+	let y = Complex::new(gd, omega * cd);
+	mna.add_norton(self.internal, self.cathode, None, y, Complex::new(0.0, 0.0))
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	let [dgs, di, dg, _] = self.slopes(param);
+	add_branch(mna, self.anode, self.internal, dgs, 0.0)?;
+	mna.add_norton(self.internal, self.cathode, None, dg, di - dg * self.vd)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	let [dgs, _, dg, dc] = self.slopes(param);
+	let zero = Complex::new(0.0, 0.0);
+	add_branch(mna, self.anode, self.internal, Complex::new(dgs, 0.0), zero)?;
+	mna.add_norton(self.internal, self.cathode, None, Complex::new(dg, omega * dc), zero)
+    }
+
+    /// Shot and flicker noise of the junction current, and thermal
+    /// noise of the series resistance
+    fn noise_sources(&self, frequency: f64, temperature: f64) -> Vec<NoiseSource> {
+	let (id, _) = self.current();
+	let m = &self.model;
+	let mut sources = vec![
+	    NoiseSource::new("id", self.internal, self.cathode, shot_noise(id)),
+	    NoiseSource::new(
+		"1overf",
+		self.internal,
+		self.cathode,
+		flicker_noise(m.kf, m.af, id, frequency),
+	    ),
+	];
+	if self.internal != self.anode {
+	    let density = thermal_noise(self.rs, temperature);
+	    sources.push(NoiseSource::new("rs", self.anode, self.internal, density));
+	}
+	sources
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	self.stamp_dc(mna, x)?;
+	// Charging current a0 * q + history, linearised about vd
+	let (id, gd) = self.current();
+	let (q, c) = self.charge(self.vd, id, gd);
+	let (a0, history) = step.integrate(&self.state);
+	let geq = a0 * c;
+	let ieq = a0 * q + history - geq * self.vd;
+	mna.add_norton(self.internal, self.cathode, None, geq, ieq)
+    }
+
+    fn initialise_transient(&mut self, x: &Unknowns) {
+	let vd = self.junction_voltage(x);
+	let (id, gd) = junction_current(self.is, self.vte, self.xbv, vd);
+	let (q, _) = self.charge(vd, id, gd);
+	self.vd = vd;
+	self.state = StateHistory::new(q, 0.0);
+    }
+
+    fn accept_transient(&mut self, x: &Unknowns, step: &TimeStep) {
+	let vd = self.junction_voltage(x);
+	let (id, gd) = junction_current(self.is, self.vte, self.xbv, vd);
+	let (q, _) = self.charge(vd, id, gd);
+	let (a0, history) = step.integrate(&self.state);
+	self.state.accept(q, a0 * q + history);
+    }
+}
@@ -0,0 +1,1287 @@
+//! Linear devices
+//!
+//! Resistors, capacitors, inductors, and independent and controlled
+//! sources. Capacitors and inductors keep the history of their charge
+//! or flux, which is used to build their transient companion models.
+
+use std::ops::Neg;
+
+use csuperlu::c::value_type::ValueType;
+use num::Complex;
+
+use crate::{
+    error::AcdcError,
+    mna::{Mna, Probe},
+    transient::{StateHistory, TimeStep},
+    waveform::SourceWaveform,
+};
+
+use super::{thermal_noise, Device, NoiseSource, Unknowns};
+
+pub struct Resistor {
+    name: String,
+    term_1: usize,
+    term_2: usize,
+    current_edge: Option<usize>,
+    resistance: f64,
+}
+
+impl Resistor {
+    pub fn new(
+	name: &str,
+	term_1: usize,
+	term_2: usize,
+	current_edge: Option<usize>,
+	resistance: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_1,
+	    term_2,
+	    current_edge,
+	    resistance,
+	}
+    }
+
+    /// Stamp the derivative with respect to the resistance, which is
+    /// the same at DC and in the small-signal model
+    fn stamp_derivative<P>(&self, param: &str, mna: &mut Mna<P>) -> Result<(), AcdcError>
+    where
+	P: ValueType + Neg<Output = P> + From<f64>,
+    {
+	if param != "r" {
+	    return Ok(());
+	}
+	match self.current_edge {
+	    Some(e) => {
+		mna.add_branch_current_term(e, e, P::one());
+		Ok(())
+	    }
+	    None => {
+		let dg = -1.0 / (self.resistance * self.resistance);
+		mna.add_norton(self.term_1, self.term_2, None, dg.into(), P::zero())
+	    }
+	}
+    }
+}
+
+impl Device for Resistor {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("r", self.resistance)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "r" => self.resistance = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_impedance(self.term_1, self.term_2, self.current_edge, self.resistance)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	let r = Complex::new(self.resistance, 0.0);
+	mna.add_impedance(self.term_1, self.term_2, self.current_edge, r)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+
+    fn noise_sources(&self, _frequency: f64, temperature: f64) -> Vec<NoiseSource> {
+	let density = thermal_noise(self.resistance, temperature);
+	vec![NoiseSource::new("thermal", self.term_1, self.term_2, density)]
+    }
+}
+
+pub struct Capacitor {
+    name: String,
+    term_1: usize,
+    term_2: usize,
+    current_edge: Option<usize>,
+    capacitance: f64,
+    /// Charge history
+    state: StateHistory,
+}
+
+impl Capacitor {
+    pub fn new(
+	name: &str,
+	term_1: usize,
+	term_2: usize,
+	current_edge: Option<usize>,
+	capacitance: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_1,
+	    term_2,
+	    current_edge,
+	    capacitance,
+	    state: StateHistory::default(),
+	}
+    }
+}
+
+impl Device for Capacitor {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("c", self.capacitance)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "c" => self.capacitance = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	// Open circuit. If the current is in group 2, it is zero.
+	match self.current_edge {
+	    Some(e) => mna.add_independent_current_source(self.term_1, self.term_2, Some(e), 0.0),
+	    None => Ok(()),
+	}
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, omega: f64) -> Result<(), AcdcError> {
+	let y = Complex::i() * omega * self.capacitance;
+	match self.current_edge {
+	    // At DC, the current in group 2 is zero
+	    Some(e) if omega == 0.0 => mna.add_independent_current_source(
+		self.term_1,
+		self.term_2,
+		Some(e),
+		Complex::new(0.0, 0.0),
+	    ),
+	    Some(e) => mna.add_impedance(self.term_1, self.term_2, Some(e), 1.0 / y),
+	    None => mna.add_norton(self.term_1, self.term_2, None, y, Complex::new(0.0, 0.0)),
+	}
+    }
+
+    /// The capacitor is open at DC, so only its small-signal model has
+    /// a derivative
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	if param != "c" {
+	    return Ok(());
+	}
+	let dy = Complex::i() * omega;
+	match self.current_edge {
+	    Some(_) if omega == 0.0 => Ok(()),
+	    Some(e) => {
+		// Derivative of the impedance 1 / (j omega C)
+		let dz = -1.0 / (dy * self.capacitance * self.capacitance);
+		mna.add_branch_current_term(e, e, dz);
+		Ok(())
+	    }
+	    None => mna.add_norton(self.term_1, self.term_2, None, dy, Complex::new(0.0, 0.0)),
+	}
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	// i = dq/dt = a0 * C * v + history
+	let (a0, history) = step.integrate(&self.state);
+	mna.add_norton(
+	    self.term_1,
+	    self.term_2,
+	    self.current_edge,
+	    a0 * self.capacitance,
+	    history,
+	)
+    }
+
+    fn initialise_transient(&mut self, x: &Unknowns) {
+	let v = x.v(self.term_1) - x.v(self.term_2);
+	let i = self.current_edge.map_or(0.0, |e| x.i(e));
+	self.state = StateHistory::new(self.capacitance * v, i);
+    }
+
+    fn accept_transient(&mut self, x: &Unknowns, step: &TimeStep) {
+	let v = x.v(self.term_1) - x.v(self.term_2);
+	let q = self.capacitance * v;
+	let (a0, history) = step.integrate(&self.state);
+	self.state.accept(q, a0 * q + history);
+    }
+}
+
+pub struct Inductor {
+    name: String,
+    term_1: usize,
+    term_2: usize,
+    current_edge: usize,
+    inductance: f64,
+    /// Flux history
+    state: StateHistory,
+}
+
+impl Inductor {
+    /// The inductor current is always in group 2
+    pub fn new(
+	name: &str,
+	term_1: usize,
+	term_2: usize,
+	current_edge: usize,
+	inductance: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_1,
+	    term_2,
+	    current_edge,
+	    inductance,
+	    state: StateHistory::default(),
+	}
+    }
+}
+
+impl Device for Inductor {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("l", self.inductance)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "l" => self.inductance = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn inductance(&self) -> Option<(usize, f64)> {
+	Some((self.current_edge, self.inductance))
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	// Short circuit
+	mna.add_impedance(self.term_1, self.term_2, Some(self.current_edge), 0.0)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, omega: f64) -> Result<(), AcdcError> {
+	let z = Complex::i() * omega * self.inductance;
+	mna.add_impedance(self.term_1, self.term_2, Some(self.current_edge), z)
+    }
+
+    /// The inductor is a short circuit at DC, so only its small-signal
+    /// model has a derivative
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	if param == "l" {
+	    let e = self.current_edge;
+	    mna.add_branch_current_term(e, e, Complex::i() * omega);
+	}
+	Ok(())
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	// v = d(flux)/dt = a0 * L * i + history
+	let (a0, history) = step.integrate(&self.state);
+	mna.add_thevenin(
+	    self.term_1,
+	    self.term_2,
+	    self.current_edge,
+	    a0 * self.inductance,
+	    history,
+	)
+    }
+
+    fn initialise_transient(&mut self, x: &Unknowns) {
+	let v = x.v(self.term_1) - x.v(self.term_2);
+	let i = x.i(self.current_edge);
+	self.state = StateHistory::new(self.inductance * i, v);
+    }
+
+    fn accept_transient(&mut self, x: &Unknowns, step: &TimeStep) {
+	// The derivative comes from the integration formula rather than
+	// the terminal voltage, which also includes any mutual coupling
+	let flux = self.inductance * x.i(self.current_edge);
+	let (a0, history) = step.integrate(&self.state);
+	self.state.accept(flux, a0 * flux + history);
+    }
+}
+
+/// Mutual inductance between two inductors, given by their current
+/// edges
+pub struct MutualInductance {
+    name: String,
+    edge_1: usize,
+    edge_2: usize,
+    inductance: f64,
+    /// History of the flux linking each inductor due to the current in
+    /// the other
+    state_1: StateHistory,
+    state_2: StateHistory,
+}
+
+impl MutualInductance {
+    pub fn new(name: &str, edge_1: usize, edge_2: usize, inductance: f64) -> Self {
+	Self {
+	    name: String::from(name),
+	    edge_1,
+	    edge_2,
+	    inductance,
+	    state_1: StateHistory::default(),
+	    state_2: StateHistory::default(),
+	}
+    }
+}
+
+impl Device for MutualInductance {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("m", self.inductance)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "m" => self.inductance = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, _mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	// The inductors are short circuits, whatever their coupling
+	Ok(())
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, omega: f64) -> Result<(), AcdcError> {
+	let z = Complex::i() * omega * self.inductance;
+	let zero = Complex::new(0.0, 0.0);
+	mna.add_mutual_impedance(self.edge_1, self.edge_2, z, zero, zero)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	if param != "m" {
+	    return Ok(());
+	}
+	let dz = Complex::i() * omega;
+	let zero = Complex::new(0.0, 0.0);
+	mna.add_mutual_impedance(self.edge_1, self.edge_2, dz, zero, zero)
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	let (a0, history_1) = step.integrate(&self.state_1);
+	let (_, history_2) = step.integrate(&self.state_2);
+	mna.add_mutual_impedance(
+	    self.edge_1,
+	    self.edge_2,
+	    a0 * self.inductance,
+	    history_1,
+	    history_2,
+	)
+    }
+
+    fn initialise_transient(&mut self, x: &Unknowns) {
+	self.state_1 = StateHistory::new(self.inductance * x.i(self.edge_2), 0.0);
+	self.state_2 = StateHistory::new(self.inductance * x.i(self.edge_1), 0.0);
+    }
+
+    fn accept_transient(&mut self, x: &Unknowns, step: &TimeStep) {
+	for (state, edge) in [(&mut self.state_1, self.edge_2), (&mut self.state_2, self.edge_1)] {
+	    let flux = self.inductance * x.i(edge);
+	    let (a0, history) = step.integrate(state);
+	    state.accept(flux, a0 * flux + history);
+	}
+    }
+}
+
+/// Ideal op-amp (nullor), whose output current is always in group 2
+pub struct IdealOpAmp {
+    name: String,
+    in_pos: usize,
+    in_neg: usize,
+    out: usize,
+    current_edge: usize,
+}
+
+impl IdealOpAmp {
+    pub fn new(name: &str, in_pos: usize, in_neg: usize, out: usize, current_edge: usize) -> Self {
+	Self {
+	    name: String::from(name),
+	    in_pos,
+	    in_neg,
+	    out,
+	    current_edge,
+	}
+    }
+}
+
+impl Device for IdealOpAmp {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_ideal_op_amp(self.in_pos, self.in_neg, self.out, self.current_edge)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_ideal_op_amp(self.in_pos, self.in_neg, self.out, self.current_edge)
+    }
+}
+
+/// Ideal transformer with turns ratio (primary turns) / (secondary
+/// turns), whose primary current is always in group 2
+pub struct IdealTransformer {
+    name: String,
+    pri_pos: usize,
+    pri_neg: usize,
+    sec_pos: usize,
+    sec_neg: usize,
+    current_edge: usize,
+    ratio: f64,
+}
+
+impl IdealTransformer {
+    pub fn new(
+	name: &str,
+	pri_pos: usize,
+	pri_neg: usize,
+	sec_pos: usize,
+	sec_neg: usize,
+	current_edge: usize,
+	ratio: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    pri_pos,
+	    pri_neg,
+	    sec_pos,
+	    sec_neg,
+	    current_edge,
+	    ratio,
+	}
+    }
+
+    /// Stamp the derivative with respect to the turns ratio
+    fn stamp_derivative<P>(&self, param: &str, mna: &mut Mna<P>) -> Result<(), AcdcError>
+    where
+	P: ValueType + Neg<Output = P>,
+    {
+	if param != "ratio" {
+	    return Ok(());
+	}
+	let e = self.current_edge;
+	mna.add_branch_voltage_term(e, self.sec_pos, self.sec_neg, P::one())?;
+	mna.add_current_controlled_current_source(
+	    self.sec_pos,
+	    self.sec_neg,
+	    e,
+	    None,
+	    -P::one(),
+	)
+    }
+}
+
+impl Device for IdealTransformer {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("ratio", self.ratio)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "ratio" => self.ratio = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_ideal_transformer(
+	    self.pri_pos,
+	    self.pri_neg,
+	    self.sec_pos,
+	    self.sec_neg,
+	    self.current_edge,
+	    self.ratio,
+	)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_ideal_transformer(
+	    self.pri_pos,
+	    self.pri_neg,
+	    self.sec_pos,
+	    self.sec_neg,
+	    self.current_edge,
+	    Complex::new(self.ratio, 0.0),
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+}
+
+pub struct VoltageSource {
+    name: String,
+    term_pos: usize,
+    term_neg: usize,
+    current_edge: usize,
+    waveform: SourceWaveform,
+}
+
+impl VoltageSource {
+    pub fn new(
+	name: &str,
+	term_pos: usize,
+	term_neg: usize,
+	current_edge: usize,
+	waveform: SourceWaveform,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_pos,
+	    term_neg,
+	    current_edge,
+	    waveform,
+	}
+    }
+}
+
+impl Device for VoltageSource {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![
+	    ("dc", self.waveform.dc_value()),
+	    ("acmag", self.waveform.ac_magnitude()),
+	]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "dc" => self.waveform.set_dc(value),
+	    "acmag" => {
+		let phase = self.waveform.ac_phase();
+		self.waveform.set_ac(value, phase)
+	    }
+	    _ => return false,
+	}
+	true
+    }
+
+    fn waveform_mut(&mut self) -> Option<&mut SourceWaveform> {
+	Some(&mut self.waveform)
+    }
+
+    fn source_probe(&self) -> Option<Probe> {
+	Some(Probe::Current(self.current_edge))
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_independent_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.dc_value(),
+	)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_independent_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.ac_value(),
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	if param == "dc" {
+	    mna.add_branch_rhs(self.current_edge, 1.0);
+	}
+	Ok(())
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	if param == "acmag" {
+	    let phase = self.waveform.ac_phase().to_radians();
+	    mna.add_branch_rhs(self.current_edge, Complex::from_polar(1.0, phase));
+	}
+	Ok(())
+    }
+
+    fn stamp_initial(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_independent_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.value_at(0.0),
+	)
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	mna.add_independent_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.value_at(step.time),
+	)
+    }
+
+    fn breakpoints(&self, stop: f64) -> Vec<f64> {
+	self.waveform.breakpoints(stop)
+    }
+}
+
+pub struct CurrentSource {
+    name: String,
+    term_pos: usize,
+    term_neg: usize,
+    current_edge: Option<usize>,
+    waveform: SourceWaveform,
+}
+
+impl CurrentSource {
+    pub fn new(
+	name: &str,
+	term_pos: usize,
+	term_neg: usize,
+	current_edge: Option<usize>,
+	waveform: SourceWaveform,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_pos,
+	    term_neg,
+	    current_edge,
+	    waveform,
+	}
+    }
+
+    /// Stamp the derivative with respect to the source value, which
+    /// changes the current by slope
+    fn stamp_derivative<P>(&self, mna: &mut Mna<P>, slope: P) -> Result<(), AcdcError>
+    where
+	P: ValueType + Neg<Output = P>,
+    {
+	match self.current_edge {
+	    Some(e) => {
+		mna.add_branch_rhs(e, slope);
+		Ok(())
+	    }
+	    None => mna.add_independent_current_source(self.term_pos, self.term_neg, None, slope),
+	}
+    }
+}
+
+impl Device for CurrentSource {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![
+	    ("dc", self.waveform.dc_value()),
+	    ("acmag", self.waveform.ac_magnitude()),
+	]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "dc" => self.waveform.set_dc(value),
+	    "acmag" => {
+		let phase = self.waveform.ac_phase();
+		self.waveform.set_ac(value, phase)
+	    }
+	    _ => return false,
+	}
+	true
+    }
+
+    fn waveform_mut(&mut self) -> Option<&mut SourceWaveform> {
+	Some(&mut self.waveform)
+    }
+
+    fn source_probe(&self) -> Option<Probe> {
+	Some(Probe::Voltage(self.term_neg, self.term_pos))
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_independent_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.dc_value(),
+	)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_independent_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.ac_value(),
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	if param == "dc" {
+	    self.stamp_derivative(mna, 1.0)?;
+	}
+	Ok(())
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	if param == "acmag" {
+	    let phase = self.waveform.ac_phase().to_radians();
+	    self.stamp_derivative(mna, Complex::from_polar(1.0, phase))?;
+	}
+	Ok(())
+    }
+
+    fn stamp_initial(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_independent_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.value_at(0.0),
+	)
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	mna.add_independent_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.current_edge,
+	    self.waveform.value_at(step.time),
+	)
+    }
+
+    fn breakpoints(&self, stop: f64) -> Vec<f64> {
+	self.waveform.breakpoints(stop)
+    }
+}
+
+/// Voltage-controlled voltage source
+pub struct Vcvs {
+    name: String,
+    term_pos: usize,
+    term_neg: usize,
+    ctrl_pos: usize,
+    ctrl_neg: usize,
+    current_edge: usize,
+    gain: f64,
+}
+
+impl Vcvs {
+    pub fn new(
+	name: &str,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_pos: usize,
+	ctrl_neg: usize,
+	current_edge: usize,
+	gain: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_pos,
+	    term_neg,
+	    ctrl_pos,
+	    ctrl_neg,
+	    current_edge,
+	    gain,
+	}
+    }
+
+    /// Stamp the derivative with respect to the gain
+    fn stamp_derivative<P>(&self, param: &str, mna: &mut Mna<P>) -> Result<(), AcdcError>
+    where
+	P: ValueType + Neg<Output = P>,
+    {
+	if param != "gain" {
+	    return Ok(());
+	}
+	mna.add_branch_voltage_term(self.current_edge, self.ctrl_pos, self.ctrl_neg, P::one())
+    }
+}
+
+impl Device for Vcvs {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("gain", self.gain)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "gain" => self.gain = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_voltage_controlled_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_pos,
+	    self.ctrl_neg,
+	    self.current_edge,
+	    self.gain,
+	)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_voltage_controlled_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_pos,
+	    self.ctrl_neg,
+	    self.current_edge,
+	    Complex::new(self.gain, 0.0),
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+}
+
+/// Current-controlled voltage source
+pub struct Ccvs {
+    name: String,
+    term_pos: usize,
+    term_neg: usize,
+    ctrl_edge: usize,
+    current_edge: usize,
+    transresistance: f64,
+}
+
+impl Ccvs {
+    pub fn new(
+	name: &str,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_edge: usize,
+	current_edge: usize,
+	transresistance: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_pos,
+	    term_neg,
+	    ctrl_edge,
+	    current_edge,
+	    transresistance,
+	}
+    }
+
+    /// Stamp the derivative with respect to the transresistance
+    fn stamp_derivative<P>(&self, param: &str, mna: &mut Mna<P>) -> Result<(), AcdcError>
+    where
+	P: ValueType + Neg<Output = P>,
+    {
+	if param != "transresistance" {
+	    return Ok(());
+	}
+	mna.add_branch_current_term(self.current_edge, self.ctrl_edge, P::one());
+	Ok(())
+    }
+}
+
+impl Device for Ccvs {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("transresistance", self.transresistance)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "transresistance" => self.transresistance = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_current_controlled_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_edge,
+	    self.current_edge,
+	    self.transresistance,
+	)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_current_controlled_voltage_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_edge,
+	    self.current_edge,
+	    Complex::new(self.transresistance, 0.0),
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+}
+
+/// Voltage-controlled current source
+pub struct Vccs {
+    name: String,
+    term_pos: usize,
+    term_neg: usize,
+    ctrl_pos: usize,
+    ctrl_neg: usize,
+    current_edge: Option<usize>,
+    transconductance: f64,
+}
+
+impl Vccs {
+    pub fn new(
+	name: &str,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_pos: usize,
+	ctrl_neg: usize,
+	current_edge: Option<usize>,
+	transconductance: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_pos,
+	    term_neg,
+	    ctrl_pos,
+	    ctrl_neg,
+	    current_edge,
+	    transconductance,
+	}
+    }
+
+    /// Stamp the derivative with respect to the transconductance
+    fn stamp_derivative<P>(&self, param: &str, mna: &mut Mna<P>) -> Result<(), AcdcError>
+    where
+	P: ValueType + Neg<Output = P>,
+    {
+	if param != "transconductance" {
+	    return Ok(());
+	}
+	match self.current_edge {
+	    Some(e) => mna.add_branch_voltage_term(e, self.ctrl_pos, self.ctrl_neg, P::one()),
+	    None => mna.add_voltage_controlled_current_source(
+		self.term_pos,
+		self.term_neg,
+		self.ctrl_pos,
+		self.ctrl_neg,
+		None,
+		P::one(),
+	    ),
+	}
+    }
+}
+
+impl Device for Vccs {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("transconductance", self.transconductance)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "transconductance" => self.transconductance = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_voltage_controlled_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_pos,
+	    self.ctrl_neg,
+	    self.current_edge,
+	    self.transconductance,
+	)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_voltage_controlled_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_pos,
+	    self.ctrl_neg,
+	    self.current_edge,
+	    Complex::new(self.transconductance, 0.0),
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+}
+
+/// Current-controlled current source
+pub struct Cccs {
+    name: String,
+    term_pos: usize,
+    term_neg: usize,
+    ctrl_edge: usize,
+    current_edge: Option<usize>,
+    gain: f64,
+}
+
+impl Cccs {
+    pub fn new(
+	name: &str,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_edge: usize,
+	current_edge: Option<usize>,
+	gain: f64,
+    ) -> Self {
+	Self {
+	    name: String::from(name),
+	    term_pos,
+	    term_neg,
+	    ctrl_edge,
+	    current_edge,
+	    gain,
+	}
+    }
+
+    /// Stamp the derivative with respect to the gain
+    fn stamp_derivative<P>(&self, param: &str, mna: &mut Mna<P>) -> Result<(), AcdcError>
+    where
+	P: ValueType + Neg<Output = P>,
+    {
+	if param != "gain" {
+	    return Ok(());
+	}
+	match self.current_edge {
+	    Some(e) => {
+		mna.add_branch_current_term(e, self.ctrl_edge, P::one());
+		Ok(())
+	    }
+	    None => mna.add_current_controlled_current_source(
+		self.term_pos,
+		self.term_neg,
+		self.ctrl_edge,
+		None,
+		P::one(),
+	    ),
+	}
+    }
+}
+
+impl Device for Cccs {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![("gain", self.gain)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "gain" => self.gain = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	mna.add_current_controlled_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_edge,
+	    self.current_edge,
+	    self.gain,
+	)
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, _omega: f64) -> Result<(), AcdcError> {
+	mna.add_current_controlled_current_source(
+	    self.term_pos,
+	    self.term_neg,
+	    self.ctrl_edge,
+	    self.current_edge,
+	    Complex::new(self.gain, 0.0),
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	_omega: f64,
+    ) -> Result<(), AcdcError> {
+	self.stamp_derivative(param, mna)
+    }
+}
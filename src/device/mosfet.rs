@@ -0,0 +1,902 @@
+//! MOSFET
+//!
+//! SPICE level 1 (Shichman-Hodges) and level 3 (semi-empirical short
+//! channel) MOSFETs. Both levels include the body effect and channel
+//! length modulation; level 3 adds mobility degradation (theta),
+//! velocity saturation (vmax) and drain-induced barrier lowering
+//! (eta). The gate capacitances follow the Meyer model, and the bulk
+//! connects to the drain and source through junction diodes. The
+//! drain and source resistances are placed between the terminals and
+//! internal nodes.
+//!
+//! The equations are written for an n-channel device with its drain
+//! above its source; a p-channel device uses them with its voltages
+//! and currents negated, and the drain and source swap over when the
+//! drain is below the source.
+
+use num::Complex;
+
+use crate::{
+    error::AcdcError,
+    mna::Mna,
+    newton::{fetlim, limvds, pnjlim, vcrit},
+    transient::{StateHistory, TimeStep},
+};
+
+use super::{
+    add_branch,
+    diode::{depletion_charge, junction_current, junction_current_slope},
+    flicker_noise, thermal_noise, thermal_voltage, Device, NoiseSource, Polarity, Unknowns,
+    BOLTZMANN, CHARGE, EPSILON_0, NOMINAL_TEMPERATURE,
+};
+
+/// Permittivity of silicon dioxide (F/m)
+const EPSILON_OX: f64 = 3.9 * EPSILON_0;
+
+/// Permittivity of silicon (F/m)
+const EPSILON_SI: f64 = 11.7 * EPSILON_0;
+
+/// Voltage step used to differentiate the level 3 drain current
+const DELTA_V: f64 = 1e-6;
+
+/// MOSFET model parameters, as given on a `.model name NMOS` or
+/// `.model name PMOS` card
+#[derive(Debug, Clone, PartialEq)]
+pub struct MosfetModel {
+    pub polarity: Polarity,
+    /// Model level (1 or 3)
+    pub level: u32,
+    /// Zero-bias threshold voltage (V)
+    pub vto: f64,
+    /// Transconductance parameter (A/V^2). If not given, it is found
+    /// from uo and tox.
+    pub kp: Option<f64>,
+    /// Bulk threshold parameter (V^0.5)
+    pub gamma: f64,
+    /// Surface potential (V)
+    pub phi: f64,
+    /// Channel length modulation (1/V, level 1 only)
+    pub lambda: f64,
+    /// Drain resistance
+    pub rd: f64,
+    /// Source resistance
+    pub rs: f64,
+    /// Bulk junction saturation current (A)
+    pub is: f64,
+    /// Zero-bias bulk-drain junction capacitance (F)
+    pub cbd: f64,
+    /// Zero-bias bulk-source junction capacitance (F)
+    pub cbs: f64,
+    /// Bulk junction potential (V)
+    pub pb: f64,
+    /// Bulk junction grading coefficient
+    pub mj: f64,
+    /// Coefficient for the forward-bias depletion capacitance
+    pub fc: f64,
+    /// Gate-source overlap capacitance per metre of channel width (F/m)
+    pub cgso: f64,
+    /// Gate-drain overlap capacitance per metre of channel width (F/m)
+    pub cgdo: f64,
+    /// Gate-bulk overlap capacitance per metre of channel length (F/m)
+    pub cgbo: f64,
+    /// Oxide thickness (m), zero if not given (in which case there is
+    /// no Meyer capacitance)
+    pub tox: f64,
+    /// Surface mobility (cm^2/V/s)
+    pub uo: f64,
+    /// Lateral diffusion (m)
+    pub ld: f64,
+    /// Mobility modulation (1/V, level 3 only)
+    pub theta: f64,
+    /// Static feedback (level 3 only)
+    pub eta: f64,
+    /// Saturation field factor (level 3 only)
+    pub kappa: f64,
+    /// Maximum drift velocity of carriers (m/s), zero for no velocity
+    /// saturation (level 3 only)
+    pub vmax: f64,
+    /// Substrate doping (1/cm^3), needed for kappa (level 3 only)
+    pub nsub: f64,
+    /// Flicker noise coefficient (which needs tox)
+    pub kf: f64,
+    /// Flicker noise exponent
+    pub af: f64,
+}
+
+impl MosfetModel {
+    /// Model with the SPICE default parameters
+    pub fn new(polarity: Polarity) -> Self {
+	Self {
+	    polarity,
+	    level: 1,
+	    vto: 0.0,
+	    kp: None,
+	    gamma: 0.0,
+	    phi: 0.6,
+	    lambda: 0.0,
+	    rd: 0.0,
+	    rs: 0.0,
+	    is: 1e-14,
+	    cbd: 0.0,
+	    cbs: 0.0,
+	    pb: 0.8,
+	    mj: 0.5,
+	    fc: 0.5,
+	    cgso: 0.0,
+	    cgdo: 0.0,
+	    cgbo: 0.0,
+	    tox: 0.0,
+	    uo: 600.0,
+	    ld: 0.0,
+	    theta: 0.0,
+	    eta: 0.0,
+	    kappa: 0.2,
+	    vmax: 0.0,
+	    nsub: 0.0,
+	    kf: 0.0,
+	    af: 1.0,
+	}
+    }
+
+    /// Set a parameter by its SPICE name, returning false if the name
+    /// is not a MOSFET parameter
+    pub fn set_param(&mut self, name: &str, value: f64) -> bool {
+	match name {
+	    "level" => self.level = value as u32,
+	    "vto" | "vt0" => self.vto = value,
+	    "kp" => self.kp = Some(value),
+	    "gamma" => self.gamma = value,
+	    "phi" => self.phi = value,
+	    "lambda" => self.lambda = value,
+	    "rd" => self.rd = value,
+	    "rs" => self.rs = value,
+	    "is" => self.is = value,
+	    "cbd" => self.cbd = value,
+	    "cbs" => self.cbs = value,
+	    "pb" => self.pb = value,
+	    "mj" => self.mj = value,
+	    "fc" => self.fc = value,
+	    "cgso" => self.cgso = value,
+	    "cgdo" => self.cgdo = value,
+	    "cgbo" => self.cgbo = value,
+	    "tox" => self.tox = value,
+	    "uo" | "u0" => self.uo = value,
+	    "ld" => self.ld = value,
+	    "theta" => self.theta = value,
+	    "eta" => self.eta = value,
+	    "kappa" => self.kappa = value,
+	    "vmax" => self.vmax = value,
+	    "nsub" => self.nsub = value,
+	    "kf" => self.kf = value,
+	    "af" => self.af = value,
+	    _ => return false,
+	}
+	true
+    }
+
+    /// Every parameter that [set_param](Self::set_param) accepts, by
+    /// its SPICE name, with its value (other than the level)
+    pub fn params(&self) -> Vec<(&'static str, f64)> {
+	vec![
+	    ("vto", self.vto),
+	    ("kp", self.kp()),
+	    ("gamma", self.gamma),
+	    ("phi", self.phi),
+	    ("lambda", self.lambda),
+	    ("rd", self.rd),
+	    ("rs", self.rs),
+	    ("is", self.is),
+	    ("cbd", self.cbd),
+	    ("cbs", self.cbs),
+	    ("pb", self.pb),
+	    ("mj", self.mj),
+	    ("fc", self.fc),
+	    ("cgso", self.cgso),
+	    ("cgdo", self.cgdo),
+	    ("cgbo", self.cgbo),
+	    ("tox", self.tox),
+	    ("uo", self.uo),
+	    ("ld", self.ld),
+	    ("theta", self.theta),
+	    ("eta", self.eta),
+	    ("kappa", self.kappa),
+	    ("vmax", self.vmax),
+	    ("nsub", self.nsub),
+	    ("kf", self.kf),
+	    ("af", self.af),
+	]
+    }
+
+    /// Oxide capacitance per unit area (F/m^2), zero if tox is not
+    /// given
+    fn cox(&self) -> f64 {
+	if self.tox > 0.0 {
+	    EPSILON_OX / self.tox
+	} else {
+	    0.0
+	}
+    }
+
+    /// Transconductance parameter, from uo and tox if kp is not given
+    fn kp(&self) -> f64 {
+	match self.kp {
+	    Some(kp) => kp,
+	    None if self.tox > 0.0 => self.uo * 1e-4 * self.cox(),
+	    None => 2e-5,
+	}
+    }
+}
+
+/// Drain current and its derivatives with respect to vgs, vds and vbs,
+/// along with the threshold and saturation voltages
+#[derive(Debug, Clone, Copy, Default)]
+struct DrainCurrent {
+    ids: f64,
+    gm: f64,
+    gds: f64,
+    gmbs: f64,
+    vth: f64,
+    vdsat: f64,
+}
+
+/// Derivatives of the stamps of a MOSFET with respect to one of its
+/// values
+#[derive(Debug, Clone, Copy, Default)]
+struct Slopes {
+    /// Drain and source conductances
+    conductances: [f64; 2],
+    /// Drain current, and so each of its derivatives, relative to its
+    /// value
+    drain_current: f64,
+    /// Saturation current of the bulk junctions
+    is: f64,
+    /// Capacitances, in the order of [Mosfet::capacitances]
+    capacitances: [f64; 5],
+}
+
+/// sqrt(phi - vbs) and its derivative with respect to vbs, continued
+/// linearly (down to zero) for a forward-biased source junction
+fn body_factor(phi: f64, vbs: f64) -> (f64, f64) {
+    let sphi = phi.sqrt();
+    if vbs <= 0.0 {
+	let sarg = (phi - vbs).sqrt();
+	(sarg, -0.5 / sarg)
+    } else {
+	let sarg = sphi - vbs / (2.0 * sphi);
+	if sarg > 0.0 {
+	    (sarg, -0.5 / sphi)
+	} else {
+	    (0.0, 0.0)
+	}
+    }
+}
+
+/// Intrinsic Meyer gate-source, gate-drain and gate-bulk capacitances
+/// of a device with total gate oxide capacitance cox, where vgst is
+/// the gate voltage above threshold
+fn meyer_capacitances(cox: f64, phi: f64, vgst: f64, vds: f64, vdsat: f64) -> [f64; 3] {
+    if vgst <= -phi {
+	// Accumulation
+	[0.0, 0.0, cox]
+    } else if vgst <= -phi / 2.0 {
+	[0.0, 0.0, -vgst * cox / phi]
+    } else if vgst <= 0.0 {
+	// Depletion
+	let cgs = 2.0 / 3.0 * cox + 4.0 / 3.0 * cox * vgst / phi;
+	[cgs, 0.0, -vgst * cox / phi]
+    } else if vds >= vdsat {
+	// Saturation
+	[2.0 / 3.0 * cox, 0.0, 0.0]
+    } else {
+	let vddif = 2.0 * vdsat - vds;
+	let cgs = 2.0 / 3.0 * cox * (1.0 - ((vdsat - vds) / vddif).powi(2));
+	let cgd = 2.0 / 3.0 * cox * (1.0 - (vdsat / vddif).powi(2));
+	[cgs, cgd, 0.0]
+    }
+}
+
+pub struct Mosfet {
+    name: String,
+    /// Drain, gate, source and bulk terminals
+    terminals: [usize; 4],
+    /// Drain and source sides of the channel (the same as the
+    /// terminals where there is no drain or source resistance)
+    internal: [usize; 2],
+    model: MosfetModel,
+    /// Channel width
+    w: f64,
+    /// Effective channel length
+    leff: f64,
+    /// kp * w / leff, at the device temperature
+    beta: f64,
+    /// Total gate oxide capacitance
+    cgate: f64,
+    /// Thermal voltage
+    vt: f64,
+    /// Device temperature (K)
+    temperature: f64,
+    /// Voltages that the device is linearised about (for an n-channel
+    /// device)
+    vgs: f64,
+    vds: f64,
+    vbs: f64,
+    /// Charge histories of the gate-source, gate-drain, gate-bulk,
+    /// bulk-drain and bulk-source capacitances
+    states: [StateHistory; 5],
+    /// Voltage across and capacitance of each Meyer capacitance at the
+    /// last accepted timepoint
+    meyer_points: [(f64, f64); 3],
+}
+
+impl Mosfet {
+    /// New MOSFET with channel width w and drawn length l. The internal
+    /// nodes are only used where the model has a drain or source
+    /// resistance, and should otherwise be the terminals.
+    pub fn new(
+	name: &str,
+	terminals: [usize; 4],
+	internal: [usize; 2],
+	model: MosfetModel,
+	w: f64,
+	l: f64,
+    ) -> Self {
+	let leff = l - 2.0 * model.ld;
+	Self {
+	    name: String::from(name),
+	    terminals,
+	    internal,
+	    w,
+	    leff,
+	    beta: model.kp() * w / leff,
+	    cgate: model.cox() * w * leff,
+	    model,
+	    vt: thermal_voltage(NOMINAL_TEMPERATURE),
+	    temperature: NOMINAL_TEMPERATURE,
+	    vgs: 0.0,
+	    vds: 0.0,
+	    vbs: 0.0,
+	    states: Default::default(),
+	    meyer_points: [(0.0, 0.0); 3],
+	}
+    }
+
+    /// Gate-source, drain-source and bulk-source voltages in the
+    /// solution x (negated for a p-channel device)
+    fn voltages(&self, x: &Unknowns) -> (f64, f64, f64) {
+	let [_, g, _, b] = self.terminals;
+	let [d, s] = self.internal;
+	let sign = self.model.polarity.sign();
+	(
+	    sign * (x.v(g) - x.v(s)),
+	    sign * (x.v(d) - x.v(s)),
+	    sign * (x.v(b) - x.v(s)),
+	)
+    }
+
+    /// Threshold voltage at bulk-source voltage vbs, ignoring any
+    /// dependence on vds
+    fn threshold(&self, vbs: f64) -> f64 {
+	let m = &self.model;
+	let (sarg, _) = body_factor(m.phi, vbs);
+	m.vto + m.gamma * (sarg - m.phi.sqrt())
+    }
+
+    /// Whether the drain is below the source (so that they swap over),
+    /// and the gate, drain and bulk voltages relative to whichever is
+    /// the source
+    fn forward(vgs: f64, vds: f64, vbs: f64) -> (bool, [f64; 3]) {
+	if vds >= 0.0 {
+	    (false, [vgs, vds, vbs])
+	} else {
+	    (true, [vgs - vds, -vds, vbs - vds])
+	}
+    }
+
+    fn drain_current(&self, vgs: f64, vds: f64, vbs: f64) -> DrainCurrent {
+	match self.model.level {
+	    3 => self.level3(vgs, vds, vbs),
+	    _ => self.level1(vgs, vds, vbs),
+	}
+    }
+
+    fn level1(&self, vgs: f64, vds: f64, vbs: f64) -> DrainCurrent {
+	let m = &self.model;
+	let (sarg, dsarg) = body_factor(m.phi, vbs);
+	let vth = m.vto + m.gamma * (sarg - m.phi.sqrt());
+	let vgst = vgs - vth;
+	if vgst <= 0.0 {
+	    return DrainCurrent {
+		vth,
+		..DrainCurrent::default()
+	    };
+	}
+	let beta = self.beta;
+	let clm = 1.0 + m.lambda * vds;
+	let (ids, gm, gds) = if vgst <= vds {
+	    (
+		beta / 2.0 * vgst * vgst * clm,
+		beta * vgst * clm,
+		beta / 2.0 * vgst * vgst * m.lambda,
+	    )
+	} else {
+	    (
+		beta * vds * (vgst - vds / 2.0) * clm,
+		beta * vds * clm,
+		beta * (vgst - vds) * clm + beta * m.lambda * vds * (vgst - vds / 2.0),
+	    )
+	};
+	DrainCurrent {
+	    ids,
+	    gm,
+	    gds,
+	    gmbs: -gm * m.gamma * dsarg,
+	    vth,
+	    vdsat: vgst,
+	}
+    }
+
+    /// Level 3 drain current, threshold voltage and saturation voltage
+    fn level3_current(&self, vgs: f64, vds: f64, vbs: f64) -> (f64, f64, f64) {
+	let m = &self.model;
+	let (sarg, _) = body_factor(m.phi, vbs);
+	let cox = m.cox();
+	let sigma = if cox > 0.0 {
+	    m.eta * 8.15e-22 / (cox * self.leff.powi(3))
+	} else {
+	    0.0
+	};
+	let vth = m.vto + m.gamma * (sarg - m.phi.sqrt()) - sigma * vds;
+	let vgst = vgs - vth;
+	if vgst <= 0.0 {
+	    return (0.0, vth, 0.0);
+	}
+
+	// Charge in the depletion layer under the channel
+	let fb = if sarg > 0.0 { m.gamma / (4.0 * sarg) } else { 0.0 };
+	let mobility = 1.0 / (1.0 + m.theta * vgst);
+	let beta = self.beta * mobility;
+	let mut vdsat = vgst / (1.0 + fb);
+	let vc = if m.vmax > 0.0 {
+	    m.vmax * self.leff / (m.uo * 1e-4 * mobility)
+	} else {
+	    f64::INFINITY
+	};
+	if vc.is_finite() {
+	    // Maximum of the velocity limited current
+	    vdsat = (vc * vc + 2.0 * vc * vdsat).sqrt() - vc;
+	}
+	let vde = vds.min(vdsat);
+	let mut ids = beta * (vgst - (1.0 + fb) / 2.0 * vde) * vde / (1.0 + vde / vc);
+
+	// Channel shortening beyond saturation
+	if vds > vdsat && m.nsub > 0.0 {
+	    let xd = (2.0 * EPSILON_SI / (CHARGE * m.nsub * 1e6)).sqrt();
+	    let delta_l = (xd * (m.kappa * (vds - vdsat)).sqrt()).min(self.leff / 2.0);
+	    ids /= 1.0 - delta_l / self.leff;
+	}
+	(ids, vth, vdsat)
+    }
+
+    /// Level 3 drain current, differentiated numerically
+    fn level3(&self, vgs: f64, vds: f64, vbs: f64) -> DrainCurrent {
+	let (ids, vth, vdsat) = self.level3_current(vgs, vds, vbs);
+	let slope = |f: &dyn Fn(f64) -> f64| (f(DELTA_V) - f(-DELTA_V)) / (2.0 * DELTA_V);
+	DrainCurrent {
+	    ids,
+	    gm: slope(&|dv| self.level3_current(vgs + dv, vds, vbs).0),
+	    gds: slope(&|dv| self.level3_current(vgs, vds + dv, vbs).0),
+	    gmbs: slope(&|dv| self.level3_current(vgs, vds, vbs + dv).0),
+	    vth,
+	    vdsat,
+	}
+    }
+
+    /// Gate-source, gate-drain and gate-bulk capacitances, including
+    /// the overlap capacitances
+    fn gate_capacitances(&self, vgs: f64, vds: f64, vbs: f64) -> [f64; 3] {
+	let m = &self.model;
+	let (reversed, [vgs_f, vds_f, vbs_f]) = Self::forward(vgs, vds, vbs);
+	let id = self.drain_current(vgs_f, vds_f, vbs_f);
+	let [cgs, cgd, cgb] = meyer_capacitances(self.cgate, m.phi, vgs_f - id.vth, vds_f, id.vdsat);
+	let (cgs, cgd) = if reversed { (cgd, cgs) } else { (cgs, cgd) };
+	[
+	    cgs + m.cgso * self.w,
+	    cgd + m.cgdo * self.w,
+	    cgb + m.cgbo * self.leff,
+	]
+    }
+
+    /// Voltage across and capacitance of each Meyer capacitance
+    fn meyer_points(&self, vgs: f64, vds: f64, vbs: f64) -> [(f64, f64); 3] {
+	let [cgs, cgd, cgb] = self.gate_capacitances(vgs, vds, vbs);
+	[(vgs, cgs), (vgs - vds, cgd), (vgs - vbs, cgb)]
+    }
+
+    /// Each capacitance, as the nodes it connects, and its incremental
+    /// capacitance at vgs, vds and vbs, for the small-signal model
+    fn capacitances(&self, vgs: f64, vds: f64, vbs: f64) -> [(usize, usize, f64); 5] {
+	let m = &self.model;
+	let [_, g, _, b] = self.terminals;
+	let [d, s] = self.internal;
+	let [cgs, cgd, cgb] = self.gate_capacitances(vgs, vds, vbs);
+	let (_, cbd) = depletion_charge(m.cbd, m.pb, m.mj, m.fc, vbs - vds);
+	let (_, cbs) = depletion_charge(m.cbs, m.pb, m.mj, m.fc, vbs);
+	[(g, s, cgs), (g, d, cgd), (g, b, cgb), (b, d, cbd), (b, s, cbs)]
+    }
+
+    /// Each capacitance, as the nodes it connects, the voltage across
+    /// it (for an n-channel device), and its charge and capacitance at
+    /// that voltage, for a transient step. The Meyer capacitances are
+    /// given as capacitances rather than charges, so their charge is
+    /// built up from the last accepted timepoint using the average of
+    /// the capacitance there and at vgs, vds and vbs.
+    fn charges(&self, vgs: f64, vds: f64, vbs: f64) -> [(usize, usize, f64, f64, f64); 5] {
+	let m = &self.model;
+	let [_, g, _, b] = self.terminals;
+	let [d, s] = self.internal;
+	let vgd = vgs - vds;
+	let vgb = vgs - vbs;
+	let vbd = vbs - vds;
+	let caps = self.gate_capacitances(vgs, vds, vbs);
+	let meyer = |k: usize, v: f64| {
+	    let (v_prev, c_prev) = self.meyer_points[k];
+	    let c = (c_prev + caps[k]) / 2.0;
+	    (self.states[k].q() + c * (v - v_prev), c)
+	};
+	let (qgs, cgs) = meyer(0, vgs);
+	let (qgd, cgd) = meyer(1, vgd);
+	let (qgb, cgb) = meyer(2, vgb);
+	let (qbd, cbd) = depletion_charge(m.cbd, m.pb, m.mj, m.fc, vbd);
+	let (qbs, cbs) = depletion_charge(m.cbs, m.pb, m.mj, m.fc, vbs);
+	[
+	    (g, s, vgs, qgs, cgs),
+	    (g, d, vgd, qgd, cgd),
+	    (g, b, vgb, qgb, cgb),
+	    (b, d, vbd, qbd, cbd),
+	    (b, s, vbs, qbs, cbs),
+	]
+    }
+
+    /// Derivatives of the stamps about vgs, vds and vbs with respect
+    /// to one of the values named by [params](Device::params)
+    fn slopes(&self, param: &str) -> Slopes {
+	let m = &self.model;
+	let mut slopes = Slopes::default();
+	match param {
+	    "w" => {
+		slopes.drain_current = 1.0 / self.w;
+		// The Meyer capacitances and the gate-source and gate-drain
+		// overlaps scale with w, but the gate-bulk overlap does not
+		let [cgs, cgd, cgb] = self.gate_capacitances(self.vgs, self.vds, self.vbs);
+		let cgb = cgb - m.cgbo * self.leff;
+		slopes.capacitances = [cgs / self.w, cgd / self.w, cgb / self.w, 0.0, 0.0];
+	    }
+	    "kp" => slopes.drain_current = 1.0 / m.kp(),
+	    "rd" if m.rd > 0.0 => slopes.conductances[0] = -1.0 / (m.rd * m.rd),
+	    "rs" if m.rs > 0.0 => slopes.conductances[1] = -1.0 / (m.rs * m.rs),
+	    "is" => slopes.is = 1.0,
+	    "cbd" => {
+		let (_, c) = depletion_charge(1.0, m.pb, m.mj, m.fc, self.vbs - self.vds);
+		slopes.capacitances[3] = c;
+	    }
+	    "cbs" => {
+		let (_, c) = depletion_charge(1.0, m.pb, m.mj, m.fc, self.vbs);
+		slopes.capacitances[4] = c;
+	    }
+	    "cgso" => slopes.capacitances[0] = self.w,
+	    "cgdo" => slopes.capacitances[1] = self.w,
+	    "cgbo" => slopes.capacitances[2] = self.leff,
+	    _ => (),
+	}
+	slopes
+    }
+
+    /// Stamp the linearisation of the device about vgs, vds and vbs,
+    /// with the given drain and source conductances, the drain current
+    /// scaled by scale, and the current and conductance of a bulk
+    /// junction at a voltage given by junction. With the derivatives
+    /// of each, this stamps the derivative of the linearisation.
+    fn stamp_linearised(
+	&self,
+	mna: &mut Mna<f64>,
+	conductances: [f64; 2],
+	scale: f64,
+	junction: impl Fn(f64) -> (f64, f64),
+    ) -> Result<(), AcdcError> {
+	let sign = self.model.polarity.sign();
+	let (reversed, [vgs, vds, vbs]) = Self::forward(self.vgs, self.vds, self.vbs);
+	let id = self.drain_current(vgs, vds, vbs);
+	self.stamp_channel(
+	    mna,
+	    conductances,
+	    reversed,
+	    [scale * id.gds, scale * id.gm, scale * id.gmbs],
+	    sign * scale * (id.ids - id.gm * vgs - id.gds * vds - id.gmbs * vbs),
+	)?;
+
+	// Bulk junctions
+	let [_, _, _, b] = self.terminals;
+	let [d, s] = self.internal;
+	for (t, v) in [(d, self.vbs - self.vds), (s, self.vbs)] {
+	    let (i, g) = junction(v);
+	    add_branch(mna, b, t, g, sign * (i - g * v))?;
+	}
+	Ok(())
+    }
+
+    /// Stamp the small-signal model of the device at radial frequency
+    /// omega, as in [stamp_linearised](Self::stamp_linearised), with
+    /// the given capacitances (in the order of
+    /// [capacitances](Self::capacitances))
+    fn stamp_small_signal(
+	&self,
+	mna: &mut Mna<Complex<f64>>,
+	conductances: [f64; 2],
+	scale: f64,
+	junction: impl Fn(f64) -> (f64, f64),
+	capacitances: [f64; 5],
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	let (reversed, [vgs, vds, vbs]) = Self::forward(self.vgs, self.vds, self.vbs);
+	let id = self.drain_current(vgs, vds, vbs);
+	let zero = Complex::new(0.0, 0.0);
+	self.stamp_channel(
+	    mna,
+	    conductances.map(|g| Complex::new(g, 0.0)),
+	    reversed,
+	    [
+		Complex::new(scale * id.gds, 0.0),
+		Complex::new(scale * id.gm, 0.0),
+		Complex::new(scale * id.gmbs, 0.0),
+	    ],
+	    zero,
+	)?;
+
+	let [_, _, _, b] = self.terminals;
+	let [d, s] = self.internal;
+	for (t, v) in [(d, self.vbs - self.vds), (s, self.vbs)] {
+	    let (_, g) = junction(v);
+	    add_branch(mna, b, t, Complex::new(g, 0.0), zero)?;
+	}
+	let branches = self.capacitances(self.vgs, self.vds, self.vbs);
+	for ((t1, t2, _), c) in branches.into_iter().zip(capacitances) {
+	    add_branch(mna, t1, t2, Complex::new(0.0, omega * c), zero)?;
+	}
+	Ok(())
+    }
+
+    /// Stamp the drain and source resistances, with the given
+    /// conductances, and the channel with output conductance gds and
+    /// transconductances gm and gmbs, with the given current when
+    /// every voltage is zero. The channel runs from the drain to the
+    /// source, or the other way if reversed.
+    fn stamp_channel<P>(
+	&self,
+	mna: &mut Mna<P>,
+	[g_d, g_s]: [P; 2],
+	reversed: bool,
+	[gds, gm, gmbs]: [P; 3],
+	current: P,
+    ) -> Result<(), AcdcError>
+    where
+	P: csuperlu::c::value_type::ValueType + std::ops::Neg<Output = P>,
+    {
+	let [drain, g, source, b] = self.terminals;
+	let [d, s] = self.internal;
+	add_branch(mna, drain, d, g_d, P::zero())?;
+	add_branch(mna, source, s, g_s, P::zero())?;
+
+	let (d, s) = if reversed { (s, d) } else { (d, s) };
+	if d == s {
+	    return Ok(());
+	}
+	mna.add_norton(d, s, None, gds, current)?;
+	if g != s {
+	    mna.add_voltage_controlled_current_source(d, s, g, s, None, gm)?;
+	}
+	if b != s {
+	    mna.add_voltage_controlled_current_source(d, s, b, s, None, gmbs)?;
+	}
+	Ok(())
+    }
+}
+
+impl Device for Mosfet {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn is_nonlinear(&self) -> bool {
+	true
+    }
+
+    /// Scales the transconductance parameter, as the mobility falls
+    /// with temperature to the power -1.5, and the thermal voltage
+    fn set_temperature(&mut self, temperature: f64) {
+	self.temperature = temperature;
+	let mobility = (temperature / NOMINAL_TEMPERATURE).powf(-1.5);
+	self.beta = self.model.kp() * self.w / self.leff * mobility;
+	self.vt = thermal_voltage(temperature);
+    }
+
+    /// The channel width, and the model parameters that the drain
+    /// current, the drain and source resistances, the bulk junction
+    /// currents and the capacitances scale with
+    fn params(&self) -> Vec<(&'static str, f64)> {
+	let m = &self.model;
+	vec![
+	    ("w", self.w),
+	    ("kp", m.kp()),
+	    ("rd", m.rd),
+	    ("rs", m.rs),
+	    ("is", m.is),
+	    ("cbd", m.cbd),
+	    ("cbs", m.cbs),
+	    ("cgso", m.cgso),
+	    ("cgdo", m.cgdo),
+	    ("cgbo", m.cgbo),
+	]
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+	let mut l = self.leff + 2.0 * self.model.ld;
+	match name {
+	    "w" => self.w = value,
+	    "l" => l = value,
+	    name => {
+		if !self.model.set_param(name, value) {
+		    return false;
+		}
+	    }
+	}
+	self.leff = l - 2.0 * self.model.ld;
+	self.cgate = self.model.cox() * self.w * self.leff;
+	self.set_temperature(self.temperature);
+	true
+    }
+
+    fn limit(&mut self, x: &Unknowns) -> bool {
+	let (vgs, vds, vbs) = self.voltages(x);
+	let von = self.threshold(self.vbs);
+	let vcrit = vcrit(self.model.is, self.vt);
+	let (vgs, vds, vbs, limited) = if self.vds >= 0.0 {
+	    let (vgs, limited_gs) = fetlim(vgs, self.vgs, von);
+	    let (vds, limited_ds) = limvds(vds, self.vds);
+	    let (vbs, limited_bs) = pnjlim(vbs, self.vbs, self.vt, vcrit);
+	    (vgs, vds, vbs, limited_gs || limited_ds || limited_bs)
+	} else {
+	    // Limit relative to the drain, which is acting as the source
+	    let (vgd, limited_gd) = fetlim(vgs - vds, self.vgs - self.vds, von);
+	    let (vsd, limited_sd) = limvds(-vds, -self.vds);
+	    let (vbd, limited_bd) = pnjlim(vbs - vds, self.vbs - self.vds, self.vt, vcrit);
+	    (vgd - vsd, -vsd, vbd - vsd, limited_gd || limited_sd || limited_bd)
+	};
+	self.vgs = vgs;
+	self.vds = vds;
+	self.vbs = vbs;
+	limited
+    }
+
+    fn stamp_dc(&self, mna: &mut Mna<f64>, _x: &Unknowns) -> Result<(), AcdcError> {
+	let m = &self.model;
+	self.stamp_linearised(mna, [1.0 / m.rd, 1.0 / m.rs], 1.0, |v| {
+	    junction_current(m.is, self.vt, f64::INFINITY, v)
+	})
+    }
+
+    fn stamp_ac(&self, mna: &mut Mna<Complex<f64>>, omega: f64) -> Result<(), AcdcError> {
+	let m = &self.model;
+	let capacitances = self.capacitances(self.vgs, self.vds, self.vbs);
+	self.stamp_small_signal(
+	    mna,
+	    [1.0 / m.rd, 1.0 / m.rs],
+	    1.0,
+	    |v| junction_current(m.is, self.vt, f64::INFINITY, v),
+	    capacitances.map(|(_, _, c)| c),
+	    omega,
+	)
+    }
+
+    fn stamp_dc_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<f64>,
+	_x: &Unknowns,
+    ) -> Result<(), AcdcError> {
+	let slopes = self.slopes(param);
+	self.stamp_linearised(mna, slopes.conductances, slopes.drain_current, |v| {
+	    let (di, dg) = junction_current_slope(self.vt, f64::INFINITY, v);
+	    (slopes.is * di, slopes.is * dg)
+	})
+    }
+
+    fn stamp_ac_derivative(
+	&self,
+	param: &str,
+	mna: &mut Mna<Complex<f64>>,
+	omega: f64,
+    ) -> Result<(), AcdcError> {
+	let slopes = self.slopes(param);
+	self.stamp_small_signal(
+	    mna,
+	    slopes.conductances,
+	    slopes.drain_current,
+	    |v| {
+		let (di, dg) = junction_current_slope(self.vt, f64::INFINITY, v);
+		(slopes.is * di, slopes.is * dg)
+	    },
+	    slopes.capacitances,
+	    omega,
+	)
+    }
+
+    /// Thermal noise of the channel and of the drain and source
+    /// resistances, and flicker noise of the drain current
+    fn noise_sources(&self, frequency: f64, temperature: f64) -> Vec<NoiseSource> {
+	let [drain, _, source, _] = self.terminals;
+	let [d, s] = self.internal;
+	let m = &self.model;
+	let mut sources = Vec::new();
+	if drain != d {
+	    sources.push(NoiseSource::new("rd", drain, d, thermal_noise(m.rd, temperature)));
+	}
+	if source != s {
+	    sources.push(NoiseSource::new("rs", source, s, thermal_noise(m.rs, temperature)));
+	}
+
+	let (_, [vgs, vds, vbs]) = Self::forward(self.vgs, self.vds, self.vbs);
+	let id = self.drain_current(vgs, vds, vbs);
+	let channel = 8.0 * BOLTZMANN * temperature * id.gm / 3.0;
+	sources.push(NoiseSource::new("id", d, s, channel));
+	let cox = m.cox();
+	if cox > 0.0 {
+	    let density = flicker_noise(m.kf, m.af, id.ids, frequency) / (cox * self.leff * self.leff);
+	    sources.push(NoiseSource::new("1overf", d, s, density));
+	}
+	sources
+    }
+
+    fn stamp_transient(
+	&self,
+	mna: &mut Mna<f64>,
+	x: &Unknowns,
+	step: &TimeStep,
+    ) -> Result<(), AcdcError> {
+	self.stamp_dc(mna, x)?;
+	// Charging current of each capacitance, linearised about the
+	// present voltages
+	let sign = self.model.polarity.sign();
+	let charges = self.charges(self.vgs, self.vds, self.vbs);
+	for ((t1, t2, v, q, c), state) in charges.into_iter().zip(self.states.iter()) {
+	    let (a0, history) = step.integrate(state);
+	    let geq = a0 * c;
+	    add_branch(mna, t1, t2, geq, sign * (a0 * q + history - geq * v))?;
+	}
+	Ok(())
+    }
+
+    fn initialise_transient(&mut self, x: &Unknowns) {
+	let (vgs, vds, vbs) = self.voltages(x);
+	self.vgs = vgs;
+	self.vds = vds;
+	self.vbs = vbs;
+	self.meyer_points = self.meyer_points(vgs, vds, vbs);
+	let charges = self.charges(vgs, vds, vbs);
+	for (k, (_, _, _, q, _)) in charges.into_iter().enumerate() {
+	    // Only differences in the Meyer charges matter, so they start
+	    // from zero
+	    let q = if k < 3 { 0.0 } else { q };
+	    self.states[k] = StateHistory::new(q, 0.0);
+	}
+    }
+
+    fn accept_transient(&mut self, x: &Unknowns, step: &TimeStep) {
+	let (vgs, vds, vbs) = self.voltages(x);
+	let charges = self.charges(vgs, vds, vbs);
+	for ((_, _, _, q, _), state) in charges.into_iter().zip(self.states.iter_mut()) {
+	    let (a0, history) = step.integrate(state);
+	    state.accept(q, a0 * q + history);
+	}
+	self.meyer_points = self.meyer_points(vgs, vds, vbs);
+    }
+}
@@ -0,0 +1,59 @@
+//! Error type
+//!
+//! All fallible operations in this crate (building a system,
+//! solving it, or reading a netlist) return an [AcdcError].
+
+use std::fmt;
+
+use crate::netlist::ParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AcdcError {
+    /// The modified nodal analysis matrix could not be factorised
+    /// (for example, because a node is floating)
+    SingularMatrix,
+    /// A matrix or vector does not have the size required
+    DimensionMismatch { expected: usize, found: usize },
+    /// The circuit cannot be built as described (for example, an
+    /// element connected between a node and itself)
+    InvalidTopology(String),
+    /// The parameters of an analysis are not valid (for example, a
+    /// logarithmic sweep starting at 0 Hz)
+    InvalidAnalysis(String),
+    /// Newton-Raphson iteration did not converge within the
+    /// iteration limit
+    NoConvergence { iterations: usize },
+    /// A transient analysis needed a timestep shorter than the
+    /// minimum to meet its error tolerances
+    TimestepTooSmall { time: f64, step: f64 },
+    /// The netlist could not be read
+    Parse(ParseError),
+}
+
+impl fmt::Display for AcdcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    AcdcError::SingularMatrix => write!(f, "singular matrix"),
+	    AcdcError::DimensionMismatch { expected, found } => {
+		write!(f, "dimension mismatch: expected {}, found {}", expected, found)
+	    }
+	    AcdcError::InvalidTopology(message) => write!(f, "invalid topology: {}", message),
+	    AcdcError::InvalidAnalysis(message) => write!(f, "invalid analysis: {}", message),
+	    AcdcError::NoConvergence { iterations } => {
+		write!(f, "no convergence after {} iterations", iterations)
+	    }
+	    AcdcError::TimestepTooSmall { time, step } => {
+		write!(f, "timestep too small at time {}: {}", time, step)
+	    }
+	    AcdcError::Parse(error) => write!(f, "parse error: {}", error),
+	}
+    }
+}
+
+impl std::error::Error for AcdcError {}
+
+impl From<ParseError> for AcdcError {
+    fn from(error: ParseError) -> Self {
+	AcdcError::Parse(error)
+    }
+}
@@ -3,3 +3,13 @@ pub mod dc;
 pub mod ac;
 pub mod sparse;
 pub mod node_map;
+pub mod netlist;
+pub mod error;
+pub mod circuit;
+pub mod device;
+pub mod transient;
+pub mod waveform;
+pub mod solution;
+pub mod noise;
+pub mod sens;
+pub mod newton;
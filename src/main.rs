@@ -1,22 +1,173 @@
-use dc::LinearDcAnalysis;
+use libacdc::{
+    ac::{sweep_frequencies, SweepType},
+    dc::{DcSolution, LinearDcAnalysis},
+    error::AcdcError,
+    netlist::{Analysis, Netlist},
+};
 
+fn run(args: &[String]) -> Result<(), AcdcError> {
 
-mod mna;
-mod dc;
-mod ac;
-mod sparse;
-mod node_map;
+    let solution = if args.len() == 2 {
+	// Read the circuit from the netlist file
+	let netlist = Netlist::from_file(&args[1])?;
+	for warning in netlist.warnings() {
+	    eprintln!("Warning: {}", warning);
+	}
+	if !netlist.analyses().is_empty() {
+	    for analysis in netlist.analyses() {
+		match analysis {
+		    Analysis::Op => print_solution(&netlist.nonlinear_dc_analysis()?.solve()?),
+		    Analysis::Tran { .. } => run_transient(&netlist)?,
+		    Analysis::Ac { .. } => run_ac(&netlist)?,
+		    Analysis::Dc { .. } => run_dc(&netlist)?,
+		    Analysis::Noise { .. } => run_noise(&netlist)?,
+		    Analysis::Tf { .. } => run_tf(&netlist)?,
+		    Analysis::Sens { ac, .. } => run_sens(&netlist, *ac)?,
+		}
+	    }
+	    return Ok(());
+	}
+	netlist.nonlinear_dc_analysis()?.solve()?
+    } else {
+	let mut dc = LinearDcAnalysis::new();
 
-fn main() {
+	// Voltage divider
+	dc.add_resistor("r1", "vcc", "v_out", false, 4.7)?;
+	dc.add_resistor("r2", "v_out", "gnd", false, 4.7)?;
+	dc.add_independent_voltage_source("v1", "vcc", "gnd", 5.0)?;
+	dc.solve()?
+    };
 
-    let mut dc = LinearDcAnalysis::new();
+    print_solution(&solution);
+    Ok(())
+}
 
-    // Voltage divider
-    dc.add_resistor("vcc", "v_out", None, 4.7);
-    dc.add_resistor("v_out", "gnd", None, 4.7);
-    dc.add_independent_voltage_source("vcc", "gnd", "v1", 5.0);
+/// Print the voltage at every node and the current in every edge of
+/// an operating point
+fn print_solution(solution: &DcSolution) {
+    for (name, v) in solution.voltages() {
+	println!("V({}) = {}", name, v);
+    }
+    for (name, i) in solution.currents() {
+	println!("I({}) = {}", name, i);
+    }
+}
+
+/// Run the transient analysis in the netlist and print the
+/// waveform at every node
+fn run_transient(netlist: &Netlist) -> Result<(), AcdcError> {
+    let waveforms = netlist.transient_analysis()?.solve()?;
+    let node_map = waveforms.node_map();
+    println!("Time: {:?}", waveforms.time());
+    for n in 1..node_map.num_nodes() {
+	let name = node_map.node_name(n);
+	println!("V({}): {:?}", name, waveforms.v(name).unwrap());
+    }
+    Ok(())
+}
+
+/// Run the AC sweep in the netlist and print the magnitude of
+/// the voltage at every node
+fn run_ac(netlist: &Netlist) -> Result<(), AcdcError> {
+    let solution = netlist.ac_sweep()?.solve()?;
+    println!("Frequency: {:?}", solution.frequencies());
+    for (name, v) in solution.voltages().skip(1) {
+	let magnitude: Vec<f64> = v.iter().map(|v| v.norm()).collect();
+	println!("|V({})|: {:?}", name, magnitude);
+    }
+    Ok(())
+}
+
+/// Run the DC sweep in the netlist and print the voltage at every
+/// node, for each value of the outer variable of a nested sweep
+fn run_dc(netlist: &Netlist) -> Result<(), AcdcError> {
+    for curve in netlist.dc_sweep()?.solve()? {
+	if let Some((name, value)) = curve.outer() {
+	    println!("{} = {}", name, value);
+	}
+	println!("{}: {:?}", curve.variable(), curve.values());
+	let node_map = curve.node_map();
+	for n in 1..node_map.num_nodes() {
+	    let name = node_map.node_name(n);
+	    println!("V({}): {:?}", name, curve.v(name).unwrap());
+	}
+    }
+    Ok(())
+}
 
-    let (voltages, currents) = dc.solve();
-    println!("Voltages: {:?}", voltages);
-    println!("Currents: {:?}", currents);
+/// Run the noise analysis in the netlist and print the output and
+/// input-referred noise densities, then the noise over the whole
+/// sweep, in total and from each element
+fn run_noise(netlist: &Netlist) -> Result<(), AcdcError> {
+    let noise = netlist.noise_analysis()?.solve()?;
+    let root = |density: &[f64]| -> Vec<f64> { density.iter().map(|d| d.sqrt()).collect() };
+    let f = noise.frequencies();
+    println!("Frequency: {:?}", f);
+    println!("Output noise (/sqrt(Hz)): {:?}", root(noise.output_density()));
+    println!("Input noise (/sqrt(Hz)): {:?}", root(&noise.input_density()));
+
+    let f_low = f.first().copied().unwrap_or(0.0);
+    let f_high = f.last().copied().unwrap_or(0.0);
+    println!("Total output noise: {}", noise.integrated_output(f_low, f_high));
+    println!("Total input noise: {}", noise.integrated_input(f_low, f_high));
+    let mut elements: Vec<&str> = Vec::new();
+    for contribution in noise.contributions() {
+	if !elements.contains(&contribution.element.as_str()) {
+	    elements.push(&contribution.element);
+	}
+    }
+    for element in elements {
+	let total = noise.integrated_element(element, f_low, f_high).unwrap_or(0.0);
+	println!("Output noise from {}: {}", element, total);
+    }
+    Ok(())
+}
+
+/// Find the transfer function in the netlist and print it
+fn run_tf(netlist: &Netlist) -> Result<(), AcdcError> {
+    let tf = netlist.transfer_function()?;
+    println!("Transfer function: {}", tf.gain);
+    println!("Input resistance: {}", tf.input_resistance);
+    println!("Output resistance: {}", tf.output_resistance);
+    Ok(())
+}
+
+/// Run the sensitivity analysis in the netlist and print the
+/// derivative of the output with respect to every value, absolute and
+/// relative, at the operating point or at each frequency
+fn run_sens(
+    netlist: &Netlist,
+    ac: Option<(SweepType, usize, f64, f64)>,
+) -> Result<(), AcdcError> {
+    let mut sens = netlist.sensitivity_analysis()?;
+    match ac {
+	None => {
+	    let solution = sens.solve()?;
+	    println!("Output: {}", solution.output());
+	    for s in solution.sensitivities() {
+		println!(
+		    "{}:{} = {}: {} ({} %/%)",
+		    s.element, s.param, s.value, s.absolute, s.relative
+		);
+	    }
+	}
+	Some((sweep, num_points, f_start, f_end)) => {
+	    let f = sweep_frequencies(sweep, num_points, f_start, f_end)?;
+	    let solution = sens.solve_ac(&f)?;
+	    println!("Frequency: {:?}", solution.frequencies());
+	    println!("Output: {:?}", solution.output());
+	    for s in solution.sensitivities() {
+		println!("{}:{} = {}: {:?}", s.element, s.param, s.value, s.absolute);
+	    }
+	}
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Err(error) = run(&args) {
+	eprintln!("Error: {}", error);
+	std::process::exit(1);
+    }
 }
@@ -2,13 +2,25 @@ use csuperlu::c::value_type::ValueType;
 
 use std::ops;
 
-use crate::sparse::solve;
+use crate::{
+    error::AcdcError,
+    sparse::{solve, solve_many, transpose},
+};
 
 use self::{mna_matrix::MnaMatrix, mna_rhs::MnaRhs};
 
 mod mna_matrix;
 mod mna_rhs;
 
+/// Output of a system, for the adjoint solve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// Voltage between two nodes, V(pos) - V(neg)
+    Voltage(usize, usize),
+    /// Current in an edge
+    Current(usize),
+}
+
 pub struct Mna<P: ValueType + ops::Neg<Output=P>> {
     matrix: MnaMatrix<P>,
     rhs: MnaRhs<P>,
@@ -28,7 +40,7 @@ impl<P: ValueType + ops::Neg<Output=P>> Mna<P> {
 	term_2: usize,
 	current_edge: Option<usize>,
 	impedance: P,
-    ) {
+    ) -> Result<(), AcdcError> {
 	let z = impedance;
         match current_edge {
             Some(e) => self
@@ -37,7 +49,7 @@ impl<P: ValueType + ops::Neg<Output=P>> Mna<P> {
             None => self
                 .matrix
                 .add_symmetric_group1(term_1, term_2, P::one() / z, -P::one() / z),
-        };
+        }
     }
 
     pub fn add_independent_voltage_source(
@@ -46,7 +58,7 @@ impl<P: ValueType + ops::Neg<Output=P>> Mna<P> {
 	term_neg: usize,
 	current_edge: usize,
 	voltage: P,
-    ) {
+    ) -> Result<(), AcdcError> {
 	let v = voltage;
         self.matrix.add_symmetric_group2(
             term_pos,
@@ -55,94 +67,488 @@ impl<P: ValueType + ops::Neg<Output=P>> Mna<P> {
             P::one(),
             -P::one(),
             P::zero(),
-        );
+        )?;
         self.rhs.add_rhs_group2(current_edge, v);
+	Ok(())
+    }
+
+    /// Add a current source driving current from term_pos to term_neg
+    /// through the source (i.e. out of term_pos).
+    ///
+    /// If current_edge is given, the source is stamped in group 2, and
+    /// its current appears in the solution. Otherwise, it only
+    /// contributes to the right-hand side.
+    pub fn add_independent_current_source(
+	&mut self,
+	term_pos: usize,
+	term_neg: usize,
+	current_edge: Option<usize>,
+	current: P,
+    ) -> Result<(), AcdcError> {
+	let i = current;
+	match current_edge {
+	    Some(e) => {
+		self.matrix.add_unsymmetric_right_group2(
+		    term_pos,
+		    term_neg,
+		    e,
+		    P::one(),
+		    -P::one(),
+		    P::one(),
+		)?;
+		self.rhs.add_rhs_group2(e, i);
+	    }
+	    None => {
+		if term_pos == term_neg {
+		    return Err(AcdcError::InvalidTopology(format!(
+			"element connected between node {} and itself",
+			term_pos
+		    )));
+		}
+		self.matrix.update_num_voltage_nodes(term_pos);
+		self.matrix.update_num_voltage_nodes(term_neg);
+		self.rhs.add_rhs_group1(term_pos, -i);
+		self.rhs.add_rhs_group1(term_neg, i);
+	    }
+	}
+	Ok(())
+    }
+
+    /// Add a branch whose current (from term_1 to term_2) is
+    /// admittance * (V(term_1) - V(term_2)) + current. This is an
+    /// admittance in parallel with a current source (a Norton
+    /// equivalent), as used by companion models.
+    pub fn add_norton(
+	&mut self,
+	term_1: usize,
+	term_2: usize,
+	current_edge: Option<usize>,
+	admittance: P,
+	current: P,
+    ) -> Result<(), AcdcError> {
+	let y = admittance;
+	match current_edge {
+	    Some(e) => self.add_thevenin(term_1, term_2, e, P::one() / y, -current / y),
+	    None => {
+		self.matrix.add_symmetric_group1(term_1, term_2, y, -y)?;
+		self.rhs.add_rhs_group1(term_1, -current);
+		self.rhs.add_rhs_group1(term_2, current);
+		Ok(())
+	    }
+	}
+    }
+
+    /// Add a branch whose voltage (V(term_1) - V(term_2)) is
+    /// impedance * I + voltage, where I is the current in current_edge.
+    /// This is an impedance in series with a voltage source (a Thevenin
+    /// equivalent), as used by companion models.
+    pub fn add_thevenin(
+	&mut self,
+	term_1: usize,
+	term_2: usize,
+	current_edge: usize,
+	impedance: P,
+	voltage: P,
+    ) -> Result<(), AcdcError> {
+	let z = impedance;
+	self.matrix.add_symmetric_group2(
+	    term_1,
+	    term_2,
+	    current_edge,
+	    P::one(),
+	    -P::one(),
+	    -z,
+	)?;
+	self.rhs.add_rhs_group2(current_edge, voltage);
+	Ok(())
+    }
+
+    /// Couple two group 2 branches (such as inductors) through a mutual
+    /// impedance. The voltage of the branch in edge_1 gains
+    /// mutual_impedance * I(edge_2) + voltage_1, and the voltage of the
+    /// branch in edge_2 gains mutual_impedance * I(edge_1) + voltage_2.
+    /// The voltages are used by companion models.
+    pub fn add_mutual_impedance(
+	&mut self,
+	edge_1: usize,
+	edge_2: usize,
+	mutual_impedance: P,
+	voltage_1: P,
+	voltage_2: P,
+    ) -> Result<(), AcdcError> {
+	if edge_1 == edge_2 {
+	    return Err(AcdcError::InvalidTopology(format!(
+		"edge {} coupled to itself",
+		edge_1
+	    )));
+	}
+	let z = mutual_impedance;
+	self.matrix.add_group2_value(edge_1, edge_2, -z);
+	self.matrix.add_group2_value(edge_2, edge_1, -z);
+	self.rhs.add_rhs_group2(edge_1, voltage_1);
+	self.rhs.add_rhs_group2(edge_2, voltage_2);
+	Ok(())
+    }
+
+    /// Add an ideal transformer with turns ratio n = (primary turns) /
+    /// (secondary turns), so that V(pri_pos) - V(pri_neg) = n *
+    /// (V(sec_pos) - V(sec_neg)). The primary current, into pri_pos, is
+    /// in current_edge, and n times it flows out of sec_pos.
+    pub fn add_ideal_transformer(
+	&mut self,
+	pri_pos: usize,
+	pri_neg: usize,
+	sec_pos: usize,
+	sec_neg: usize,
+	current_edge: usize,
+	ratio: P,
+    ) -> Result<(), AcdcError> {
+	let n = ratio;
+	self.matrix.add_symmetric_group2(
+	    pri_pos,
+	    pri_neg,
+	    current_edge,
+	    P::one(),
+	    -P::one(),
+	    P::zero(),
+	)?;
+	self.matrix.add_unsymmetric_bottom_group2(
+	    sec_pos,
+	    sec_neg,
+	    current_edge,
+	    -n,
+	    n,
+	    P::zero(),
+	)?;
+	self.matrix.add_unsymmetric_right_group2(
+	    sec_pos,
+	    sec_neg,
+	    current_edge,
+	    -n,
+	    n,
+	    P::zero(),
+	)
+    }
+
+    /// Add an ideal operational amplifier, with infinite gain, as a
+    /// nullor: a nullator holding V(in_pos) = V(in_neg) (with no input
+    /// current), and a norator from ground to out supplying whatever
+    /// current that needs. The current out of the output is in
+    /// current_edge.
+    pub fn add_ideal_op_amp(
+	&mut self,
+	in_pos: usize,
+	in_neg: usize,
+	out: usize,
+	current_edge: usize,
+    ) -> Result<(), AcdcError> {
+	self.matrix.add_unsymmetric_right_group2(
+	    out,
+	    0,
+	    current_edge,
+	    -P::one(),
+	    P::one(),
+	    P::zero(),
+	)?;
+	self.matrix.add_unsymmetric_bottom_group2(
+	    in_pos,
+	    in_neg,
+	    current_edge,
+	    P::one(),
+	    -P::one(),
+	    P::zero(),
+	)
+    }
+
+    /// Add a voltage source whose voltage is gain * (V(ctrl_pos) - V(ctrl_neg))
+    pub fn add_voltage_controlled_voltage_source(
+	&mut self,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_pos: usize,
+	ctrl_neg: usize,
+	current_edge: usize,
+	gain: P,
+    ) -> Result<(), AcdcError> {
+	let k = gain;
+	self.matrix.add_symmetric_group2(
+	    term_pos,
+	    term_neg,
+	    current_edge,
+	    P::one(),
+	    -P::one(),
+	    P::zero(),
+	)?;
+	self.matrix.add_unsymmetric_bottom_group2(
+	    ctrl_pos,
+	    ctrl_neg,
+	    current_edge,
+	    -k,
+	    k,
+	    P::zero(),
+	)
+    }
+
+    /// Add a voltage source whose voltage is transresistance * I(ctrl_edge).
+    /// The controlling current must be a group 2 edge current.
+    pub fn add_current_controlled_voltage_source(
+	&mut self,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_edge: usize,
+	current_edge: usize,
+	transresistance: P,
+    ) -> Result<(), AcdcError> {
+	let r = transresistance;
+	self.matrix.add_symmetric_group2(
+	    term_pos,
+	    term_neg,
+	    current_edge,
+	    P::one(),
+	    -P::one(),
+	    P::zero(),
+	)?;
+	self.matrix.add_group2_value(current_edge, ctrl_edge, -r);
+	Ok(())
     }
-    
-    /* Unclean!
-    pub fn add_element_stamp(&mut self, component: &Component) {
-        match component {
-            Component::VoltageControlledVoltageSource {
-                term_pos,
-                term_neg,
+
+    /// Add a current source driving transconductance * (V(ctrl_pos) - V(ctrl_neg))
+    /// out of term_pos, through the source, into term_neg. If current_edge is
+    /// given, the source current is in group 2.
+    pub fn add_voltage_controlled_current_source(
+	&mut self,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_pos: usize,
+	ctrl_neg: usize,
+	current_edge: Option<usize>,
+	transconductance: P,
+    ) -> Result<(), AcdcError> {
+	let g = transconductance;
+	match current_edge {
+	    Some(e) => {
+		self.matrix.add_unsymmetric_right_group2(
+		    term_pos,
+		    term_neg,
+		    e,
+		    P::one(),
+		    -P::one(),
+		    P::one(),
+		)?;
+		self.matrix.add_unsymmetric_bottom_group2(
+		    ctrl_pos,
+		    ctrl_neg,
+		    e,
+		    -g,
+		    g,
+		    P::zero(),
+		)
+	    }
+	    None => self.matrix.add_unsymmetric_group1(
+		term_pos,
+		term_neg,
 		ctrl_pos,
 		ctrl_neg,
-                current_index,
-                voltage_scale: k,
-            } => {
-                self.matrix.add_symmetric_group2(
-                    *term_pos,
-                    *term_neg,
-                    *current_index,
-                    1.0,
-                    -1.0,
-                    0.0,
-                );
-		self.matrix.add_unsymmetric_bottom_group2(
-		    *ctrl_pos,
-		    *ctrl_neg,
-		    *current_index,
-		    -*k,
-		    *k,
-		    0.0,
-		);
-            },
-            Component::CurrentControlledVoltageSource {
-                term_pos,
-                term_neg,
+		g,
+		-g,
+	    ),
+	}
+    }
+
+    /// Add a current source driving gain * I(ctrl_edge) out of term_pos,
+    /// through the source, into term_neg. The controlling current must be
+    /// a group 2 edge current. If current_edge is given, the source current
+    /// is also in group 2.
+    pub fn add_current_controlled_current_source(
+	&mut self,
+	term_pos: usize,
+	term_neg: usize,
+	ctrl_edge: usize,
+	current_edge: Option<usize>,
+	gain: P,
+    ) -> Result<(), AcdcError> {
+	let f = gain;
+	match current_edge {
+	    Some(e) => {
+		self.matrix.add_unsymmetric_right_group2(
+		    term_pos,
+		    term_neg,
+		    e,
+		    P::one(),
+		    -P::one(),
+		    P::one(),
+		)?;
+		self.matrix.add_group2_value(e, ctrl_edge, -f);
+		Ok(())
+	    }
+	    None => self.matrix.add_unsymmetric_right_group2(
+		term_pos,
+		term_neg,
 		ctrl_edge,
-                current_index,
-                voltage_scale: k,
-            } => {
-                self.matrix.add_symmetric_group2(
-                    *term_pos,
-                    *term_neg,
-                    *current_index,
-                    1.0,
-                    -1.0,
-                    0.0,
-                );
-		self.matrix.add_group2_value(*current_index, *ctrl_edge, -*k);
-            },
-	    Component::IndependentCurrentSource {
-                term_pos,
-                term_neg,
-                current_index,
-                current: i
-            } => {
-                match current_index {
-                    Some(edge) => {
-			self.matrix.add_unsymmetric_right_group2(
-			    *term_pos, *term_neg, *edge,
-			    1.0, -1.0, 1.0);
-			self.rhs.add_rhs_group2(*edge, *i);
-		    },
-                    None => {
-			self.rhs.add_rhs_group1(*term_pos, -*i);
-			self.rhs.add_rhs_group1(*term_neg, *i);
-		    }
-                }
-            },
-            _ => todo!("Not currently implemented"),
-        }
+		f,
+		-f,
+		P::zero(),
+	    ),
+	}
+    }
+
+    /// Add gain * (V(ctrl_pos) - V(ctrl_neg)) to the voltage of the
+    /// branch in current_edge, without adding the branch itself. This
+    /// is the part of a stamp that depends on a gain (such as that of
+    /// a voltage-controlled voltage source), for stamping its
+    /// derivative.
+    pub fn add_branch_voltage_term(
+	&mut self,
+	current_edge: usize,
+	ctrl_pos: usize,
+	ctrl_neg: usize,
+	gain: P,
+    ) -> Result<(), AcdcError> {
+	let k = gain;
+	self.matrix.add_unsymmetric_bottom_group2(
+	    ctrl_pos,
+	    ctrl_neg,
+	    current_edge,
+	    -k,
+	    k,
+	    P::zero(),
+	)
+    }
+
+    /// Add impedance * I(ctrl_edge) to the voltage of the branch in
+    /// current_edge, without adding the branch itself. This is the
+    /// part of a stamp that depends on an impedance or a
+    /// transresistance, for stamping its derivative.
+    pub fn add_branch_current_term(&mut self, current_edge: usize, ctrl_edge: usize, impedance: P) {
+	self.matrix.add_group2_value(current_edge, ctrl_edge, -impedance);
+    }
+
+    /// Add to the right-hand side of the branch in current_edge (the
+    /// voltage of a voltage source, or the current of a current source
+    /// in group 2), without adding the branch itself
+    pub fn add_branch_rhs(&mut self, current_edge: usize, value: P) {
+	self.matrix.update_num_current_edges(current_edge);
+	self.rhs.add_rhs_group2(current_edge, value);
     }
-     */
 
-    /// Returns node voltages, edge currents
-    pub fn solve(self) -> (Vec<P>, Vec<P>) {
+    /// Returns node voltages, edge currents. The system is left
+    /// unchanged, so it can be solved again after adding to it.
+    pub fn solve(&self) -> Result<(Vec<P>, Vec<P>), AcdcError> {
         let num_voltage_nodes = self.matrix.num_voltage_nodes();
         let num_current_edges = self.matrix.num_current_edges();
         let matrix = self.matrix.get_matrix();
-
-	matrix.print_structure(num_voltage_nodes);
-	
 	let rhs = self.rhs.get_vector(num_voltage_nodes, num_current_edges);
 
-	let mut solution = solve(matrix, rhs);
+	let mut solution = solve(matrix, rhs)?;
 	let currents: Vec<_> = solution
 	    .drain(num_voltage_nodes..)
 	    .collect();
 	// Solution now contains the voltages
-	(solution, currents)
+	Ok((solution, currents))
+    }
+
+    /// Right-hand side b of the system, as node (excluding ground) and
+    /// edge values
+    pub fn rhs(&self) -> (Vec<P>, Vec<P>) {
+        let num_voltage_nodes = self.matrix.num_voltage_nodes();
+        let num_current_edges = self.matrix.num_current_edges();
+	let mut nodes = self.rhs.get_vector(num_voltage_nodes, num_current_edges);
+	let edges = nodes.split_off(num_voltage_nodes);
+	(nodes, edges)
+    }
+
+    /// Residual A x - b of the system at the node voltages (excluding
+    /// ground) and edge currents of x, as node and edge values.
+    /// Unknowns missing from x are taken to be zero, and those outside
+    /// the system are ignored. The system is left unchanged.
+    pub fn residual(&self, (voltages, currents): (&[P], &[P])) -> Result<(Vec<P>, Vec<P>), AcdcError>
+    where
+	P: ops::Mul<Output = P>,
+    {
+        let num_voltage_nodes = self.matrix.num_voltage_nodes();
+        let num_current_edges = self.matrix.num_current_edges();
+        let matrix = self.matrix.get_matrix();
+	let x = |k: usize| {
+	    let value = if k < num_voltage_nodes {
+		voltages.get(k)
+	    } else {
+		currents.get(k - num_voltage_nodes)
+	    };
+	    value.copied().unwrap_or_else(P::zero)
+	};
+
+	let mut residual: Vec<P> = self
+	    .rhs
+	    .get_vector(num_voltage_nodes, num_current_edges)
+	    .into_iter()
+	    .map(|b| -b)
+	    .collect();
+	for ((row, col), value) in matrix.non_zero_vals().iter() {
+	    residual[*row] = residual[*row] + *value * x(*col);
+	}
+	let edges: Vec<_> = residual
+	    .drain(num_voltage_nodes..)
+	    .collect();
+	Ok((residual, edges))
+    }
+
+    /// Solve the adjoint (transposed) system for the output V(out_pos)
+    /// - V(out_neg). Returns node values (excluding ground) and edge
+    /// values y, such that a unit current injected into node n1 and
+    /// drawn from node n2 changes the output by y(n1) - y(n2), and a
+    /// unit change in the right-hand side of edge e changes it by
+    /// y(e). The system is left unchanged.
+    pub fn solve_adjoint(&self, out_pos: usize, out_neg: usize) -> Result<(Vec<P>, Vec<P>), AcdcError> {
+	let mut solutions = self.solve_adjoint_probes(&[Probe::Voltage(out_pos, out_neg)])?;
+	Ok(solutions.pop().unwrap())
+    }
+
+    /// Solve the adjoint system, as in [solve_adjoint](Self::solve_adjoint),
+    /// for each of several outputs, using one factorization of the
+    /// matrix. The system is left unchanged.
+    pub fn solve_adjoint_probes(&self, probes: &[Probe]) -> Result<Vec<(Vec<P>, Vec<P>)>, AcdcError> {
+        let num_voltage_nodes = self.matrix.num_voltage_nodes();
+        let num_current_edges = self.matrix.num_current_edges();
+        let matrix = transpose(&self.matrix.get_matrix());
+	let mut rhs = Vec::new();
+	for probe in probes {
+	    let mut b = vec![P::zero(); num_voltage_nodes + num_current_edges];
+	    match *probe {
+		Probe::Voltage(out_pos, out_neg) => {
+		    for (n, x) in [(out_pos, P::one()), (out_neg, -P::one())] {
+			if n > num_voltage_nodes {
+			    return Err(AcdcError::InvalidTopology(format!(
+				"output node {} is not part of the system",
+				n
+			    )));
+			}
+			if n != 0 {
+			    b[n - 1] = b[n - 1] + x;
+			}
+		    }
+		}
+		Probe::Current(e) => {
+		    if e >= num_current_edges {
+			return Err(AcdcError::InvalidTopology(format!(
+			    "output edge {} is not part of the system",
+			    e
+			)));
+		    }
+		    b[num_voltage_nodes + e] = P::one();
+		}
+	    }
+	    rhs.push(b);
+	}
+
+	let solutions = solve_many(matrix, rhs)?;
+	Ok(solutions
+	   .into_iter()
+	   .map(|mut solution| {
+	       let edges: Vec<_> = solution
+		   .drain(num_voltage_nodes..)
+		   .collect();
+	       (solution, edges)
+	   })
+	   .collect())
     }
 }
@@ -1,6 +1,6 @@
 use std::cmp;
 use csuperlu::{sparse_matrix::SparseMat, c::value_type::ValueType};
-use crate::sparse::{plus_equals, concat_horizontal, concat_vertical};
+use crate::{error::AcdcError, sparse::plus_equals};
 
 /// Matrix for modified nodal analysis
 ///
@@ -46,32 +46,38 @@ impl<P: ValueType> MnaMatrix<P> {
         self.num_current_edges
     }
 
-    pub fn get_matrix(mut self) -> SparseMat<P> {
-        self.top_left
-            .resize(self.num_voltage_nodes, self.num_voltage_nodes);
-        self.bottom_right
-            .resize(self.num_current_edges, self.num_current_edges);
-        self.top_right
-            .resize(self.num_voltage_nodes, self.num_current_edges);
-        self.bottom_left
-            .resize(self.num_current_edges, self.num_voltage_nodes);
-
-        let top = concat_horizontal(self.top_left, &self.top_right);
-        let bottom = concat_horizontal(self.bottom_left, &self.bottom_right);
-        concat_vertical(top, &bottom)
+    /// Assemble the four blocks into the full matrix, leaving them
+    /// unchanged so that the system can be solved again
+    pub fn get_matrix(&self) -> SparseMat<P> {
+        let n = self.num_voltage_nodes;
+        let m = self.num_current_edges;
+        let blocks = [
+            (&self.top_left, 0, 0),
+            (&self.top_right, 0, n),
+            (&self.bottom_left, n, 0),
+            (&self.bottom_right, n, n),
+        ];
+        let mut matrix = SparseMat::empty();
+        for (block, row_offset, col_offset) in blocks {
+            for ((row, col), value) in block.non_zero_vals().iter() {
+                matrix.insert_unbounded(row_offset + *row, col_offset + *col, *value);
+            }
+        }
+        matrix.resize(n + m, n + m);
+        matrix
     }
 
     /// Increase the number of voltage nodes if n is not already included. Note
     /// that this function uses the netlist value of n (i.e. the matrix index is
     /// n-1).
-    fn update_num_voltage_nodes(&mut self, n: usize) {
+    pub fn update_num_voltage_nodes(&mut self, n: usize) {
         self.num_voltage_nodes = cmp::max(self.num_voltage_nodes, n);
     }
 
     /// Increase the number of current edges if e is not already included. Note that
     /// e is the actual index into the matrix, so the number of rows will be resized
     /// to e+1
-    fn update_num_current_edges(&mut self, e: usize) {
+    pub fn update_num_current_edges(&mut self, e: usize) {
         self.num_current_edges = cmp::max(self.num_current_edges, e + 1);
     }
 
@@ -84,9 +90,12 @@ impl<P: ValueType> MnaMatrix<P> {
     /// be negative are not written.
     ///
     /// This matrix block is added to the current matrix in the top left of the MNA matrix.
-    pub fn add_symmetric_group1(&mut self, n1: usize, n2: usize, x1: P, x2: P) {
+    pub fn add_symmetric_group1(&mut self, n1: usize, n2: usize, x1: P, x2: P) -> Result<(), AcdcError> {
         if n1 == n2 {
-            panic!("Cannot set symmetric group 1 where n1 == n2");
+            return Err(AcdcError::InvalidTopology(format!(
+                "element connected between node {} and itself",
+                n1
+            )));
         }
         self.update_num_voltage_nodes(n1);
         self.update_num_voltage_nodes(n2);
@@ -100,6 +109,46 @@ impl<P: ValueType> MnaMatrix<P> {
             plus_equals(&mut self.top_left, n1 - 1, n2 - 1, x2);
             plus_equals(&mut self.top_left, n2 - 1, n1 - 1, x2);
         }
+        Ok(())
+    }
+
+    /// Add an unsymmetric block of values to the top-left matrix.
+    ///
+    /// The value $x_1$ is added to $(n_1-1, c_1-1)$ and $(n_2-1, c_2-1)$, and
+    /// $x_2$ is added to $(n_1-1, c_2-1)$ and $(n_2-1, c_1-1)$. With $x_2 = -x_1$,
+    /// this is the stamp for a current out of $n_1$ and into $n_2$ controlled
+    /// by the voltage between $c_1$ and $c_2$. Entries involving the ground node
+    /// are not written.
+    pub fn add_unsymmetric_group1(
+        &mut self,
+        n1: usize,
+        n2: usize,
+        c1: usize,
+        c2: usize,
+        x1: P,
+        x2: P,
+    ) -> Result<(), AcdcError> {
+        if n1 == n2 {
+            return Err(AcdcError::InvalidTopology(format!(
+                "element connected between node {} and itself",
+                n1
+            )));
+        }
+        if c1 == c2 {
+            return Err(AcdcError::InvalidTopology(format!(
+                "element controlled by node {} relative to itself",
+                c1
+            )));
+        }
+        for n in [n1, n2, c1, c2] {
+            self.update_num_voltage_nodes(n);
+        }
+        for (row, col, value) in [(n1, c1, x1), (n2, c2, x1), (n1, c2, x2), (n2, c1, x2)] {
+            if row != 0 && col != 0 {
+                plus_equals(&mut self.top_left, row - 1, col - 1, value);
+            }
+        }
+        Ok(())
     }
 
     /// Add a symmetric component into the off-diagonal blocks and bottom-left matrix
@@ -118,9 +167,12 @@ impl<P: ValueType> MnaMatrix<P> {
         x1: P,
         x2: P,
         y: P,
-    ) {
+    ) -> Result<(), AcdcError> {
         if n1 == n2 {
-            panic!("Cannot set symmetric group 2 where n1 == n2");
+            return Err(AcdcError::InvalidTopology(format!(
+                "element connected between node {} and itself",
+                n1
+            )));
         }
         self.update_num_voltage_nodes(n1);
         self.update_num_voltage_nodes(n2);
@@ -134,9 +186,9 @@ impl<P: ValueType> MnaMatrix<P> {
             plus_equals(&mut self.top_right, n2 - 1, e, x2);
             plus_equals(&mut self.bottom_left, e, n2 - 1, x2);
         }
+        Ok(())
     }
 
-    /*
     /// Same as symmetric version, but only adds values to the
     /// right-hand portion of the matrix (top and bottom)
     pub fn add_unsymmetric_right_group2(
@@ -144,12 +196,15 @@ impl<P: ValueType> MnaMatrix<P> {
         n1: usize,
         n2: usize,
         e: usize,
-        x1: f64,
-        x2: f64,
-        y: f64,
-    ) {
+        x1: P,
+        x2: P,
+        y: P,
+    ) -> Result<(), AcdcError> {
         if n1 == n2 {
-            panic!("Cannot set unsymmetric group (right) 2 where n1 == n2");
+            return Err(AcdcError::InvalidTopology(format!(
+                "element connected between node {} and itself",
+                n1
+            )));
         }
         self.update_num_voltage_nodes(n1);
         self.update_num_voltage_nodes(n2);
@@ -161,10 +216,9 @@ impl<P: ValueType> MnaMatrix<P> {
         if n2 != 0 {
             plus_equals(&mut self.top_right, n2 - 1, e, x2);
         }
+        Ok(())
     }
-     */
 
-    /*
     /// Same as symmetric version, but only adds values to the
     /// bottom portion of the matrix (left and right)
     pub fn add_unsymmetric_bottom_group2(
@@ -172,12 +226,15 @@ impl<P: ValueType> MnaMatrix<P> {
         n1: usize,
         n2: usize,
         e: usize,
-        x1: f64,
-        x2: f64,
-        y: f64,
-    ) {
+        x1: P,
+        x2: P,
+        y: P,
+    ) -> Result<(), AcdcError> {
         if n1 == n2 {
-            panic!("Cannot set unsymmetric group (bottom) 2 where n1 == n2");
+            return Err(AcdcError::InvalidTopology(format!(
+                "element controlled by node {} relative to itself",
+                n1
+            )));
         }
         self.update_num_voltage_nodes(n1);
         self.update_num_voltage_nodes(n2);
@@ -189,21 +246,19 @@ impl<P: ValueType> MnaMatrix<P> {
         if n2 != 0 {
             plus_equals(&mut self.bottom_left, e, n2 - 1, x2);
         }
+        Ok(())
     }
-     */
 
-    /*
     /// Add a single value in the group2 (current-current, bottom-right) portion
     /// of the matrix
     pub fn add_group2_value(
         &mut self,
         e1: usize,
         e2: usize,
-        y: f64,
+        y: P,
     ) {
         self.update_num_current_edges(e1);
         self.update_num_current_edges(e2);
         plus_equals(&mut self.bottom_right, e1, e2, y);
     }
-     */
 }
@@ -1,4 +1,5 @@
 use csuperlu::{sparse_matrix::SparseMat, c::value_type::ValueType};
+use crate::sparse::plus_equals;
 
 /// Modified nodal analysis right-hand side
 ///
@@ -21,7 +22,7 @@ impl<P: ValueType> MnaRhs<P> {
         }
     }
 
-    pub fn get_vector(self, num_voltage_nodes: usize, num_current_edges: usize) -> Vec<P> {
+    pub fn get_vector(&self, num_voltage_nodes: usize, num_current_edges: usize) -> Vec<P> {
         let mut out = vec![P::zero(); num_voltage_nodes + num_current_edges];
         for ((row, _), value) in self.top.non_zero_vals().iter() {
             out[*row] = *value;
@@ -32,17 +33,16 @@ impl<P: ValueType> MnaRhs<P> {
         out
     }
 
-    /*
-    /// Add a RHS element in the group 1 matrix.
-    pub fn add_rhs_group1(&mut self, n: usize, x: f64) {
+    /// Add a RHS element in the group 1 matrix. Nothing is
+    /// written if n is the ground node.
+    pub fn add_rhs_group1(&mut self, n: usize, x: P) {
 	if n != 0 {
-            self.top.insert_unbounded(n - 1, 1, x);
+            plus_equals(&mut self.top, n - 1, 1, x);
 	}
     }
-     */
     
     /// Add a RHS element in the group 2 matrix
     pub fn add_rhs_group2(&mut self, e: usize, x: P) {
-        self.bottom.insert_unbounded(e, 1, x);
+        plus_equals(&mut self.bottom, e, 1, x);
     }
 }
@@ -0,0 +1,2351 @@
+//! Netlist parsing
+//!
+//! Reads a SPICE-compatible netlist deck into a typed list of
+//! circuit elements, which can then be used to build the analyses
+//! in this crate.
+//!
+//! Each line of the deck is either an element line, a comment, or a
+//! control line. Lines starting with `*` or `#` are comments, and
+//! lines starting with `+` continue the previous line. Parsing stops
+//! at `.end`. Element lines have the form
+//!
+//! ```text
+//! R1 n1 n2 1000 [G2]
+//! C1 n1 n2 1e-9 [G2]
+//! L1 n1 n2 1e-6
+//! V1 n+ n- [[DC] 5] [AC 1 [0]] [PULSE(0 5 1n 1n 1n 10n 20n)]
+//! I1 n+ n- [[DC] 1e-3] [AC 1 [0]] [SIN(0 1m 1k)] [G2]
+//! E1 n+ n- nc+ nc- 10
+//! H1 n+ n- V1 1000
+//! G1 n+ n- nc+ nc- 1e-3 [G2]
+//! F1 n+ n- V1 10 [G2]
+//! D1 anode cathode model [area]
+//! Q1 collector base emitter model [area]
+//! K1 L1 L2 0.99
+//! M1 drain gate source bulk model [L=length] [W=width]
+//! X1 n1 n2 subckt [name=value ...]
+//! ```
+//!
+//! where the optional trailing `G2` places the element current in
+//! group 2, so that it appears in the solution under the element
+//! name. Elements whose current controls another element, and
+//! inductors, are always in group 2. Sources take a DC value, an AC
+//! magnitude and phase (in degrees), and a transient function (see
+//! [waveform](crate::waveform), or `PWL FILE name` to read the
+//! points from a file). A `K` element couples two inductors
+//! with coupling coefficient k. Element names are case-insensitive;
+//! node names are used exactly as written. Values may use engineering
+//! notation (see [value]), and `{expression}` is replaced by its value
+//! (see [expr]).
+//!
+//! The supported control lines are
+//!
+//! ```text
+//! .op
+//! .ac dec|oct|lin N fstart fstop
+//! .dc var start stop step [var2 start2 stop2 step2]
+//! .noise V(out[,ref]) src dec|oct|lin N fstart fstop
+//! .tf V(out[,ref])|I(element) src
+//! .sens V(out[,ref])|I(element) [ac dec|oct|lin N fstart fstop]
+//! .tran tstep tstop [tstart [tmax]] [UIC]
+//! .ic V(n1)=1 V(n2)=0
+//! .model name type (param=value ...)
+//! .subckt name ports... [params: name=value ...] / .ends
+//! .param name=value ...
+//! .options reltol=1e-3 abstol=1p vntol=1u trtol=7 method=gear fixedstep
+//! ```
+//!
+//! The analyses are made by the method of [Netlist] for each control
+//! line (for example [Netlist::ac_sweep] for `.ac`), which describes
+//! its arguments. `.model`, `.subckt` (see [subckt]), `.param` (see
+//! [expr]) and `.options` lines set up the circuit and the analyses.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    sync::OnceLock,
+};
+
+use regex::{Captures, Regex};
+
+use crate::{
+    ac::{sweep_frequencies, LinearAcCircuit, LinearAcSweep, SmallSignalAcSweep, SweepType},
+    circuit::Circuit,
+    dc::{
+	DcSweep, LinearDcAnalysis, NonlinearDcAnalysis, SweepRange, SweepVariable,
+	TransferFunction,
+    },
+    device::{bjt::BjtModel, diode::DiodeModel, mosfet::MosfetModel, Polarity},
+    error::AcdcError,
+    newton::NewtonOptions,
+    noise::NoiseAnalysis,
+    sens::SensitivityAnalysis,
+    solution::OutputVariable,
+    transient::{IntegrationMethod, TransientAnalysis, TransientOptions},
+    waveform::{SourceWaveform, TransientFunction},
+};
+
+use self::{
+    expr::{evaluate_params, Expr},
+    subckt::{Scope, Subcircuit},
+    value::parse_value,
+};
+
+pub mod expr;
+pub mod subckt;
+pub mod value;
+
+/// Error encountered while reading a netlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Line in the deck where the error occurred (starting at 1)
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, message: String) -> Self {
+	Self {
+	    line: Some(line),
+	    message,
+	}
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self.line {
+	    Some(line) => write!(f, "line {}: {}", line, self.message),
+	    None => write!(f, "{}", self.message),
+	}
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Diagnostic for a netlist line that parsed, but looks suspicious
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Circuit element read from a netlist
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    /// Resistor (group 1 or group 2)
+    Resistor {
+	name: String,
+	term_1: String,
+	term_2: String,
+	resistance: f64,
+	group2: bool,
+    },
+    /// Capacitor (group 1 or group 2)
+    Capacitor {
+	name: String,
+	term_1: String,
+	term_2: String,
+	capacitance: f64,
+	group2: bool,
+    },
+    /// Inductor (always group 2)
+    Inductor {
+	name: String,
+	term_1: String,
+	term_2: String,
+	inductance: f64,
+    },
+    /// Independent voltage source (always group 2)
+    VoltageSource {
+	name: String,
+	term_pos: String,
+	term_neg: String,
+	waveform: SourceWaveform,
+    },
+    /// Independent current source (group 1 or group 2)
+    CurrentSource {
+	name: String,
+	term_pos: String,
+	term_neg: String,
+	waveform: SourceWaveform,
+	group2: bool,
+    },
+    /// Voltage-controlled voltage source (always group 2)
+    Vcvs {
+	name: String,
+	term_pos: String,
+	term_neg: String,
+	ctrl_pos: String,
+	ctrl_neg: String,
+	gain: f64,
+    },
+    /// Current-controlled voltage source (always group 2), controlled
+    /// by the current through the element ctrl_element
+    Ccvs {
+	name: String,
+	term_pos: String,
+	term_neg: String,
+	ctrl_element: String,
+	transresistance: f64,
+    },
+    /// Voltage-controlled current source (group 1 or group 2)
+    Vccs {
+	name: String,
+	term_pos: String,
+	term_neg: String,
+	ctrl_pos: String,
+	ctrl_neg: String,
+	transconductance: f64,
+	group2: bool,
+    },
+    /// Current-controlled current source (group 1 or group 2),
+    /// controlled by the current through the element ctrl_element
+    Cccs {
+	name: String,
+	term_pos: String,
+	term_neg: String,
+	ctrl_element: String,
+	gain: f64,
+	group2: bool,
+    },
+    /// Junction diode using the named `.model` card, with area factor
+    Diode {
+	name: String,
+	anode: String,
+	cathode: String,
+	model: String,
+	area: f64,
+    },
+    /// Bipolar transistor using the named `.model` card, with area
+    /// factor
+    Bjt {
+	name: String,
+	collector: String,
+	base: String,
+	emitter: String,
+	model: String,
+	area: f64,
+    },
+    /// Coupling between two inductors, with coupling coefficient k
+    MutualInductance {
+	name: String,
+	inductor_1: String,
+	inductor_2: String,
+	coupling: f64,
+    },
+    /// MOSFET using the named `.model` card, with channel width and
+    /// length
+    Mosfet {
+	name: String,
+	drain: String,
+	gate: String,
+	source: String,
+	bulk: String,
+	model: String,
+	w: f64,
+	l: f64,
+    },
+}
+
+/// Device model read from a `.model` card
+#[derive(Debug, Clone, PartialEq)]
+pub enum Model {
+    Diode(DiodeModel),
+    Bjt(BjtModel),
+    Mosfet(MosfetModel),
+}
+
+impl Model {
+    /// The SPICE model type, e.g. `D`
+    pub fn kind(&self) -> &str {
+	match self {
+	    Model::Diode(_) => "D",
+	    Model::Bjt(model) => match model.polarity {
+		Polarity::N => "NPN",
+		Polarity::P => "PNP",
+	    },
+	    Model::Mosfet(model) => match model.polarity {
+		Polarity::N => "NMOS",
+		Polarity::P => "PMOS",
+	    },
+	}
+    }
+}
+
+impl Element {
+    /// The (lower case) element name, e.g. `r1`
+    pub fn name(&self) -> &str {
+	match self {
+	    Element::Resistor { name, .. } => name,
+	    Element::Capacitor { name, .. } => name,
+	    Element::Inductor { name, .. } => name,
+	    Element::VoltageSource { name, .. } => name,
+	    Element::CurrentSource { name, .. } => name,
+	    Element::Vcvs { name, .. } => name,
+	    Element::Ccvs { name, .. } => name,
+	    Element::Vccs { name, .. } => name,
+	    Element::Cccs { name, .. } => name,
+	    Element::Diode { name, .. } => name,
+	    Element::Bjt { name, .. } => name,
+	    Element::Mosfet { name, .. } => name,
+	    Element::MutualInductance { name, .. } => name,
+	}
+    }
+
+    /// Names of the elements whose current controls this one
+    /// (including the inductors a mutual inductance couples)
+    fn controlling_elements(&self) -> Vec<&str> {
+	match self {
+	    Element::Ccvs { ctrl_element, .. } => vec![ctrl_element.as_str()],
+	    Element::Cccs { ctrl_element, .. } => vec![ctrl_element.as_str()],
+	    Element::MutualInductance {
+		inductor_1,
+		inductor_2,
+		..
+	    } => vec![inductor_1.as_str(), inductor_2.as_str()],
+	    _ => Vec::new(),
+	}
+    }
+}
+
+/// Analysis requested by a control line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Analysis {
+    /// DC operating point
+    Op,
+    /// Transient analysis from time zero to stop, with suggested
+    /// timestep step, recording the solution from start. If uic is
+    /// set, the initial conditions are used instead of the operating
+    /// point.
+    Tran {
+	step: f64,
+	stop: f64,
+	start: f64,
+	max_step: Option<f64>,
+	uic: bool,
+    },
+    /// AC sweep from f_start to f_end (in Hz). For a logarithmic sweep,
+    /// num_points is the number of points per decade or octave.
+    Ac {
+	sweep: SweepType,
+	num_points: usize,
+	f_start: f64,
+	f_end: f64,
+    },
+    /// DC sweep of one variable, or of two nested variables, innermost
+    /// first. Each variable is a source, `temp`, or a parameter.
+    Dc { sweeps: Vec<(String, SweepRange)> },
+    /// Noise at V(output) - V(reference), referred to the source named
+    /// input, over a sweep like that of [Analysis::Ac]
+    Noise {
+	output: String,
+	reference: String,
+	input: String,
+	sweep: SweepType,
+	num_points: usize,
+	f_start: f64,
+	f_end: f64,
+    },
+    /// Small-signal DC transfer function from the source named input
+    /// to output
+    Tf { output: OutputVariable, input: String },
+    /// Sensitivity of output to every element value and model
+    /// parameter, at the operating point, or over a sweep like that of
+    /// [Analysis::Ac] (given as the sweep type, number of points, and
+    /// start and end frequencies)
+    Sens {
+	output: OutputVariable,
+	ac: Option<(SweepType, usize, f64, f64)>,
+    },
+}
+
+/// Circuit description read from a netlist deck
+#[derive(Debug, Default)]
+pub struct Netlist {
+    elements: Vec<Element>,
+    /// Line in the deck of each element, for checks made once every
+    /// element is read
+    element_lines: Vec<usize>,
+    analyses: Vec<Analysis>,
+    /// Initial node voltages from `.ic` lines
+    initial_conditions: Vec<(String, f64)>,
+    /// Transient tolerances from `.options` lines
+    options: TransientOptions,
+    /// Device models from `.model` cards, by (lower case) name
+    models: HashMap<String, Model>,
+    /// Subcircuit definitions, by (lower case) name
+    subcircuits: HashMap<String, Subcircuit>,
+    /// Values of the parameters defined by `.param` lines, by (lower
+    /// case) name
+    params: HashMap<String, f64>,
+    /// Parameter values that replace those in the deck
+    overrides: HashMap<String, f64>,
+    /// The deck, so that it can be re-evaluated when a parameter changes
+    source: String,
+    warnings: Vec<Warning>,
+}
+
+impl Netlist {
+    /// Parse a netlist deck from a string
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+	Self::parse_with_params(input, &HashMap::new())
+    }
+
+    /// Parse a netlist deck from a string, overriding the values of
+    /// some of the parameters defined by its `.param` lines
+    pub fn parse_with_params(input: &str, overrides: &HashMap<String, f64>) -> Result<Self, ParseError> {
+	let mut netlist = Self {
+	    source: String::from(input),
+	    overrides: overrides.clone(),
+	    ..Self::default()
+	};
+	let lines = logical_lines(input)?;
+	netlist.parse_params(&lines)?;
+
+	// Subcircuit being defined, and instances to flatten once every
+	// subcircuit is defined
+	let mut definition: Option<(usize, Subcircuit)> = None;
+	let mut instances = Vec::new();
+	for (line, text) in lines {
+	    let keyword = text.split_whitespace().next().unwrap().to_ascii_lowercase();
+	    if let Some((_, subckt)) = definition.as_mut() {
+		if keyword == ".ends" {
+		    let (_, subckt) = definition.take().unwrap();
+		    netlist.define_subckt(line, subckt)?;
+		} else {
+		    subckt.push_line(line, &text)?;
+		}
+		continue;
+	    }
+	    match keyword.as_str() {
+		".subckt" => {
+		    definition = Some((line, netlist.parse_subckt(line, &text)?));
+		    continue;
+		}
+		// Already evaluated
+		".param" => continue,
+		_ => (),
+	    }
+	    let text = substitute(line, &text, &netlist.params)?;
+	    let tokens: Vec<&str> = text.split_whitespace().collect();
+	    if tokens[0].starts_with('.') {
+		match tokens[0].to_ascii_lowercase().as_str() {
+		    ".end" => break,
+		    ".ends" => {
+			return Err(ParseError::new(
+			    line,
+			    String::from(".ends without .subckt"),
+			))
+		    }
+		    ".op" => netlist.parse_op(line, &tokens)?,
+		    ".tran" => netlist.parse_tran(line, &tokens)?,
+		    ".ac" => netlist.parse_ac(line, &tokens)?,
+		    ".noise" => netlist.parse_noise(line, &text)?,
+		    ".tf" => netlist.parse_tf(line, &text)?,
+		    ".sens" => netlist.parse_sens(line, &text)?,
+		    ".dc" => netlist.parse_dc(line, &tokens)?,
+		    ".ic" => netlist.parse_ic(line, &text)?,
+		    ".options" | ".option" | ".opt" => netlist.parse_options(line, &text)?,
+		    ".model" => netlist.parse_model(line, &text)?,
+		    other => {
+			return Err(ParseError::new(
+			    line,
+			    format!("unsupported control line {}", other),
+			))
+		    }
+		}
+		continue;
+	    }
+	    if tokens[0].starts_with(['x', 'X']) {
+		instances.push((line, tokens.iter().map(|t| String::from(*t)).collect::<Vec<_>>()));
+		continue;
+	    }
+	    let element = netlist.parse_element(line, &tokens)?;
+	    netlist.add_element(line, element);
+	}
+	if let Some((line, subckt)) = definition {
+	    return Err(ParseError::new(
+		line,
+		format!("subcircuit {} has no .ends", subckt.name()),
+	    ));
+	}
+	let top = Scope::top(&netlist.params);
+	for (line, tokens) in instances {
+	    netlist.instantiate(line, &tokens, &top, &mut Vec::new())?;
+	}
+	netlist.check_controlling_elements()?;
+	netlist.check_models()?;
+	netlist.check_coupled_inductors()?;
+	Ok(netlist)
+    }
+
+    /// Evaluate the `.param name=value ...` lines outside subcircuit
+    /// definitions, after applying the overrides. Each value is an
+    /// expression that may refer to parameters defined before or after
+    /// it.
+    fn parse_params(&mut self, lines: &[(usize, String)]) -> Result<(), ParseError> {
+	let mut definitions = Vec::new();
+	let mut definition_lines = Vec::new();
+	let mut in_subckt = false;
+	for (line, text) in lines {
+	    let (keyword, rest) = text
+		.split_once(char::is_whitespace)
+		.unwrap_or((text.as_str(), ""));
+	    match keyword.to_ascii_lowercase().as_str() {
+		".subckt" => in_subckt = true,
+		".ends" => in_subckt = false,
+		".param" if !in_subckt => {
+		    for definition in param_definitions(*line, rest)? {
+			definitions.push(definition);
+			definition_lines.push(*line);
+		    }
+		}
+		_ => (),
+	    }
+	}
+	for name in self.overrides.keys() {
+	    if !definitions.iter().any(|(defined, _)| defined == name) {
+		return Err(ParseError {
+		    line: None,
+		    message: format!("unknown parameter {}", name),
+		});
+	    }
+	}
+	self.params = self.overrides.clone();
+	evaluate_params(&definitions, &mut self.params)
+	    .map_err(|(n, message)| ParseError::new(definition_lines[n], message))
+    }
+
+    /// Add an element read from a line of the deck
+    fn add_element(&mut self, line: usize, element: Element) {
+	self.elements.push(element);
+	self.element_lines.push(line);
+    }
+
+    /// Change the value of a parameter defined by a `.param` line,
+    /// re-evaluating every value in the deck that depends on it
+    pub fn set_param(&mut self, name: &str, value: f64) -> Result<(), ParseError> {
+	let mut overrides = self.overrides.clone();
+	overrides.insert(name.to_ascii_lowercase(), value);
+	*self = Self::parse_with_params(&self.source, &overrides)?;
+	Ok(())
+    }
+
+    /// Parameter values, by (lower case) name
+    pub fn params(&self) -> &HashMap<String, f64> {
+	&self.params
+    }
+
+    /// Read and parse a netlist file
+    pub fn from_file(file_path: &str) -> Result<Self, ParseError> {
+	let input = fs::read_to_string(file_path).map_err(|error| ParseError {
+	    line: None,
+	    message: format!("could not open file {} ({})", file_path, error),
+	})?;
+	Self::parse(&input)
+    }
+
+    pub fn elements(&self) -> &[Element] {
+	&self.elements
+    }
+
+    pub fn analyses(&self) -> &[Analysis] {
+	&self.analyses
+    }
+
+    /// Initial node voltages, as (node name, voltage)
+    pub fn initial_conditions(&self) -> &[(String, f64)] {
+	&self.initial_conditions
+    }
+
+    /// Tolerances and limits set by `.options`
+    pub fn options(&self) -> &TransientOptions {
+	&self.options
+    }
+
+    /// DC Newton-Raphson tolerances and limits set by `.options`
+    pub fn newton_options(&self) -> NewtonOptions {
+	NewtonOptions {
+	    reltol: self.options.reltol,
+	    abstol: self.options.abstol,
+	    vntol: self.options.vntol,
+	    max_iterations: self.options.dc_iterations,
+	}
+    }
+
+    /// Diagnostics for lines that parsed but may not mean what was intended
+    pub fn warnings(&self) -> &[Warning] {
+	&self.warnings
+    }
+
+    /// Names of elements whose current controls another element
+    /// (including coupled inductors)
+    fn controlling_elements(&self) -> HashSet<&str> {
+	self.elements
+	    .iter()
+	    .flat_map(Element::controlling_elements)
+	    .collect()
+    }
+
+    /// Check that every mutual inductance couples two different
+    /// inductors
+    fn check_coupled_inductors(&self) -> Result<(), ParseError> {
+	for (element, line) in self.elements.iter().zip(self.element_lines.iter()) {
+	    if let Element::MutualInductance {
+		name,
+		inductor_1,
+		inductor_2,
+		..
+	    } = element
+	    {
+		if inductor_1 == inductor_2 {
+		    return Err(ParseError::new(
+			*line,
+			format!("{} couples {} to itself", name, inductor_1),
+		    ));
+		}
+		for inductor in [inductor_1, inductor_2] {
+		    if self.inductance(inductor).is_none() {
+			return Err(ParseError::new(
+			    *line,
+			    format!("{} couples {}, which is not an inductor", name, inductor),
+			));
+		    }
+		}
+	    }
+	}
+	Ok(())
+    }
+
+    /// Inductance of the named inductor
+    fn inductance(&self, name: &str) -> Option<f64> {
+	self.elements.iter().find_map(|element| match element {
+	    Element::Inductor {
+		name: n,
+		inductance,
+		..
+	    } if n == name => Some(*inductance),
+	    _ => None,
+	})
+    }
+
+    /// Each mutual inductance, as (name, inductor 1, inductor 2,
+    /// coupling coefficient). These are added after all the other
+    /// elements, so that the inductors they couple exist.
+    fn mutual_inductances(&self) -> Vec<(&str, &str, &str, f64)> {
+	self.elements
+	    .iter()
+	    .filter_map(|element| match element {
+		Element::MutualInductance {
+		    name,
+		    inductor_1,
+		    inductor_2,
+		    coupling,
+		} => Some((
+		    name.as_str(),
+		    inductor_1.as_str(),
+		    inductor_2.as_str(),
+		    *coupling,
+		)),
+		_ => None,
+	    })
+	    .collect()
+    }
+
+    /// Check that every controlling element exists in the netlist
+    fn check_controlling_elements(&self) -> Result<(), ParseError> {
+	for (element, line) in self.elements.iter().zip(self.element_lines.iter()) {
+	    for ctrl_element in element.controlling_elements() {
+		if !self.elements.iter().any(|e| e.name() == ctrl_element) {
+		    return Err(ParseError::new(
+			*line,
+			format!("controlling element {} is not defined", ctrl_element),
+		    ));
+		}
+	    }
+	}
+	Ok(())
+    }
+
+    pub fn models(&self) -> &HashMap<String, Model> {
+	&self.models
+    }
+
+    pub fn subcircuits(&self) -> &HashMap<String, Subcircuit> {
+	&self.subcircuits
+    }
+
+    /// Check that every device refers to a model of the right type
+    fn check_models(&self) -> Result<(), ParseError> {
+	for (element, line) in self.elements.iter().zip(self.element_lines.iter()) {
+	    let (model, kinds) = match element {
+		Element::Diode { model, .. } => (model, &["D"][..]),
+		Element::Bjt { model, .. } => (model, &["NPN", "PNP"][..]),
+		Element::Mosfet { model, .. } => (model, &["NMOS", "PMOS"][..]),
+		_ => continue,
+	    };
+	    match self.models.get(model) {
+		Some(m) if kinds.contains(&m.kind()) => (),
+		Some(m) => {
+		    return Err(ParseError::new(
+			*line,
+			format!(
+			    "{} needs a {} model, but {} is a {} model",
+			    element.name(),
+			    kinds.join(" or "),
+			    model,
+			    m.kind()
+			),
+		    ))
+		}
+		None => {
+		    return Err(ParseError::new(
+			*line,
+			format!("model {} is not defined", model),
+		    ))
+		}
+	    }
+	}
+	Ok(())
+    }
+
+    fn diode_model(&self, name: &str) -> Result<&DiodeModel, AcdcError> {
+	match self.models.get(name) {
+	    Some(Model::Diode(model)) => Ok(model),
+	    _ => Err(AcdcError::InvalidTopology(format!("no diode model {}", name))),
+	}
+    }
+
+    fn mosfet_model(&self, name: &str) -> Result<&MosfetModel, AcdcError> {
+	match self.models.get(name) {
+	    Some(Model::Mosfet(model)) => Ok(model),
+	    _ => Err(AcdcError::InvalidTopology(format!("no MOSFET model {}", name))),
+	}
+    }
+
+    fn bjt_model(&self, name: &str) -> Result<&BjtModel, AcdcError> {
+	match self.models.get(name) {
+	    Some(Model::Bjt(model)) => Ok(model),
+	    _ => Err(AcdcError::InvalidTopology(format!("no transistor model {}", name))),
+	}
+    }
+
+    /// Parse a value in engineering notation, keeping any warning
+    fn value(&mut self, line: usize, token: &str) -> Result<f64, ParseError> {
+	let value = parse_value(token).map_err(|error| ParseError::new(line, error.message))?;
+	if let Some(message) = value.warning {
+	    self.warnings.push(Warning { line, message });
+	}
+	Ok(value.value)
+    }
+
+    /// Parse a positive whole number
+    fn count(&mut self, line: usize, name: &str, token: &str) -> Result<usize, ParseError> {
+	let value = self.value(line, token)?;
+	if !(value >= 1.0) || value.fract() != 0.0 {
+	    return Err(ParseError::new(
+		line,
+		format!("{} must be a positive integer, found {}", name, token),
+	    ));
+	}
+	Ok(value as usize)
+    }
+
+    /// Parse `.op`
+    fn parse_op(&mut self, line: usize, tokens: &[&str]) -> Result<(), ParseError> {
+	expect_args(line, ".op", &tokens[1..], 0)?;
+	self.analyses.push(Analysis::Op);
+	Ok(())
+    }
+
+    /// Parse `.ac dec|oct|lin N fstart fstop`
+    fn parse_ac(&mut self, line: usize, tokens: &[&str]) -> Result<(), ParseError> {
+	let args = &tokens[1..];
+	expect_args(line, ".ac", args, 4)?;
+	let (sweep, num_points, f_start, f_end) = self.frequency_sweep(line, ".ac", args)?;
+	self.analyses.push(Analysis::Ac {
+	    sweep,
+	    num_points,
+	    f_start,
+	    f_end,
+	});
+	Ok(())
+    }
+
+    /// Parse `.noise V(out[,ref]) src dec|oct|lin N fstart fstop`
+    fn parse_noise(&mut self, line: usize, text: &str) -> Result<(), ParseError> {
+	static NOISE: OnceLock<Regex> = OnceLock::new();
+	let re = NOISE.get_or_init(|| {
+	    Regex::new(r"(?i)^\.noise\s+v\(\s*([^,\s)]+)\s*(?:,\s*([^\s)]+)\s*)?\)\s+(.*)$").unwrap()
+	});
+	let captures = re.captures(text.trim()).ok_or_else(|| {
+	    ParseError::new(
+		line,
+		String::from("expected .noise V(out[,ref]) src dec|oct|lin N fstart fstop"),
+	    )
+	})?;
+	let args: Vec<&str> = captures[3].split_whitespace().collect();
+	expect_args(line, ".noise V(out)", &args, 5)?;
+	let (sweep, num_points, f_start, f_end) = self.frequency_sweep(line, ".noise", &args[1..])?;
+	self.analyses.push(Analysis::Noise {
+	    output: String::from(&captures[1]),
+	    reference: String::from(captures.get(2).map_or("0", |m| m.as_str())),
+	    input: args[0].to_ascii_lowercase(),
+	    sweep,
+	    num_points,
+	    f_start,
+	    f_end,
+	});
+	Ok(())
+    }
+
+    /// Parse `.tf V(out[,ref])|I(element) src`
+    fn parse_tf(&mut self, line: usize, text: &str) -> Result<(), ParseError> {
+	static TF: OnceLock<Regex> = OnceLock::new();
+	let re = TF.get_or_init(|| {
+	    Regex::new(&format!(r"(?i)^\.tf\s+{}\s+(\S+)$", OUTPUT_VARIABLE)).unwrap()
+	});
+	let captures = re.captures(text.trim()).ok_or_else(|| {
+	    ParseError::new(line, String::from("expected .tf V(out[,ref])|I(element) src"))
+	})?;
+	self.analyses.push(Analysis::Tf {
+	    output: output_variable(&captures),
+	    input: captures[4].to_ascii_lowercase(),
+	});
+	Ok(())
+    }
+
+    /// Parse `.sens V(out[,ref])|I(element) [ac dec|oct|lin N fstart fstop]`
+    fn parse_sens(&mut self, line: usize, text: &str) -> Result<(), ParseError> {
+	static SENS: OnceLock<Regex> = OnceLock::new();
+	let re = SENS.get_or_init(|| {
+	    Regex::new(&format!(r"(?i)^\.sens\s+{}\s*(.*)$", OUTPUT_VARIABLE)).unwrap()
+	});
+	let captures = re.captures(text.trim()).ok_or_else(|| {
+	    ParseError::new(
+		line,
+		String::from("expected .sens V(out[,ref])|I(element) [ac dec|oct|lin N fstart fstop]"),
+	    )
+	})?;
+	let args: Vec<&str> = captures[4].split_whitespace().collect();
+	let ac = match args.first() {
+	    None => None,
+	    Some(first) if first.eq_ignore_ascii_case("ac") => {
+		expect_args(line, ".sens ac", &args[1..], 4)?;
+		Some(self.frequency_sweep(line, ".sens", &args[1..])?)
+	    }
+	    Some(other) => {
+		return Err(ParseError::new(
+		    line,
+		    format!("expected ac or the end of .sens, found {}", other),
+		))
+	    }
+	};
+	self.analyses.push(Analysis::Sens {
+	    output: output_variable(&captures),
+	    ac,
+	});
+	Ok(())
+    }
+
+    /// Parse the `dec|oct|lin N fstart fstop` of the control line name
+    fn frequency_sweep(
+	&mut self,
+	line: usize,
+	name: &str,
+	args: &[&str],
+    ) -> Result<(SweepType, usize, f64, f64), ParseError> {
+	let sweep = match args[0].to_ascii_lowercase().as_str() {
+	    "lin" => SweepType::Linear,
+	    "dec" => SweepType::Decade,
+	    "oct" => SweepType::Octave,
+	    other => {
+		return Err(ParseError::new(
+		    line,
+		    format!("expected lin, dec or oct in {}, found {}", name, other),
+		))
+	    }
+	};
+	let num_points = self.count(line, &format!("number of points in {}", name), args[1])?;
+	let f_start = self.value(line, args[2])?;
+	let f_end = self.value(line, args[3])?;
+	if f_end < f_start || f_start < 0.0 || (f_start == 0.0 && sweep != SweepType::Linear) {
+	    return Err(ParseError::new(
+		line,
+		format!("invalid {} frequency range {} to {}", name, args[2], args[3]),
+	    ));
+	}
+	Ok((sweep, num_points, f_start, f_end))
+    }
+
+    /// Parse `.dc var start stop step [var2 start2 stop2 step2]`, where
+    /// either range may instead be `dec|oct|lin N start stop` or `list
+    /// values...`
+    fn parse_dc(&mut self, line: usize, tokens: &[&str]) -> Result<(), ParseError> {
+	let mut args = &tokens[1..];
+	let mut sweeps = Vec::new();
+	while !args.is_empty() {
+	    if sweeps.len() == 2 {
+		return Err(ParseError::new(
+		    line,
+		    String::from("at most two variables can be swept in .dc"),
+		));
+	    }
+	    let variable = args[0].to_ascii_lowercase();
+	    let (range, num_args) = self.sweep_range(line, &args[1..])?;
+	    range
+		.values()
+		.map_err(|error| ParseError::new(line, error.to_string()))?;
+	    sweeps.push((variable, range));
+	    args = &args[1 + num_args..];
+	}
+	if sweeps.is_empty() {
+	    return Err(ParseError::new(
+		line,
+		String::from("expected a variable to sweep after .dc"),
+	    ));
+	}
+	self.analyses.push(Analysis::Dc { sweeps });
+	Ok(())
+    }
+
+    /// Parse the range of one `.dc` variable, returning it and the
+    /// number of fields it used
+    fn sweep_range(&mut self, line: usize, args: &[&str]) -> Result<(SweepRange, usize), ParseError> {
+	let kind = args.first().map(|token| token.to_ascii_lowercase());
+	let sweep = match kind.as_deref() {
+	    Some("list") => {
+		// Values continue up to the next variable name
+		let num_values = args[1..]
+		    .iter()
+		    .take_while(|token| parse_value(token).is_ok())
+		    .count();
+		let values = args[1..=num_values]
+		    .iter()
+		    .map(|token| self.value(line, token))
+		    .collect::<Result<Vec<f64>, ParseError>>()?;
+		return Ok((SweepRange::List(values), 1 + num_values));
+	    }
+	    Some("lin") => Some(SweepType::Linear),
+	    Some("dec") => Some(SweepType::Decade),
+	    Some("oct") => Some(SweepType::Octave),
+	    _ => None,
+	};
+	match sweep {
+	    Some(sweep) => {
+		if args.len() < 4 {
+		    return Err(ParseError::new(
+			line,
+			format!("expected N start stop after {} in .dc", args[0]),
+		    ));
+		}
+		let num_points = self.count(line, "number of points in .dc", args[1])?;
+		let start = self.value(line, args[2])?;
+		let stop = self.value(line, args[3])?;
+		Ok((
+		    SweepRange::Points {
+			sweep,
+			num_points,
+			start,
+			stop,
+		    },
+		    4,
+		))
+	    }
+	    None => {
+		if args.len() < 3 {
+		    return Err(ParseError::new(
+			line,
+			String::from("expected start stop step in .dc"),
+		    ));
+		}
+		let start = self.value(line, args[0])?;
+		let stop = self.value(line, args[1])?;
+		let step = self.value(line, args[2])?;
+		Ok((SweepRange::Linear { start, stop, step }, 3))
+	    }
+	}
+    }
+
+    /// Parse `.tran tstep tstop [tstart [tmax]] [UIC]`
+    fn parse_tran(&mut self, line: usize, tokens: &[&str]) -> Result<(), ParseError> {
+	let mut args: Vec<&str> = tokens[1..].to_vec();
+	let uic = args.len() > 2 && args.last().unwrap().eq_ignore_ascii_case("uic");
+	if uic {
+	    args.pop();
+	}
+	if args.len() < 2 || args.len() > 4 {
+	    return Err(ParseError::new(
+		line,
+		format!("expected 2 to 4 fields after .tran, found {}", args.len()),
+	    ));
+	}
+	let step = self.value(line, args[0])?;
+	let stop = self.value(line, args[1])?;
+	if !(step > 0.0) || !(stop > 0.0) {
+	    return Err(ParseError::new(
+		line,
+		String::from(".tran timestep and stop time must be positive"),
+	    ));
+	}
+	let start = match args.get(2) {
+	    Some(token) => self.value(line, token)?,
+	    None => 0.0,
+	};
+	if start < 0.0 || start >= stop {
+	    return Err(ParseError::new(
+		line,
+		String::from(".tran start time must be between zero and the stop time"),
+	    ));
+	}
+	let max_step = match args.get(3) {
+	    Some(token) => Some(self.value(line, token)?),
+	    None => None,
+	};
+	self.analyses.push(Analysis::Tran {
+	    step,
+	    stop,
+	    start,
+	    max_step,
+	    uic,
+	});
+	Ok(())
+    }
+
+    /// Parse the optional area factor after the model name of a
+    /// semiconductor device, which has num_args other fields
+    fn area(
+	&mut self,
+	line: usize,
+	name: &str,
+	args: &[&str],
+	num_args: usize,
+    ) -> Result<f64, ParseError> {
+	if args.len() != num_args && args.len() != num_args + 1 {
+	    return Err(ParseError::new(
+		line,
+		format!(
+		    "expected {} or {} fields after {}, found {}",
+		    num_args,
+		    num_args + 1,
+		    name,
+		    args.len()
+		),
+	    ));
+	}
+	match args.get(num_args) {
+	    Some(token) => self.value(line, token),
+	    None => Ok(1.0),
+	}
+    }
+
+    /// Parse `.model name type [(]name=value ...[)]`, which defines the
+    /// parameters of a device model that devices refer to by name. The
+    /// types are `D` (diode), `NPN` and `PNP` (bipolar transistors),
+    /// and `NMOS` and `PMOS` (MOSFETs, with `level=1` or `level=3`).
+    fn parse_model(&mut self, line: usize, text: &str) -> Result<(), ParseError> {
+	// Parentheses around the parameters are optional
+	let spec = text.replace(['(', ')'], " ");
+	let tokens: Vec<&str> = spec.split_whitespace().collect();
+	if tokens.len() < 3 {
+	    return Err(ParseError::new(
+		line,
+		String::from("expected name and type after .model"),
+	    ));
+	}
+	let name = tokens[1].to_ascii_lowercase();
+	let mut model = match tokens[2].to_ascii_lowercase().as_str() {
+	    "d" => Model::Diode(DiodeModel::default()),
+	    "npn" => Model::Bjt(BjtModel::new(Polarity::N)),
+	    "pnp" => Model::Bjt(BjtModel::new(Polarity::P)),
+	    "nmos" => Model::Mosfet(MosfetModel::new(Polarity::N)),
+	    "pmos" => Model::Mosfet(MosfetModel::new(Polarity::P)),
+	    other => {
+		return Err(ParseError::new(
+		    line,
+		    format!("unsupported model type {}", other),
+		))
+	    }
+	};
+
+	static PARAM: OnceLock<Regex> = OnceLock::new();
+	let re = PARAM.get_or_init(|| {
+	    Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)\s*=\s*([^\s=]+)\s*").unwrap()
+	});
+	let params = tokens[3..].join(" ");
+	let mut rest = params.as_str();
+	while !rest.is_empty() {
+	    let captures = re.captures(rest).ok_or_else(|| {
+		ParseError::new(line, format!("expected name=value in .model, found {}", rest))
+	    })?;
+	    let param = captures[1].to_ascii_lowercase();
+	    let value = self.value(line, &captures[2])?;
+	    rest = &rest[captures[0].len()..];
+	    let known = match &mut model {
+		Model::Diode(m) => m.set_param(&param, value),
+		Model::Bjt(m) => m.set_param(&param, value),
+		Model::Mosfet(m) => m.set_param(&param, value),
+	    };
+	    if !known {
+		self.warnings.push(Warning {
+		    line,
+		    message: format!("ignoring unsupported {} model parameter {}", model.kind(), param),
+		});
+	    }
+	}
+
+	if let Model::Mosfet(m) = &model {
+	    if m.level != 1 && m.level != 3 {
+		return Err(ParseError::new(
+		    line,
+		    format!("unsupported MOSFET level {}", m.level),
+		));
+	    }
+	}
+	if self.models.insert(name.clone(), model).is_some() {
+	    return Err(ParseError::new(line, format!("model {} is defined twice", name)));
+	}
+	Ok(())
+    }
+
+    /// Parse `.options name=value ...`, which sets the transient
+    /// tolerances (see [TransientOptions]), the minimum timestep
+    /// (`tmin`), fixed steps at tstep (`fixedstep`), the integration
+    /// method (`method=trap|gear|euler`), and the Newton-Raphson
+    /// iteration limits for the operating point (`itl1`) and for each
+    /// timestep (`itl4`). Options that only apply to other simulators
+    /// are ignored with a warning.
+    fn parse_options(&mut self, line: usize, text: &str) -> Result<(), ParseError> {
+	static OPTION: OnceLock<Regex> = OnceLock::new();
+	let re = OPTION.get_or_init(|| {
+	    Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)(\s*=\s*([^\s=]+))?\s*").unwrap()
+	});
+	// Skip the .options keyword
+	let mut rest = text.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim_start());
+	while !rest.is_empty() {
+	    let captures = re.captures(rest).ok_or_else(|| {
+		ParseError::new(line, format!("expected name=value in .options, found {}", rest))
+	    })?;
+	    let name = captures[1].to_ascii_lowercase();
+	    rest = &rest[captures[0].len()..];
+	    let value = match captures.get(3) {
+		Some(value) => value.as_str(),
+		None => {
+		    if name == "fixedstep" {
+			self.options.fixed_step = true;
+		    } else {
+			self.warnings.push(Warning {
+			    line,
+			    message: format!("ignoring unsupported option {}", name),
+			});
+		    }
+		    continue;
+		}
+	    };
+	    match name.as_str() {
+		"reltol" => self.options.reltol = self.value(line, value)?,
+		"abstol" => self.options.abstol = self.value(line, value)?,
+		"vntol" => self.options.vntol = self.value(line, value)?,
+		"trtol" => self.options.trtol = self.value(line, value)?,
+		"tmin" => self.options.min_step = Some(self.value(line, value)?),
+		"itl1" => self.options.dc_iterations = self.count(line, &name, value)?,
+		"itl4" => self.options.max_iterations = self.count(line, &name, value)?,
+		"method" => {
+		    self.options.method = match value.to_ascii_lowercase().as_str() {
+			"trap" | "trapezoidal" => IntegrationMethod::Trapezoidal,
+			"gear" => IntegrationMethod::Gear2,
+			"euler" => IntegrationMethod::BackwardEuler,
+			other => {
+			    return Err(ParseError::new(
+				line,
+				format!("unknown integration method {}", other),
+			    ))
+			}
+		    }
+		}
+		_ => self.warnings.push(Warning {
+		    line,
+		    message: format!("ignoring unsupported option {}", name),
+		}),
+	    }
+	}
+	Ok(())
+    }
+
+    /// Parse `.ic V(node)=value ...`
+    fn parse_ic(&mut self, line: usize, text: &str) -> Result<(), ParseError> {
+	static INITIAL_CONDITION: OnceLock<Regex> = OnceLock::new();
+	let re = INITIAL_CONDITION.get_or_init(|| {
+	    Regex::new(r"(?i)^v\(\s*([^()\s]+)\s*\)\s*=\s*([^\s=]+)\s*").unwrap()
+	});
+	// Skip the .ic keyword
+	let mut rest = text[3..].trim_start();
+	if rest.is_empty() {
+	    return Err(ParseError::new(line, String::from(".ic with no initial conditions")));
+	}
+	while !rest.is_empty() {
+	    let captures = re.captures(rest).ok_or_else(|| {
+		ParseError::new(line, format!("expected V(node)=value in .ic, found {}", rest))
+	    })?;
+	    let node = String::from(&captures[1]);
+	    let voltage = self.value(line, &captures[2])?;
+	    self.initial_conditions.push((node, voltage));
+	    rest = &rest[captures[0].len()..];
+	}
+	Ok(())
+    }
+
+    /// Parse the value of an independent source, made of any of
+    /// `[DC] value`, `AC mag [phase]` and a transient function
+    fn parse_source(&mut self, line: usize, args: &[&str]) -> Result<SourceWaveform, ParseError> {
+	// Parentheses and commas only separate the function arguments
+	let spec = args.join(" ").replace(['(', ')', ','], " ");
+	let tokens: Vec<&str> = spec.split_whitespace().collect();
+	let is_keyword = |token: &str| {
+	    SOURCE_KEYWORDS.contains(&token.to_ascii_lowercase().as_str())
+	};
+
+	let mut waveform = SourceWaveform::default();
+	let mut n = 0;
+	while n < tokens.len() {
+	    let keyword = tokens[n].to_ascii_lowercase();
+	    n += 1;
+	    match keyword.as_str() {
+		"dc" | "ac" => {
+		    let value = tokens.get(n).ok_or_else(|| {
+			ParseError::new(line, format!("expected value after {}", keyword))
+		    })?;
+		    let value = self.value(line, value)?;
+		    n += 1;
+		    if keyword == "dc" {
+			waveform.set_dc(value);
+		    } else {
+			let mut phase = 0.0;
+			if let Some(token) = tokens.get(n).filter(|t| !is_keyword(**t)) {
+			    phase = self.value(line, token)?;
+			    n += 1;
+			}
+			waveform.set_ac(value, phase);
+		    }
+		}
+		"pwl" if tokens.get(n).map_or(false, |t| t.eq_ignore_ascii_case("file")) => {
+		    let file_path = tokens.get(n + 1).ok_or_else(|| {
+			ParseError::new(line, String::from("expected file name after PWL FILE"))
+		    })?;
+		    n += 2;
+		    let values = self.pwl_file(line, file_path.trim_matches('"'))?;
+		    waveform.set_function(transient_function(line, "pwl", &values)?);
+		}
+		"pulse" | "sin" | "exp" | "pwl" | "sffm" => {
+		    let mut values = Vec::new();
+		    while n < tokens.len() && !is_keyword(tokens[n]) {
+			values.push(self.value(line, tokens[n])?);
+			n += 1;
+		    }
+		    waveform.set_function(transient_function(line, &keyword, &values)?);
+		}
+		_ if n == 1 => waveform.set_dc(self.value(line, tokens[0])?),
+		_ => {
+		    return Err(ParseError::new(
+			line,
+			format!("unexpected {} in source value", tokens[n - 1]),
+		    ))
+		}
+	    }
+	}
+	Ok(waveform)
+    }
+
+    /// Read the (time, value) pairs of a `PWL FILE`
+    fn pwl_file(&mut self, line: usize, file_path: &str) -> Result<Vec<f64>, ParseError> {
+	let input = fs::read_to_string(file_path).map_err(|error| {
+	    ParseError::new(line, format!("could not open file {} ({})", file_path, error))
+	})?;
+	let mut values = Vec::new();
+	for text in input.lines() {
+	    let text = text.trim();
+	    if text.is_empty() || text.starts_with('*') || text.starts_with('#') {
+		continue;
+	    }
+	    for token in text.split(|c: char| c.is_whitespace() || c == ',') {
+		if !token.is_empty() {
+		    values.push(self.value(line, token)?);
+		}
+	    }
+	}
+	Ok(values)
+    }
+
+    fn parse_element(&mut self, line: usize, tokens: &[&str]) -> Result<Element, ParseError> {
+	let name = tokens[0].to_ascii_lowercase();
+	let mut args: Vec<&str> = tokens[1..].to_vec();
+	match name.chars().next().unwrap() {
+	    'r' => {
+		let group2 = in_group2(&mut args);
+		expect_args(line, &name, &args, 3)?;
+		Ok(Element::Resistor {
+		    term_1: String::from(args[0]),
+		    term_2: String::from(args[1]),
+		    resistance: self.value(line, args[2])?,
+		    name,
+		    group2,
+		})
+	    }
+	    'c' => {
+		let group2 = in_group2(&mut args);
+		expect_args(line, &name, &args, 3)?;
+		Ok(Element::Capacitor {
+		    term_1: String::from(args[0]),
+		    term_2: String::from(args[1]),
+		    capacitance: self.value(line, args[2])?,
+		    name,
+		    group2,
+		})
+	    }
+	    'l' => {
+		// An inductor current is in group 2 with or without the flag
+		in_group2(&mut args);
+		expect_args(line, &name, &args, 3)?;
+		Ok(Element::Inductor {
+		    term_1: String::from(args[0]),
+		    term_2: String::from(args[1]),
+		    inductance: self.value(line, args[2])?,
+		    name,
+		})
+	    }
+	    'v' => {
+		expect_source_args(line, &name, &args)?;
+		Ok(Element::VoltageSource {
+		    term_pos: String::from(args[0]),
+		    term_neg: String::from(args[1]),
+		    waveform: self.parse_source(line, &args[2..])?,
+		    name,
+		})
+	    }
+	    'e' => {
+		expect_args(line, &name, &args, 5)?;
+		Ok(Element::Vcvs {
+		    term_pos: String::from(args[0]),
+		    term_neg: String::from(args[1]),
+		    ctrl_pos: String::from(args[2]),
+		    ctrl_neg: String::from(args[3]),
+		    gain: self.value(line, args[4])?,
+		    name,
+		})
+	    }
+	    'h' => {
+		expect_args(line, &name, &args, 4)?;
+		Ok(Element::Ccvs {
+		    term_pos: String::from(args[0]),
+		    term_neg: String::from(args[1]),
+		    ctrl_element: args[2].to_ascii_lowercase(),
+		    transresistance: self.value(line, args[3])?,
+		    name,
+		})
+	    }
+	    'g' => {
+		let group2 = in_group2(&mut args);
+		expect_args(line, &name, &args, 5)?;
+		Ok(Element::Vccs {
+		    term_pos: String::from(args[0]),
+		    term_neg: String::from(args[1]),
+		    ctrl_pos: String::from(args[2]),
+		    ctrl_neg: String::from(args[3]),
+		    transconductance: self.value(line, args[4])?,
+		    name,
+		    group2,
+		})
+	    }
+	    'f' => {
+		let group2 = in_group2(&mut args);
+		expect_args(line, &name, &args, 4)?;
+		Ok(Element::Cccs {
+		    term_pos: String::from(args[0]),
+		    term_neg: String::from(args[1]),
+		    ctrl_element: args[2].to_ascii_lowercase(),
+		    gain: self.value(line, args[3])?,
+		    name,
+		    group2,
+		})
+	    }
+	    'd' => {
+		let area = self.area(line, &name, &args, 3)?;
+		Ok(Element::Diode {
+		    anode: String::from(args[0]),
+		    cathode: String::from(args[1]),
+		    model: args[2].to_ascii_lowercase(),
+		    area,
+		    name,
+		})
+	    }
+	    'q' => {
+		let area = self.area(line, &name, &args, 4)?;
+		Ok(Element::Bjt {
+		    collector: String::from(args[0]),
+		    base: String::from(args[1]),
+		    emitter: String::from(args[2]),
+		    model: args[3].to_ascii_lowercase(),
+		    area,
+		    name,
+		})
+	    }
+	    'k' => {
+		expect_args(line, &name, &args, 3)?;
+		let coupling = self.value(line, args[2])?;
+		if !(coupling > 0.0 && coupling <= 1.0) {
+		    return Err(ParseError::new(
+			line,
+			format!("coupling coefficient of {} must be in (0, 1]", name),
+		    ));
+		}
+		Ok(Element::MutualInductance {
+		    inductor_1: args[0].to_ascii_lowercase(),
+		    inductor_2: args[1].to_ascii_lowercase(),
+		    coupling,
+		    name,
+		})
+	    }
+	    'm' => {
+		if args.len() < 5 {
+		    return Err(ParseError::new(
+			line,
+			format!("expected at least 5 fields after {}, found {}", name, args.len()),
+		    ));
+		}
+		// SPICE default channel width and length
+		let mut w = 100e-6;
+		let mut l = 100e-6;
+		for param in args[5..].iter() {
+		    let (key, value) = param.split_once('=').ok_or_else(|| {
+			ParseError::new(line, format!("expected name=value, found {}", param))
+		    })?;
+		    let value = self.value(line, value)?;
+		    match key.to_ascii_lowercase().as_str() {
+			"w" => w = value,
+			"l" => l = value,
+			other => self.warnings.push(Warning {
+			    line,
+			    message: format!("ignoring unsupported MOSFET parameter {}", other),
+			}),
+		    }
+		}
+		Ok(Element::Mosfet {
+		    drain: String::from(args[0]),
+		    gate: String::from(args[1]),
+		    source: String::from(args[2]),
+		    bulk: String::from(args[3]),
+		    model: args[4].to_ascii_lowercase(),
+		    w,
+		    l,
+		    name,
+		})
+	    }
+	    'i' => {
+		let group2 = in_group2(&mut args);
+		expect_source_args(line, &name, &args)?;
+		Ok(Element::CurrentSource {
+		    term_pos: String::from(args[0]),
+		    term_neg: String::from(args[1]),
+		    waveform: self.parse_source(line, &args[2..])?,
+		    name,
+		    group2,
+		})
+	    }
+	    _ => Err(ParseError::new(
+		line,
+		format!("unsupported element {}", tokens[0]),
+	    )),
+	}
+    }
+
+    /// Make a linear DC analysis of the circuit
+    ///
+    /// Capacitors are treated as open circuits, and inductors as
+    /// short circuits (whose current is reported under the inductor
+    /// name).
+    pub fn linear_dc_analysis(&self) -> Result<LinearDcAnalysis, AcdcError> {
+	self.linear_dc_analysis_with_edges(self.controlling_elements())
+    }
+
+    /// Find the transfer function requested by the first `.tf
+    /// V(out[,ref])|I(element) src` line: the DC gain from the
+    /// independent source src to a voltage or to the current in an
+    /// element, with the resistance seen by the source and at the
+    /// output (see [LinearDcAnalysis::transfer_function]). The circuit
+    /// must be linear, as for
+    /// [linear_dc_analysis](Self::linear_dc_analysis).
+    pub fn transfer_function(&self) -> Result<TransferFunction<f64>, AcdcError> {
+	let (output, input) = self
+	    .analyses
+	    .iter()
+	    .find_map(|analysis| match analysis {
+		Analysis::Tf { output, input } => Some((output, input)),
+		_ => None,
+	    })
+	    .ok_or_else(|| ParseError {
+		line: None,
+		message: String::from("no .tf line in netlist"),
+	    })?;
+	// An output element needs a current edge
+	let mut edges = self.controlling_elements();
+	if let OutputVariable::Current(element) = output {
+	    edges.insert(element.as_str());
+	}
+	self.linear_dc_analysis_with_edges(edges)?
+	    .transfer_function(output, input)
+    }
+
+    /// Make a linear DC analysis in which the named elements have
+    /// their current in group 2
+    fn linear_dc_analysis_with_edges(
+	&self,
+	controls: HashSet<&str>,
+    ) -> Result<LinearDcAnalysis, AcdcError> {
+	let mut dc = LinearDcAnalysis::new();
+	for element in self.elements.iter() {
+	    match element {
+		Element::Resistor {
+		    name,
+		    term_1,
+		    term_2,
+		    resistance,
+		    group2,
+		} => dc.add_resistor(
+		    name,
+		    term_1,
+		    term_2,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *resistance,
+		)?,
+		Element::Capacitor {
+		    name,
+		    term_1,
+		    term_2,
+		    group2,
+		    ..
+		} => {
+		    // The current through an open circuit is zero
+		    if current_edge(&controls, name, *group2).is_some() {
+			dc.add_independent_current_source(name, term_1, term_2, true, 0.0)?;
+		    }
+		}
+		Element::Inductor {
+		    name,
+		    term_1,
+		    term_2,
+		    ..
+		} => dc.add_independent_voltage_source(name, term_1, term_2, 0.0)?,
+		Element::VoltageSource {
+		    name,
+		    term_pos,
+		    term_neg,
+		    waveform,
+		} => dc.add_independent_voltage_source_waveform(name, term_pos, term_neg, waveform)?,
+		Element::CurrentSource {
+		    name,
+		    term_pos,
+		    term_neg,
+		    waveform,
+		    group2,
+		} => dc.add_independent_current_source_waveform(
+		    name,
+		    term_pos,
+		    term_neg,
+		    current_edge(&controls, name, *group2).is_some(),
+		    waveform,
+		)?,
+		Element::Vcvs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    gain,
+		} => dc.add_voltage_controlled_voltage_source(
+		    name, term_pos, term_neg, ctrl_pos, ctrl_neg, *gain,
+		)?,
+		Element::Ccvs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    transresistance,
+		} => dc.add_current_controlled_voltage_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    *transresistance,
+		)?,
+		Element::Vccs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    transconductance,
+		    group2,
+		} => dc.add_voltage_controlled_current_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *transconductance,
+		)?,
+		Element::Cccs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    gain,
+		    group2,
+		} => dc.add_current_controlled_current_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *gain,
+		)?,
+		Element::Diode { name, .. }
+		| Element::Bjt { name, .. }
+		| Element::Mosfet { name, .. } => return Err(nonlinear_element(name)),
+		// Inductors are short circuits at DC, whatever their coupling
+		Element::MutualInductance { .. } => (),
+	    }
+	}
+	Ok(dc)
+    }
+
+    /// Make a linear AC sweep of the circuit between f_start and f_end (in Hz)
+    pub fn linear_ac_sweep(
+	&self,
+	f_start: f64,
+	f_end: f64,
+	num_steps: usize,
+    ) -> Result<LinearAcSweep, AcdcError> {
+	let mut ac = LinearAcSweep::new(f_start, f_end, num_steps)?;
+	self.add_ac_elements(ac.circuit_mut())?;
+	Ok(ac)
+    }
+
+    /// Make the AC sweep requested by the first `.ac dec|oct|lin N
+    /// fstart fstop` line, from fstart to fstop (inclusive) with N
+    /// points per decade or octave, or N points in all for a linear
+    /// sweep. The circuit is linearised about its operating point, and
+    /// excited by the sources with an AC magnitude.
+    pub fn ac_sweep(&self) -> Result<SmallSignalAcSweep, AcdcError> {
+	let (sweep, num_points, f_start, f_end) = self
+	    .analyses
+	    .iter()
+	    .find_map(|analysis| match analysis {
+		Analysis::Ac {
+		    sweep,
+		    num_points,
+		    f_start,
+		    f_end,
+		} => Some((*sweep, *num_points, *f_start, *f_end)),
+		_ => None,
+	    })
+	    .ok_or_else(|| ParseError {
+		line: None,
+		message: String::from("no .ac line in netlist"),
+	    })?;
+	let mut ac = SmallSignalAcSweep::new(self.circuit()?, sweep, num_points, f_start, f_end)?;
+	ac.set_options(self.newton_options());
+	Ok(ac)
+    }
+
+    fn add_ac_elements(&self, ac: &mut LinearAcCircuit) -> Result<(), AcdcError> {
+	let controls = self.controlling_elements();
+	for element in self.elements.iter() {
+	    match element {
+		Element::Resistor {
+		    name,
+		    term_1,
+		    term_2,
+		    resistance,
+		    group2,
+		} => ac.add_resistor(
+		    name,
+		    term_1,
+		    term_2,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *resistance,
+		)?,
+		Element::Capacitor {
+		    name,
+		    term_1,
+		    term_2,
+		    capacitance,
+		    group2,
+		} => ac.add_capacitor(
+		    name,
+		    term_1,
+		    term_2,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *capacitance,
+		)?,
+		Element::Inductor {
+		    name,
+		    term_1,
+		    term_2,
+		    inductance,
+		    ..
+		} => ac.add_inductor(name, term_1, term_2, *inductance)?,
+		Element::VoltageSource {
+		    name,
+		    term_pos,
+		    term_neg,
+		    waveform,
+		} => ac.add_independent_voltage_source_waveform(name, term_pos, term_neg, waveform)?,
+		Element::CurrentSource {
+		    name,
+		    term_pos,
+		    term_neg,
+		    waveform,
+		    group2,
+		} => ac.add_independent_current_source_waveform(
+		    name,
+		    term_pos,
+		    term_neg,
+		    current_edge(&controls, name, *group2).is_some(),
+		    waveform,
+		)?,
+		Element::Vcvs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    gain,
+		} => ac.add_voltage_controlled_voltage_source(
+		    name, term_pos, term_neg, ctrl_pos, ctrl_neg, *gain,
+		)?,
+		Element::Ccvs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    transresistance,
+		} => ac.add_current_controlled_voltage_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    *transresistance,
+		)?,
+		Element::Vccs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    transconductance,
+		    group2,
+		} => ac.add_voltage_controlled_current_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *transconductance,
+		)?,
+		Element::Cccs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    gain,
+		    group2,
+		} => ac.add_current_controlled_current_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *gain,
+		)?,
+		Element::Diode { name, .. }
+		| Element::Bjt { name, .. }
+		| Element::Mosfet { name, .. } => return Err(nonlinear_element(name)),
+		Element::MutualInductance { .. } => (),
+	    }
+	}
+	for (name, inductor_1, inductor_2, coupling) in self.mutual_inductances() {
+	    ac.add_mutual_inductance(name, inductor_1, inductor_2, coupling)?;
+	}
+	Ok(())
+    }
+
+    /// Make a circuit of devices, for analyses that rebuild the system
+    /// more than once. Inductor currents are always in group 2.
+    pub fn circuit(&self) -> Result<Circuit, AcdcError> {
+	self.circuit_with_edges(self.controlling_elements())
+    }
+
+    /// Make the sensitivity analysis requested by the first `.sens
+    /// V(out[,ref])|I(element) [ac dec|oct|lin N fstart fstop]` line,
+    /// for the derivative of a voltage or current with respect to every
+    /// element value and model parameter (see [sens](crate::sens)). Its
+    /// frequencies, if it is an AC sensitivity analysis, are in the
+    /// [Analysis::Sens] line.
+    pub fn sensitivity_analysis(&self) -> Result<SensitivityAnalysis, AcdcError> {
+	let output = self
+	    .analyses
+	    .iter()
+	    .find_map(|analysis| match analysis {
+		Analysis::Sens { output, .. } => Some(output),
+		_ => None,
+	    })
+	    .ok_or_else(|| ParseError {
+		line: None,
+		message: String::from("no .sens line in netlist"),
+	    })?;
+	// An output element needs a current edge
+	let mut edges = self.controlling_elements();
+	if let OutputVariable::Current(element) = output {
+	    edges.insert(element.as_str());
+	}
+	let mut sens = SensitivityAnalysis::new(self.circuit_with_edges(edges)?, output.clone())?;
+	sens.set_options(self.newton_options());
+	Ok(sens)
+    }
+
+    /// Make a circuit in which the named elements have their current
+    /// in group 2
+    fn circuit_with_edges(&self, controls: HashSet<&str>) -> Result<Circuit, AcdcError> {
+	let mut circuit = Circuit::new();
+	for element in self.elements.iter() {
+	    match element {
+		Element::Resistor {
+		    name,
+		    term_1,
+		    term_2,
+		    resistance,
+		    group2,
+		} => circuit.add_resistor(
+		    name,
+		    term_1,
+		    term_2,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *resistance,
+		)?,
+		Element::Capacitor {
+		    name,
+		    term_1,
+		    term_2,
+		    capacitance,
+		    group2,
+		} => circuit.add_capacitor(
+		    name,
+		    term_1,
+		    term_2,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *capacitance,
+		)?,
+		Element::Inductor {
+		    name,
+		    term_1,
+		    term_2,
+		    inductance,
+		    ..
+		} => circuit.add_inductor(name, term_1, term_2, *inductance)?,
+		Element::VoltageSource {
+		    name,
+		    term_pos,
+		    term_neg,
+		    waveform,
+		} => circuit.add_independent_voltage_source_waveform(
+		    name,
+		    term_pos,
+		    term_neg,
+		    waveform.clone(),
+		)?,
+		Element::CurrentSource {
+		    name,
+		    term_pos,
+		    term_neg,
+		    waveform,
+		    group2,
+		} => circuit.add_independent_current_source_waveform(
+		    name,
+		    term_pos,
+		    term_neg,
+		    current_edge(&controls, name, *group2).is_some(),
+		    waveform.clone(),
+		)?,
+		Element::Vcvs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    gain,
+		} => circuit.add_voltage_controlled_voltage_source(
+		    name, term_pos, term_neg, ctrl_pos, ctrl_neg, *gain,
+		)?,
+		Element::Ccvs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    transresistance,
+		} => circuit.add_current_controlled_voltage_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    *transresistance,
+		)?,
+		Element::Vccs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    transconductance,
+		    group2,
+		} => circuit.add_voltage_controlled_current_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_pos,
+		    ctrl_neg,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *transconductance,
+		)?,
+		Element::Cccs {
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    gain,
+		    group2,
+		} => circuit.add_current_controlled_current_source(
+		    name,
+		    term_pos,
+		    term_neg,
+		    ctrl_element,
+		    current_edge(&controls, name, *group2).is_some(),
+		    *gain,
+		)?,
+		Element::Diode {
+		    name,
+		    anode,
+		    cathode,
+		    model,
+		    area,
+		} => circuit.add_diode(name, anode, cathode, self.diode_model(model)?, *area)?,
+		Element::Bjt {
+		    name,
+		    collector,
+		    base,
+		    emitter,
+		    model,
+		    area,
+		} => circuit.add_bjt(
+		    name,
+		    collector,
+		    base,
+		    emitter,
+		    self.bjt_model(model)?,
+		    *area,
+		)?,
+		Element::Mosfet {
+		    name,
+		    drain,
+		    gate,
+		    source,
+		    bulk,
+		    model,
+		    w,
+		    l,
+		} => circuit.add_mosfet(
+		    name,
+		    drain,
+		    gate,
+		    source,
+		    bulk,
+		    self.mosfet_model(model)?,
+		    *w,
+		    *l,
+		)?,
+		Element::MutualInductance { .. } => (),
+	    }
+	}
+	for (name, inductor_1, inductor_2, coupling) in self.mutual_inductances() {
+	    circuit.add_mutual_inductance(name, inductor_1, inductor_2, coupling)?;
+	}
+	Ok(circuit)
+    }
+
+    /// Make the DC sweep requested by the first `.dc var start stop
+    /// step` line, of a source, the temperature (`temp`, in Celsius) or
+    /// a parameter. The range may instead be `dec|oct|lin N start stop`
+    /// or `list values...`, and a second variable and range make an
+    /// outer sweep, giving a family of curves.
+    pub fn dc_sweep(&self) -> Result<DcSweep, AcdcError> {
+	let sweeps = self
+	    .analyses
+	    .iter()
+	    .find_map(|analysis| match analysis {
+		Analysis::Dc { sweeps } => Some(sweeps),
+		_ => None,
+	    })
+	    .ok_or_else(|| ParseError {
+		line: None,
+		message: String::from("no .dc line in netlist"),
+	    })?;
+	let (variable, range) = &sweeps[0];
+	let mut dc = DcSweep::new(self.circuit()?, self.sweep_variable(variable)?, range)?;
+	if let Some((variable, range)) = sweeps.get(1) {
+	    dc.set_outer_sweep(self.sweep_variable(variable)?, range)?;
+	}
+	dc.set_options(self.newton_options());
+	Ok(dc)
+    }
+
+    /// Variable of a `.dc` sweep: an independent source, `temp`, or a
+    /// parameter, which is swept by evaluating the deck again with each
+    /// value
+    fn sweep_variable(&self, name: &str) -> Result<SweepVariable, AcdcError> {
+	if name == "temp" {
+	    return Ok(SweepVariable::Temperature);
+	}
+	let is_source = self.elements.iter().any(|element| {
+	    matches!(
+		element,
+		Element::VoltageSource { .. } | Element::CurrentSource { .. }
+	    ) && element.name() == name
+	});
+	if is_source {
+	    return Ok(SweepVariable::Source(String::from(name)));
+	}
+	if !self.params.contains_key(name) {
+	    return Err(AcdcError::InvalidAnalysis(format!(
+		"{} is not a source, temp or a parameter",
+		name
+	    )));
+	}
+	let source = self.source.clone();
+	let overrides = self.overrides.clone();
+	let param = String::from(name);
+	Ok(SweepVariable::Parameter {
+	    name: String::from(name),
+	    build: Box::new(move |value| {
+		let mut overrides = overrides.clone();
+		overrides.insert(param.clone(), value);
+		Self::parse_with_params(&source, &overrides)?.circuit()
+	    }),
+	})
+    }
+
+    /// Make the noise analysis requested by the first `.noise
+    /// V(out[,ref]) src dec|oct|lin N fstart fstop` line, for the noise
+    /// at V(out) (or V(out) - V(ref)) over a sweep like that of
+    /// [ac_sweep](Self::ac_sweep), referred to the input by the gain
+    /// from the independent source src (see [noise](crate::noise))
+    pub fn noise_analysis(&self) -> Result<NoiseAnalysis, AcdcError> {
+	let (output, reference, input, sweep, num_points, f_start, f_end) = self
+	    .analyses
+	    .iter()
+	    .find_map(|analysis| match analysis {
+		Analysis::Noise {
+		    output,
+		    reference,
+		    input,
+		    sweep,
+		    num_points,
+		    f_start,
+		    f_end,
+		} => Some((output, reference, input, *sweep, *num_points, *f_start, *f_end)),
+		_ => None,
+	    })
+	    .ok_or_else(|| ParseError {
+		line: None,
+		message: String::from("no .noise line in netlist"),
+	    })?;
+	let f = sweep_frequencies(sweep, num_points, f_start, f_end)?;
+	let mut noise = NoiseAnalysis::new(self.circuit()?, output, reference, input, f)?;
+	noise.set_options(self.newton_options());
+	Ok(noise)
+    }
+
+    /// Make a DC operating point analysis of the circuit (as requested
+    /// by `.op`), which may contain nonlinear devices
+    pub fn nonlinear_dc_analysis(&self) -> Result<NonlinearDcAnalysis, AcdcError> {
+	let mut dc = NonlinearDcAnalysis::new(self.circuit()?);
+	dc.set_options(self.newton_options());
+	Ok(dc)
+    }
+
+    /// Make the transient analysis requested by the first `.tran tstep
+    /// tstop [tstart [tmax]] [UIC]` line, from time zero to tstop,
+    /// never taking a step longer than tmax (by default tstep, or tstop
+    /// / 50 if that is shorter) and recording the solution from tstart.
+    /// The `.ic V(node)=value ...` voltages are held while the
+    /// operating point is found or, with `UIC`, used directly as the
+    /// initial solution.
+    pub fn transient_analysis(&self) -> Result<TransientAnalysis, AcdcError> {
+	let (step, stop, start, max_step, uic) = self
+	    .analyses
+	    .iter()
+	    .find_map(|analysis| match analysis {
+		Analysis::Tran {
+		    step,
+		    stop,
+		    start,
+		    max_step,
+		    uic,
+		} => Some((*step, *stop, *start, *max_step, *uic)),
+		_ => None,
+	    })
+	    .ok_or_else(|| ParseError {
+		line: None,
+		message: String::from("no .tran line in netlist"),
+	    })?;
+	let mut tran = TransientAnalysis::new(self.circuit()?, step, stop);
+	let mut options = self.options.clone();
+	if max_step.is_some() {
+	    options.max_step = max_step;
+	}
+	tran.set_options(options);
+	tran.set_start(start);
+	tran.use_initial_conditions(uic);
+	for (node, voltage) in self.initial_conditions.iter() {
+	    tran.set_initial_condition(node, *voltage)?;
+	}
+	Ok(tran)
+    }
+}
+
+/// Split the deck into logical lines, removing comments and blank
+/// lines and joining continuation lines. Each logical line is returned
+/// with the (1-based) number of the line it started on.
+fn logical_lines(input: &str) -> Result<Vec<(usize, String)>, ParseError> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (n, text) in input.lines().enumerate() {
+	let line = n + 1;
+	let text = text.trim();
+	if text.is_empty() || text.starts_with('*') || text.starts_with('#') {
+	    continue;
+	}
+	if let Some(rest) = text.strip_prefix('+') {
+	    match lines.last_mut() {
+		Some((_, previous)) => {
+		    previous.push(' ');
+		    previous.push_str(rest);
+		}
+		None => {
+		    return Err(ParseError::new(
+			line,
+			String::from("continuation line with nothing to continue"),
+		    ))
+		}
+	    }
+	} else {
+	    lines.push((line, String::from(text)));
+	}
+    }
+    Ok(lines)
+}
+
+/// Parse `name=value ...` parameter definitions, where each value is
+/// an expression, in braces if it contains spaces
+fn param_definitions(line: usize, text: &str) -> Result<Vec<(String, Expr)>, ParseError> {
+    static DEFINITION: OnceLock<Regex> = OnceLock::new();
+    let re = DEFINITION.get_or_init(|| {
+	Regex::new(r"^\s*([A-Za-z_][A-Za-z0-9_]*)\s*=\s*(\{[^}]*\}|[^\s{}]+)").unwrap()
+    });
+    let mut definitions = Vec::new();
+    let mut rest = text.trim_end();
+    while !rest.is_empty() {
+	let captures = re.captures(rest).ok_or_else(|| {
+	    ParseError::new(
+		line,
+		format!("expected name=value, found {}", rest.trim_start()),
+	    )
+	})?;
+	let value = &captures[2];
+	let value = value
+	    .strip_prefix('{')
+	    .and_then(|v| v.strip_suffix('}'))
+	    .unwrap_or(value);
+	let expr = Expr::parse(value).map_err(|message| ParseError::new(line, message))?;
+	definitions.push((captures[1].to_ascii_lowercase(), expr));
+	rest = &rest[captures.get(0).unwrap().end()..];
+    }
+    Ok(definitions)
+}
+
+/// Replace each `{expression}` in a line with its value
+fn substitute(line: usize, text: &str, params: &HashMap<String, f64>) -> Result<String, ParseError> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+	let end = rest[start..]
+	    .find('}')
+	    .ok_or_else(|| ParseError::new(line, String::from("missing } in expression")))?;
+	let expr = Expr::parse(&rest[start + 1..start + end])
+	    .map_err(|message| ParseError::new(line, message))?;
+	let value = expr
+	    .eval(params)
+	    .map_err(|message| ParseError::new(line, message))?;
+	result.push_str(&rest[..start]);
+	result.push_str(&value.to_string());
+	rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Pattern for `V(out[,ref])` or `I(element)`, capturing out, ref and
+/// element as groups 1 to 3
+const OUTPUT_VARIABLE: &str = r"(?:v\(\s*([^,\s)]+)\s*(?:,\s*([^\s)]+)\s*)?\)|i\(\s*([^\s)]+)\s*\))";
+
+/// Output variable matched by [OUTPUT_VARIABLE], where the reference
+/// node is ground if it is not given
+fn output_variable(captures: &Captures) -> OutputVariable {
+    match captures.get(3) {
+	Some(element) => OutputVariable::Current(element.as_str().to_ascii_lowercase()),
+	None => OutputVariable::Voltage(
+	    String::from(&captures[1]),
+	    String::from(captures.get(2).map_or("0", |m| m.as_str())),
+	),
+    }
+}
+
+/// The current edge of an element, if it is in group 2
+fn current_edge<'a>(controls: &HashSet<&str>, name: &'a str, group2: bool) -> Option<&'a str> {
+    if group2 || controls.contains(name) {
+	Some(name)
+    } else {
+	None
+    }
+}
+
+/// Remove a trailing group 2 flag from the token list, returning
+/// whether it was present
+fn in_group2(tokens: &mut Vec<&str>) -> bool {
+    if tokens.len() > 1 && tokens.last().unwrap().eq_ignore_ascii_case("G2") {
+	tokens.pop();
+	true
+    } else {
+	false
+    }
+}
+
+/// Error for a nonlinear element in a linear analysis
+fn nonlinear_element(name: &str) -> AcdcError {
+    AcdcError::InvalidAnalysis(format!(
+	"{} is nonlinear, so it needs a nonlinear analysis",
+	name
+    ))
+}
+
+/// Keywords that start a part of an independent source value
+const SOURCE_KEYWORDS: [&str; 7] = ["dc", "ac", "pulse", "sin", "exp", "pwl", "sffm"];
+
+/// Make a transient source function from its (already parsed) arguments
+fn transient_function(line: usize, kind: &str, values: &[f64]) -> Result<TransientFunction, ParseError> {
+    let (min, max) = match kind {
+	"pulse" => (2, 7),
+	"sin" => (3, 6),
+	"exp" => (4, 6),
+	"sffm" => (5, 5),
+	_ => (2, usize::MAX),
+    };
+    if values.len() < min || values.len() > max {
+	return Err(ParseError::new(
+	    line,
+	    format!("wrong number of arguments ({}) for {}", values.len(), kind.to_ascii_uppercase()),
+	));
+    }
+    let arg = |n: usize, default: f64| values.get(n).copied().unwrap_or(default);
+    let function = match kind {
+	"pulse" => TransientFunction::Pulse {
+	    v1: values[0],
+	    v2: values[1],
+	    delay: arg(2, 0.0),
+	    rise: arg(3, 0.0),
+	    fall: arg(4, 0.0),
+	    width: arg(5, f64::INFINITY),
+	    period: arg(6, f64::INFINITY),
+	},
+	"sin" => TransientFunction::Sin {
+	    offset: values[0],
+	    amplitude: values[1],
+	    freq: values[2],
+	    delay: arg(3, 0.0),
+	    damping: arg(4, 0.0),
+	    phase: arg(5, 0.0),
+	},
+	"exp" => TransientFunction::Exp {
+	    v1: values[0],
+	    v2: values[1],
+	    rise_delay: values[2],
+	    rise_tau: values[3],
+	    fall_delay: arg(4, f64::INFINITY),
+	    fall_tau: arg(5, values[3]),
+	},
+	"sffm" => TransientFunction::Sffm {
+	    offset: values[0],
+	    amplitude: values[1],
+	    carrier_freq: values[2],
+	    modulation_index: values[3],
+	    signal_freq: values[4],
+	},
+	_ => {
+	    if values.len() % 2 != 0 {
+		return Err(ParseError::new(
+		    line,
+		    String::from("PWL needs (time, value) pairs"),
+		));
+	    }
+	    let points: Vec<(f64, f64)> = values.chunks(2).map(|p| (p[0], p[1])).collect();
+	    if points.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+		return Err(ParseError::new(
+		    line,
+		    String::from("PWL times must be in increasing order"),
+		));
+	    }
+	    TransientFunction::Pwl(points)
+	}
+    };
+    Ok(function)
+}
+
+/// Check that a source has two nodes and a value
+fn expect_source_args(line: usize, name: &str, args: &[&str]) -> Result<(), ParseError> {
+    if args.len() < 3 {
+	Err(ParseError::new(
+	    line,
+	    format!("expected nodes and value after {}", name),
+	))
+    } else {
+	Ok(())
+    }
+}
+
+/// Check the number of tokens following the element name
+fn expect_args(line: usize, name: &str, args: &[&str], num: usize) -> Result<(), ParseError> {
+    if args.len() != num {
+	Err(ParseError::new(
+	    line,
+	    format!("expected {} fields after {}, found {}", num, name, args.len()),
+	))
+    } else {
+	Ok(())
+    }
+}
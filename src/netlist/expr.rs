@@ -0,0 +1,556 @@
+//! Parameter expressions
+//!
+//! Values written in braces, like `{2 * r + 1k}`, and the values of
+//! `.param` statements are expressions. They may use numbers (with
+//! scale factors but not units, see [value](super::value)),
+//! parameters, the operators `+ - * /` and `^` (or `**`) for powers,
+//! parentheses, the constant `pi`, and the functions
+//!
+//! | Arguments | Functions |
+//! |-----------|-----------|
+//! | one       | `abs sqrt exp ln log log10 sin cos tan asin acos atan sinh cosh tanh floor ceil round int sgn` |
+//! | two       | `min max pow atan2 hypot` |
+//!
+//! where `log` is the natural logarithm, as in SPICE. Parameter and
+//! function names are case-insensitive.
+
+use std::collections::{HashMap, HashSet};
+
+use super::value::{is_scale_factor, parse_value};
+
+/// Binary operator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+/// Parsed expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// Parameter, by (lower case) name
+    Param(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    /// Function call, by (lower case) name
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Op(Op),
+    Open,
+    Close,
+    Comma,
+}
+
+impl Expr {
+    /// Parse an expression (without the surrounding braces)
+    pub fn parse(text: &str) -> Result<Self, String> {
+	let tokens = tokenize(text)?;
+	let mut parser = Parser { tokens, pos: 0 };
+	let expr = parser.sum()?;
+	match parser.tokens.get(parser.pos) {
+	    None => Ok(expr),
+	    Some(_) => Err(format!("unexpected {} in expression {}", parser.describe(), text)),
+	}
+    }
+
+    /// Evaluate the expression, given the parameter values
+    pub fn eval(&self, params: &HashMap<String, f64>) -> Result<f64, String> {
+	let value = match self {
+	    Expr::Number(x) => *x,
+	    Expr::Param(name) => match params.get(name) {
+		Some(x) => *x,
+		None if name == "pi" => std::f64::consts::PI,
+		None => return Err(format!("unknown parameter {}", name)),
+	    },
+	    Expr::Neg(x) => -x.eval(params)?,
+	    Expr::Binary(op, a, b) => {
+		let (a, b) = (a.eval(params)?, b.eval(params)?);
+		match op {
+		    Op::Add => a + b,
+		    Op::Sub => a - b,
+		    Op::Mul => a * b,
+		    Op::Div => a / b,
+		    Op::Pow => a.powf(b),
+		}
+	    }
+	    Expr::Call(name, args) => {
+		let args = args
+		    .iter()
+		    .map(|arg| arg.eval(params))
+		    .collect::<Result<Vec<f64>, String>>()?;
+		call(name, &args)?
+	    }
+	};
+	if value.is_finite() {
+	    Ok(value)
+	} else {
+	    Err(String::from("expression does not evaluate to a finite number"))
+	}
+    }
+
+    /// Names of the parameters the expression refers to
+    pub fn params(&self) -> HashSet<&str> {
+	let mut names = HashSet::new();
+	self.collect_params(&mut names);
+	names
+    }
+
+    fn collect_params<'a>(&'a self, names: &mut HashSet<&'a str>) {
+	match self {
+	    Expr::Number(_) => (),
+	    Expr::Param(name) => {
+		names.insert(name);
+	    }
+	    Expr::Neg(x) => x.collect_params(names),
+	    Expr::Binary(_, a, b) => {
+		a.collect_params(names);
+		b.collect_params(names);
+	    }
+	    Expr::Call(_, args) => {
+		for arg in args {
+		    arg.collect_params(names);
+		}
+	    }
+	}
+    }
+}
+
+fn call(name: &str, args: &[f64]) -> Result<f64, String> {
+    let value = match (name, args) {
+	("abs", [x]) => x.abs(),
+	("sqrt", [x]) => x.sqrt(),
+	("exp", [x]) => x.exp(),
+	("ln" | "log", [x]) => x.ln(),
+	("log10", [x]) => x.log10(),
+	("sin", [x]) => x.sin(),
+	("cos", [x]) => x.cos(),
+	("tan", [x]) => x.tan(),
+	("asin", [x]) => x.asin(),
+	("acos", [x]) => x.acos(),
+	("atan", [x]) => x.atan(),
+	("sinh", [x]) => x.sinh(),
+	("cosh", [x]) => x.cosh(),
+	("tanh", [x]) => x.tanh(),
+	("floor", [x]) => x.floor(),
+	("ceil", [x]) => x.ceil(),
+	("round", [x]) => x.round(),
+	("int", [x]) => x.trunc(),
+	("sgn", [x]) => {
+	    if *x == 0.0 {
+		0.0
+	    } else {
+		x.signum()
+	    }
+	}
+	("min", [x, y]) => x.min(*y),
+	("max", [x, y]) => x.max(*y),
+	("pow", [x, y]) => x.powf(*y),
+	("atan2", [y, x]) => y.atan2(*x),
+	("hypot", [x, y]) => x.hypot(*y),
+	_ => {
+	    return Err(format!(
+		"unknown function {} (with {} arguments)",
+		name,
+		args.len()
+	    ))
+	}
+    };
+    Ok(value)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+	let c = chars[i];
+	let start = i;
+	i += 1;
+	let token = match c {
+	    ' ' | '\t' => continue,
+	    '+' => Token::Op(Op::Add),
+	    '-' => Token::Op(Op::Sub),
+	    '*' if chars.get(i) == Some(&'*') => {
+		i += 1;
+		Token::Op(Op::Pow)
+	    }
+	    '*' => Token::Op(Op::Mul),
+	    '/' => Token::Op(Op::Div),
+	    '^' => Token::Op(Op::Pow),
+	    '(' => Token::Open,
+	    ')' => Token::Close,
+	    ',' => Token::Comma,
+	    '0'..='9' | '.' => {
+		while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+		    i += 1;
+		}
+		// Exponent, then scale factor and units
+		if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+		    let digits = match chars.get(i + 1) {
+			Some('+' | '-') => i + 2,
+			_ => i + 1,
+		    };
+		    if matches!(chars.get(digits), Some(c) if c.is_ascii_digit()) {
+			i = digits;
+			while i < chars.len() && chars[i].is_ascii_digit() {
+			    i += 1;
+			}
+		    }
+		}
+		let suffix_start = i;
+		while i < chars.len() && chars[i].is_ascii_alphabetic() {
+		    i += 1;
+		}
+		// Units would be ambiguous with parameter names, so only a
+		// scale factor may follow the number
+		let suffix: String = chars[suffix_start..i].iter().collect();
+		if !suffix.is_empty() && !is_scale_factor(&suffix) {
+		    return Err(format!(
+			"unknown scale factor {} in expression {}",
+			suffix, text
+		    ));
+		}
+		let number: String = chars[start..i].iter().collect();
+		let value = parse_value(&number).map_err(|error| error.message)?;
+		Token::Number(value.value)
+	    }
+	    c if c.is_ascii_alphabetic() || c == '_' => {
+		while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+		    i += 1;
+		}
+		let name: String = chars[start..i].iter().collect();
+		Token::Name(name.to_ascii_lowercase())
+	    }
+	    c => return Err(format!("unexpected {} in expression {}", c, text)),
+	};
+	tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, from the lowest precedence (sums) to the
+/// highest (numbers, parameters, calls and parentheses)
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn advance(&mut self) -> Option<Token> {
+	let token = self.tokens.get(self.pos).cloned();
+	self.pos += 1;
+	token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+	self.tokens.get(self.pos)
+    }
+
+    fn describe(&self) -> String {
+	match self.peek() {
+	    None => String::from("end of expression"),
+	    Some(Token::Number(x)) => format!("number {}", x),
+	    Some(Token::Name(name)) => String::from(name),
+	    Some(Token::Op(_)) => String::from("operator"),
+	    Some(Token::Open) => String::from("("),
+	    Some(Token::Close) => String::from(")"),
+	    Some(Token::Comma) => String::from(","),
+	}
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+	if self.peek() == Some(&token) {
+	    self.pos += 1;
+	    Ok(())
+	} else {
+	    Err(format!("unexpected {} in expression", self.describe()))
+	}
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+	let mut expr = self.product()?;
+	while let Some(Token::Op(op @ (Op::Add | Op::Sub))) = self.peek().cloned() {
+	    self.pos += 1;
+	    expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+	}
+	Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+	let mut expr = self.unary()?;
+	while let Some(Token::Op(op @ (Op::Mul | Op::Div))) = self.peek().cloned() {
+	    self.pos += 1;
+	    expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+	}
+	Ok(expr)
+    }
+
+    /// Signs bind less tightly than powers, so -2^2 is -4
+    fn unary(&mut self) -> Result<Expr, String> {
+	match self.peek() {
+	    Some(Token::Op(Op::Sub)) => {
+		self.pos += 1;
+		Ok(Expr::Neg(Box::new(self.unary()?)))
+	    }
+	    Some(Token::Op(Op::Add)) => {
+		self.pos += 1;
+		self.unary()
+	    }
+	    _ => self.power(),
+	}
+    }
+
+    /// Powers are right-associative, so 2^3^2 is 2^9
+    fn power(&mut self) -> Result<Expr, String> {
+	let base = self.primary()?;
+	if self.peek() == Some(&Token::Op(Op::Pow)) {
+	    self.pos += 1;
+	    let exponent = self.unary()?;
+	    return Ok(Expr::Binary(Op::Pow, Box::new(base), Box::new(exponent)));
+	}
+	Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+	let description = self.describe();
+	match self.advance() {
+	    Some(Token::Number(x)) => Ok(Expr::Number(x)),
+	    Some(Token::Name(name)) => {
+		if self.peek() != Some(&Token::Open) {
+		    return Ok(Expr::Param(name));
+		}
+		self.pos += 1;
+		let mut args = Vec::new();
+		if self.peek() != Some(&Token::Close) {
+		    args.push(self.sum()?);
+		    while self.peek() == Some(&Token::Comma) {
+			self.pos += 1;
+			args.push(self.sum()?);
+		    }
+		}
+		self.expect(Token::Close)?;
+		Ok(Expr::Call(name, args))
+	    }
+	    Some(Token::Open) => {
+		let expr = self.sum()?;
+		self.expect(Token::Close)?;
+		Ok(expr)
+	    }
+	    _ => Err(format!("unexpected {} in expression", description)),
+	}
+    }
+}
+
+/// Evaluate parameter definitions, in whatever order their references
+/// allow, adding them to params (which holds the values already known,
+/// and is not overwritten). If a parameter is defined more than once,
+/// the last definition is used. An error gives the position in
+/// definitions of the definition that could not be evaluated.
+pub fn evaluate_params(
+    definitions: &[(String, Expr)],
+    params: &mut HashMap<String, f64>,
+) -> Result<(), (usize, String)> {
+    let mut pending: Vec<usize> = Vec::new();
+    for (n, (name, _)) in definitions.iter().enumerate().rev() {
+	if !params.contains_key(name) && !pending.iter().any(|&other| definitions[other].0 == *name) {
+	    pending.push(n);
+	}
+    }
+    pending.reverse();
+    while !pending.is_empty() {
+	// A definition is ready once it refers to no pending parameter
+	let ready = pending.iter().position(|&n| {
+	    definitions[n]
+		.1
+		.params()
+		.iter()
+		.all(|param| !pending.iter().any(|&other| definitions[other].0 == *param))
+	});
+	let n = match ready {
+	    Some(ready) => pending.remove(ready),
+	    None => {
+		let names: Vec<&str> = pending.iter().map(|&n| definitions[n].0.as_str()).collect();
+		return Err((
+		    pending[0],
+		    format!("circular parameter definitions ({})", names.join(", ")),
+		));
+	    }
+	};
+	let (name, expr) = &definitions[n];
+	let value = expr
+	    .eval(params)
+	    .map_err(|message| (n, format!("parameter {}: {}", name, message)))?;
+	params.insert(name.clone(), value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlist::{Element, Netlist};
+
+    fn eval(text: &str) -> Result<f64, String> {
+	Expr::parse(text)?.eval(&HashMap::new())
+    }
+
+    fn assert_eval(text: &str, expected: f64) {
+	let value = eval(text).unwrap();
+	assert!(
+	    (value - expected).abs() <= 1e-12 * expected.abs().max(1.0),
+	    "{} evaluated to {}, not {}",
+	    text,
+	    value,
+	    expected
+	);
+    }
+
+    /// Resistance of the named resistor in a netlist
+    fn resistance(netlist: &Netlist, name: &str) -> f64 {
+	netlist
+	    .elements()
+	    .iter()
+	    .find_map(|element| match element {
+		Element::Resistor {
+		    name: n,
+		    resistance,
+		    ..
+		} if n == name => Some(*resistance),
+		_ => None,
+	    })
+	    .unwrap()
+    }
+
+    #[test]
+    fn scale_factors_but_not_units() {
+	assert_eval("2k", 2e3);
+	assert_eval("1meg", 1e6);
+	assert_eval("1.5e3", 1.5e3);
+	assert!(eval("2r").is_err());
+	assert!(eval("1kohm").is_err());
+	assert!(eval("1e").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+	assert_eval("1 + 2 * 3", 7.0);
+	assert_eval("(1 + 2) * 3", 9.0);
+	assert_eval("10 - 2 - 3", 5.0);
+	assert_eval("8 / 2 / 2", 2.0);
+	assert_eval("-2^2", -4.0);
+	assert_eval("2^3^2", 512.0);
+	assert_eval("2**3", 8.0);
+	assert_eval("2 * -3", -6.0);
+	assert!(eval("1 +").is_err());
+	assert!(eval("(1 + 2").is_err());
+	assert!(eval("1 2").is_err());
+    }
+
+    #[test]
+    fn functions() {
+	assert_eval("sqrt(16)", 4.0);
+	assert_eval("max(1, 2) + min(1, 2)", 3.0);
+	assert_eval("log(exp(2))", 2.0);
+	assert_eval("log10(1k)", 3.0);
+	assert_eval("atan2(1, 1)", std::f64::consts::FRAC_PI_4);
+	assert_eval("sgn(0) + sgn(-3)", -1.0);
+	assert_eval("int(-2.5)", -2.0);
+	assert_eval("2 * pi", 2.0 * std::f64::consts::PI);
+	assert_eval("SQRT(4)", 2.0);
+	assert!(eval("foo(1)").is_err());
+	assert!(eval("sqrt(1, 2)").is_err());
+	assert!(eval("sqrt(-1)").is_err());
+	assert!(eval("1 / 0").is_err());
+    }
+
+    #[test]
+    fn parameter_references() {
+	let expr = Expr::parse("2 * Rload + r1").unwrap();
+	assert_eq!(expr.params(), HashSet::from(["rload", "r1"]));
+	let params = HashMap::from([(String::from("rload"), 1e3), (String::from("r1"), 500.0)]);
+	assert_eq!(expr.eval(&params).unwrap(), 2500.0);
+	assert!(expr.eval(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn parameters_in_any_order() {
+	let definitions: Vec<(String, Expr)> = [("a", "b + 1"), ("b", "2 * c"), ("c", "3")]
+	    .iter()
+	    .map(|(name, text)| (String::from(*name), Expr::parse(text).unwrap()))
+	    .collect();
+	let mut params = HashMap::from([(String::from("c"), 5.0)]);
+	evaluate_params(&definitions, &mut params).unwrap();
+	// c is already known, so its definition is not used
+	assert_eq!(params["c"], 5.0);
+	assert_eq!(params["b"], 10.0);
+	assert_eq!(params["a"], 11.0);
+
+	let circular: Vec<(String, Expr)> = [("a", "b"), ("b", "a")]
+	    .iter()
+	    .map(|(name, text)| (String::from(*name), Expr::parse(text).unwrap()))
+	    .collect();
+	assert!(evaluate_params(&circular, &mut HashMap::new()).is_err());
+    }
+
+    /// An error in a parameter definition is reported on its line
+    #[test]
+    fn parameter_error_line() {
+	let error = Netlist::parse(
+	    ".param a=1 b=2
+	     .param c={d + 1}
+	     R1 1 0 {a}",
+	)
+	.unwrap_err();
+	assert_eq!(error.line, Some(2));
+	assert!(error.message.contains("parameter c"), "{}", error.message);
+    }
+
+    #[test]
+    fn subcircuit_overrides() {
+	let netlist = Netlist::parse(
+	    ".param r=1k
+	     .subckt stage in out params: r=2k
+	     R1 in out {r}
+	     R2 out 0 {2 * r}
+	     .ends
+	     V1 a 0 1
+	     X1 a b stage
+	     X2 b c stage r=3k
+	     R3 c 0 {r}",
+	)
+	.unwrap();
+	// Instance parameters take precedence over .param lines
+	assert_eq!(resistance(&netlist, "x1.r1"), 2e3);
+	assert_eq!(resistance(&netlist, "x1.r2"), 4e3);
+	assert_eq!(resistance(&netlist, "x2.r1"), 3e3);
+	assert_eq!(resistance(&netlist, "x2.r2"), 6e3);
+	assert_eq!(resistance(&netlist, "r3"), 1e3);
+    }
+
+    #[test]
+    fn set_param_re_evaluates() {
+	let mut netlist = Netlist::parse(
+	    ".param r=1k
+	     .param r2={2 * r}
+	     V1 a 0 1
+	     R1 a b {r}
+	     R2 b 0 {r2}",
+	)
+	.unwrap();
+	assert_eq!(resistance(&netlist, "r2"), 2e3);
+	for r in [2e3, 5e3] {
+	    netlist.set_param("R", r).unwrap();
+	    assert_eq!(resistance(&netlist, "r1"), r);
+	    assert_eq!(resistance(&netlist, "r2"), 2.0 * r);
+	}
+    }
+}
@@ -0,0 +1,419 @@
+//! Subcircuits
+//!
+//! A `.subckt` definition is a block of element lines with a list of
+//! ports and, optionally, default parameter values
+//!
+//! ```text
+//! .subckt stage in out params: r=1k c=1n
+//! R1 in mid {r}
+//! C1 mid 0 {c}
+//! E1 out 0 mid 0 10
+//! .ends
+//! ```
+//!
+//! An `X` line instantiates a subcircuit, connecting its ports to
+//! nodes in order, and may override its parameters
+//!
+//! ```text
+//! X1 a b stage r=2k
+//! ```
+//!
+//! Instances are flattened into the netlist. Elements and internal
+//! nodes of an instance are named after it, so the node `mid` in `X1`
+//! becomes `x1.mid` (and in an instance `X2` inside `X1`,
+//! `x1.x2.mid`), while ports become the nodes they connect to, and
+//! ground is shared. Expressions in braces are evaluated with the
+//! parameters of the instance and those of `.param` lines outside any
+//! subcircuit, where the instance parameters take precedence. Default
+//! values may be expressions too, and `.param` lines in the body add
+//! parameters. Controlling elements (of `F` and `H` sources) and
+//! coupled inductors (of `K` elements) refer to an element of the same
+//! subcircuit if there is one of that name.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::node_map::is_ground;
+
+use super::{
+    expr::{evaluate_params, Expr},
+    param_definitions, substitute, Element, Netlist, ParseError,
+};
+
+/// Subcircuit definition
+#[derive(Debug, Clone)]
+pub struct Subcircuit {
+    name: String,
+    ports: Vec<String>,
+    /// Parameters, by (lower case) name, with their default values
+    params: Vec<(String, Expr)>,
+    /// Lines of the body, with their line numbers in the deck
+    lines: Vec<(usize, String)>,
+}
+
+impl Subcircuit {
+    pub fn name(&self) -> &str {
+	&self.name
+    }
+
+    pub fn ports(&self) -> &[String] {
+	&self.ports
+    }
+
+    /// Add a line to the body
+    pub(super) fn push_line(&mut self, line: usize, text: &str) -> Result<(), ParseError> {
+	if text.starts_with('.') {
+	    let (keyword, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+	    if keyword.eq_ignore_ascii_case(".param") {
+		self.params.extend(param_definitions(line, rest)?);
+		return Ok(());
+	    }
+	    return Err(ParseError::new(
+		line,
+		format!("{} is not allowed inside .subckt", keyword),
+	    ));
+	}
+	self.lines.push((line, String::from(text)));
+	Ok(())
+    }
+
+    /// Names of the elements (and instances) in the body
+    fn element_names(&self) -> HashSet<String> {
+	self.lines
+	    .iter()
+	    .filter_map(|(_, text)| text.split_whitespace().next())
+	    .map(|name| name.to_ascii_lowercase())
+	    .collect()
+    }
+}
+
+/// Naming of the elements and nodes of one subcircuit instance (or of
+/// the top level of the netlist)
+pub(super) struct Scope {
+    /// Hierarchical name of the instance, empty at the top level
+    prefix: String,
+    /// Node connected to each port
+    ports: HashMap<String, String>,
+    /// Parameter values for expressions
+    params: HashMap<String, f64>,
+    /// Names of the elements defined in the same subcircuit
+    locals: HashSet<String>,
+}
+
+impl Scope {
+    pub(super) fn top(params: &HashMap<String, f64>) -> Self {
+	Self {
+	    prefix: String::new(),
+	    ports: HashMap::new(),
+	    params: params.clone(),
+	    locals: HashSet::new(),
+	}
+    }
+
+    fn element(&self, name: &str) -> String {
+	if self.prefix.is_empty() {
+	    String::from(name)
+	} else {
+	    format!("{}.{}", self.prefix, name)
+	}
+    }
+
+    fn node(&self, node: &str) -> String {
+	if is_ground(node) {
+	    return String::from(node);
+	}
+	match self.ports.get(node) {
+	    Some(actual) => actual.clone(),
+	    None => self.element(node),
+	}
+    }
+
+    /// Element named in another element of this scope
+    fn reference(&self, name: &str) -> String {
+	if self.locals.contains(name) {
+	    self.element(name)
+	} else {
+	    String::from(name)
+	}
+    }
+
+    /// Evaluate an expression that is not in braces
+    fn eval(&self, line: usize, text: &str) -> Result<f64, ParseError> {
+	Expr::parse(text)
+	    .and_then(|expr| expr.eval(&self.params))
+	    .map_err(|message| ParseError::new(line, message))
+    }
+}
+
+impl Netlist {
+    /// Parse a `.subckt name ports... [params:] name=value ...` line,
+    /// returning the definition with an empty body
+    pub(super) fn parse_subckt(&mut self, line: usize, text: &str) -> Result<Subcircuit, ParseError> {
+	// Words up to the first parameter are the keyword, the name and
+	// the ports
+	let mut words = Vec::new();
+	let mut rest = text.trim_start();
+	while !rest.is_empty() {
+	    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+	    let word = &rest[..end];
+	    if word.contains('=') || word.to_ascii_lowercase().starts_with("params:") {
+		break;
+	    }
+	    words.push(word);
+	    rest = rest[end..].trim_start();
+	}
+	if words.len() < 2 {
+	    return Err(ParseError::new(
+		line,
+		String::from("expected a name after .subckt"),
+	    ));
+	}
+	if rest.to_ascii_lowercase().starts_with("params:") {
+	    rest = &rest["params:".len()..];
+	}
+	Ok(Subcircuit {
+	    name: words[1].to_ascii_lowercase(),
+	    ports: words[2..].iter().map(|port| String::from(*port)).collect(),
+	    params: param_definitions(line, rest)?,
+	    lines: Vec::new(),
+	})
+    }
+
+    /// Finish a subcircuit definition at its `.ends` line
+    pub(super) fn define_subckt(&mut self, line: usize, subckt: Subcircuit) -> Result<(), ParseError> {
+	let name = subckt.name.clone();
+	if self.subcircuits.insert(name.clone(), subckt).is_some() {
+	    return Err(ParseError::new(
+		line,
+		format!("subcircuit {} is defined twice", name),
+	    ));
+	}
+	Ok(())
+    }
+
+    /// Flatten the instance `X name nodes... subckt [params:] name=value
+    /// ...` into the netlist, where the instance line is in scope and
+    /// stack lists the subcircuits being instantiated
+    pub(super) fn instantiate(
+	&mut self,
+	line: usize,
+	tokens: &[String],
+	scope: &Scope,
+	stack: &mut Vec<String>,
+    ) -> Result<(), ParseError> {
+	let name = tokens[0].to_ascii_lowercase();
+	let args: Vec<&str> = tokens[1..]
+	    .iter()
+	    .map(String::as_str)
+	    .filter(|t| !t.eq_ignore_ascii_case("params:"))
+	    .collect();
+	let num_nodes = match args.iter().position(|t| t.contains('=')).unwrap_or(args.len()) {
+	    0 => {
+		return Err(ParseError::new(
+		    line,
+		    format!("expected a subcircuit name after {}", name),
+		))
+	    }
+	    n => n - 1,
+	};
+	let subckt_name = args[num_nodes].to_ascii_lowercase();
+	let subckt = self.subcircuits.get(&subckt_name).cloned().ok_or_else(|| {
+	    ParseError::new(line, format!("subcircuit {} is not defined", subckt_name))
+	})?;
+	if num_nodes != subckt.ports.len() {
+	    return Err(ParseError::new(
+		line,
+		format!(
+		    "{} connects {} nodes, but {} has {} ports",
+		    name,
+		    num_nodes,
+		    subckt_name,
+		    subckt.ports.len()
+		),
+	    ));
+	}
+	if stack.contains(&subckt_name) {
+	    return Err(ParseError::new(
+		line,
+		format!("subcircuit {} instantiates itself", subckt_name),
+	    ));
+	}
+
+	// Parameters of the subcircuit hide those of .param lines
+	let mut params = self.params.clone();
+	for (key, _) in subckt.params.iter() {
+	    params.remove(key);
+	}
+	for param in args[num_nodes + 1..].iter() {
+	    let (key, value) = param.split_once('=').ok_or_else(|| {
+		ParseError::new(line, format!("expected name=value, found {}", param))
+	    })?;
+	    let key = key.to_ascii_lowercase();
+	    if !subckt.params.iter().any(|(defined, _)| *defined == key) {
+		return Err(ParseError::new(
+		    line,
+		    format!("subcircuit {} has no parameter {}", subckt_name, key),
+		));
+	    }
+	    params.insert(key, scope.eval(line, value)?);
+	}
+	evaluate_params(&subckt.params, &mut params).map_err(|(_, message)| {
+	    ParseError::new(line, format!("in {}, {}", name, message))
+	})?;
+
+	let inner = Scope {
+	    prefix: scope.element(&name),
+	    ports: subckt
+		.ports
+		.iter()
+		.cloned()
+		.zip(args[..num_nodes].iter().map(|node| scope.node(node)))
+		.collect(),
+	    params,
+	    locals: subckt.element_names(),
+	};
+	stack.push(subckt_name);
+	for (line, text) in subckt.lines.iter() {
+	    let tokens: Vec<String> = substitute(*line, text, &inner.params)?
+		.split_whitespace()
+		.map(String::from)
+		.collect();
+	    if tokens[0].starts_with(['x', 'X']) {
+		self.instantiate(*line, &tokens, &inner, stack)?;
+	    } else {
+		let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+		let mut element = self.parse_element(*line, &tokens)?;
+		element.flatten(&inner);
+		self.add_element(*line, element);
+	    }
+	}
+	stack.pop();
+	Ok(())
+    }
+}
+
+impl Element {
+    /// Rename the element, its nodes, and the elements it refers to,
+    /// for use in a subcircuit instance
+    fn flatten(&mut self, scope: &Scope) {
+	let rename_nodes = |nodes: &mut [&mut String]| {
+	    for node in nodes.iter_mut() {
+		**node = scope.node(node);
+	    }
+	};
+	match self {
+	    Element::Resistor {
+		name,
+		term_1,
+		term_2,
+		..
+	    }
+	    | Element::Capacitor {
+		name,
+		term_1,
+		term_2,
+		..
+	    }
+	    | Element::Inductor {
+		name,
+		term_1,
+		term_2,
+		..
+	    } => {
+		*name = scope.element(name);
+		rename_nodes(&mut [term_1, term_2]);
+	    }
+	    Element::VoltageSource {
+		name,
+		term_pos,
+		term_neg,
+		..
+	    }
+	    | Element::CurrentSource {
+		name,
+		term_pos,
+		term_neg,
+		..
+	    } => {
+		*name = scope.element(name);
+		rename_nodes(&mut [term_pos, term_neg]);
+	    }
+	    Element::Vcvs {
+		name,
+		term_pos,
+		term_neg,
+		ctrl_pos,
+		ctrl_neg,
+		..
+	    }
+	    | Element::Vccs {
+		name,
+		term_pos,
+		term_neg,
+		ctrl_pos,
+		ctrl_neg,
+		..
+	    } => {
+		*name = scope.element(name);
+		rename_nodes(&mut [term_pos, term_neg, ctrl_pos, ctrl_neg]);
+	    }
+	    Element::Ccvs {
+		name,
+		term_pos,
+		term_neg,
+		ctrl_element,
+		..
+	    }
+	    | Element::Cccs {
+		name,
+		term_pos,
+		term_neg,
+		ctrl_element,
+		..
+	    } => {
+		*name = scope.element(name);
+		rename_nodes(&mut [term_pos, term_neg]);
+		*ctrl_element = scope.reference(ctrl_element);
+	    }
+	    Element::Diode {
+		name,
+		anode,
+		cathode,
+		..
+	    } => {
+		*name = scope.element(name);
+		rename_nodes(&mut [anode, cathode]);
+	    }
+	    Element::Bjt {
+		name,
+		collector,
+		base,
+		emitter,
+		..
+	    } => {
+		*name = scope.element(name);
+		rename_nodes(&mut [collector, base, emitter]);
+	    }
+	    Element::MutualInductance {
+		name,
+		inductor_1,
+		inductor_2,
+		..
+	    } => {
+		*name = scope.element(name);
+		*inductor_1 = scope.reference(inductor_1);
+		*inductor_2 = scope.reference(inductor_2);
+	    }
+	    Element::Mosfet {
+		name,
+		drain,
+		gate,
+		source,
+		bulk,
+		..
+	    } => {
+		*name = scope.element(name);
+		rename_nodes(&mut [drain, gate, source, bulk]);
+	    }
+	}
+    }
+}
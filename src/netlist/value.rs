@@ -0,0 +1,198 @@
+//! Engineering-notation values
+//!
+//! Numbers in a netlist may be followed by a SPICE scale factor and
+//! then by arbitrary unit letters, which are ignored. The scale
+//! factors are (case-insensitive):
+//!
+//! | Suffix | Factor    |
+//! |--------|-----------|
+//! | `t`    | 1e12      |
+//! | `g`    | 1e9       |
+//! | `meg`  | 1e6       |
+//! | `k`    | 1e3       |
+//! | `mil`  | 25.4e-6   |
+//! | `m`    | 1e-3      |
+//! | `u`    | 1e-6      |
+//! | `n`    | 1e-9      |
+//! | `p`    | 1e-12     |
+//! | `f`    | 1e-15     |
+//!
+//! For example, `4.7k`, `10kOhm`, `1uF` and `2.2MEG` are all valid.
+//! Because suffixes are case-insensitive, `1M` is one milli-unit and
+//! `1F` is one femto-unit, not a megohm or a farad. Values like these
+//! parse, but come with a warning.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use super::ParseError;
+
+/// Value parsed from a netlist token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    pub value: f64,
+    /// Set if the token is valid but probably does not mean what
+    /// the author intended
+    pub warning: Option<String>,
+}
+
+/// Parse a number with an optional SPICE scale factor and units
+pub fn parse_value(token: &str) -> Result<Value, ParseError> {
+    let invalid = || ParseError {
+	line: None,
+	message: format!("invalid value {}", token),
+    };
+
+    static MANTISSA: OnceLock<Regex> = OnceLock::new();
+    let re = MANTISSA.get_or_init(|| {
+	Regex::new(r"^[+-]?([0-9]+\.?[0-9]*|\.[0-9]+)([eE][+-]?[0-9]+)?").unwrap()
+    });
+    let mantissa = re.find(token).ok_or_else(invalid)?;
+    let number: f64 = mantissa.as_str().parse().map_err(|_| invalid())?;
+
+    let suffix = &token[mantissa.end()..];
+    // An exponent without digits, such as 1e or 1e+
+    if suffix.starts_with(['e', 'E']) {
+	return Err(ParseError {
+	    line: None,
+	    message: format!("incomplete exponent in value {}", token),
+	});
+    }
+    let (scale, scale_len) = scale_factor(suffix);
+
+    // Anything after the scale factor must be unit letters
+    let units = &suffix[scale_len..];
+    if !units.chars().all(|c| c.is_ascii_alphabetic()) {
+	return Err(ParseError {
+	    line: None,
+	    message: if units.starts_with(|c: char| c.is_ascii_digit()) {
+		format!(
+		    "ambiguous value {} (write the decimal point explicitly, e.g. 4.7k)",
+		    token
+		)
+	    } else {
+		format!("invalid value {}", token)
+	    },
+	});
+    }
+
+    let warning = if scale_len == 1 && suffix.starts_with('M') {
+	Some(format!(
+	    "value {} is interpreted as milli (1e-3); use MEG for mega",
+	    token
+	))
+    } else if scale_len == 1 && suffix.starts_with('F') {
+	Some(format!(
+	    "value {} is interpreted as femto (1e-15), not farads",
+	    token
+	))
+    } else {
+	None
+    };
+
+    Ok(Value {
+	value: number * scale,
+	warning,
+    })
+}
+
+/// Scale factor at the start of a suffix and its length, or 1 and 0
+/// if there is none
+fn scale_factor(suffix: &str) -> (f64, usize) {
+    let lower = suffix.to_ascii_lowercase();
+    if lower.starts_with("meg") {
+	(1e6, 3)
+    } else if lower.starts_with("mil") {
+	(25.4e-6, 3)
+    } else {
+	match lower.chars().next() {
+	    Some('t') => (1e12, 1),
+	    Some('g') => (1e9, 1),
+	    Some('k') => (1e3, 1),
+	    Some('m') => (1e-3, 1),
+	    Some('u') => (1e-6, 1),
+	    Some('n') => (1e-9, 1),
+	    Some('p') => (1e-12, 1),
+	    Some('f') => (1e-15, 1),
+	    _ => (1.0, 0),
+	}
+    }
+}
+
+/// Whether a suffix is a scale factor alone, without units, as the
+/// suffix of a number in an expression must be
+pub(crate) fn is_scale_factor(suffix: &str) -> bool {
+    let (_, scale_len) = scale_factor(suffix);
+    scale_len > 0 && scale_len == suffix.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a value that must be valid
+    fn value(token: &str) -> Value {
+	parse_value(token).unwrap()
+    }
+
+    /// Check a parsed value to within rounding
+    fn assert_value(token: &str, expected: f64) {
+	let value = value(token).value;
+	assert!(
+	    (value - expected).abs() <= 1e-12 * expected.abs(),
+	    "{} parsed as {}, not {}",
+	    token,
+	    value,
+	    expected
+	);
+    }
+
+    #[test]
+    fn scale_factors_and_units() {
+	assert_value("4.7k", 4.7e3);
+	assert_value("303p", 303e-12);
+	assert_value("30u", 30e-6);
+	assert_value("10kOhm", 10e3);
+	assert_value("1uF", 1e-6);
+	assert_value("2.2MEG", 2.2e6);
+	assert_value("-1.5e3", -1.5e3);
+	assert_value(".5", 0.5);
+    }
+
+    #[test]
+    fn meg_milli_and_mil() {
+	assert_value("1meg", 1e6);
+	assert_value("1m", 1e-3);
+	assert_value("2mil", 50.8e-6);
+    }
+
+    #[test]
+    fn incomplete_exponent() {
+	assert!(parse_value("1e").is_err());
+	assert!(parse_value("1e+").is_err());
+	assert_value("1e3", 1e3);
+    }
+
+    #[test]
+    fn invalid_values() {
+	assert!(parse_value("k").is_err());
+	assert!(parse_value("1k5").is_err());
+	assert!(parse_value("1k-").is_err());
+    }
+
+    #[test]
+    fn ambiguity_warnings() {
+	let milli = value("1M");
+	assert_eq!(milli.value, 1e-3);
+	assert!(milli.warning.unwrap().contains("milli"));
+
+	let femto = value("1F");
+	assert_eq!(femto.value, 1e-15);
+	assert!(femto.warning.unwrap().contains("femto"));
+
+	assert!(value("1MEG").warning.is_none());
+	assert!(value("1uF").warning.is_none());
+	assert!(value("1m").warning.is_none());
+    }
+}
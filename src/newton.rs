@@ -0,0 +1,297 @@
+//! Newton-Raphson iteration
+//!
+//! Nonlinear devices stamp a linearisation of themselves about the
+//! current estimate of the solution. The system is rebuilt and solved
+//! repeatedly until the solution stops changing (to within the
+//! tolerances) and no device had to limit its voltages. A linear
+//! circuit is solved in a single iteration.
+
+use crate::{
+    circuit::Circuit,
+    device::Unknowns,
+    error::AcdcError,
+    mna::Mna,
+};
+
+/// Tolerances and iteration limit for Newton-Raphson iteration, named
+/// after the corresponding SPICE `.options`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewtonOptions {
+    /// Relative tolerance on voltages and currents
+    pub reltol: f64,
+    /// Absolute current tolerance (A)
+    pub abstol: f64,
+    /// Absolute voltage tolerance (V)
+    pub vntol: f64,
+    /// Number of iterations after which the solver gives up
+    pub max_iterations: usize,
+}
+
+impl Default for NewtonOptions {
+    fn default() -> Self {
+	Self {
+	    reltol: 1e-3,
+	    abstol: 1e-12,
+	    vntol: 1e-6,
+	    max_iterations: 100,
+	}
+    }
+}
+
+/// Solve the system stamped by the stamp function, starting from the
+/// node voltages and edge currents in initial. Returns the node
+/// voltages and edge currents, or NoConvergence if the iteration limit
+/// is reached.
+pub fn solve_circuit<S>(
+    circuit: &mut Circuit,
+    initial: (Vec<f64>, Vec<f64>),
+    options: &NewtonOptions,
+    stamp: S,
+) -> Result<(Vec<f64>, Vec<f64>), AcdcError>
+where
+    S: Fn(&Circuit, &mut Mna<f64>, &Unknowns) -> Result<(), AcdcError>,
+{
+    let nonlinear = circuit.devices().iter().any(|d| d.is_nonlinear());
+    let (mut voltages, mut currents) = initial;
+
+    // Linearise the devices about the initial solution
+    let x = Unknowns::new(&voltages, &currents);
+    for device in circuit.devices_mut() {
+	device.limit(&x);
+    }
+
+    for iteration in 0..options.max_iterations {
+	let mut mna = Mna::new();
+	let x = Unknowns::new(&voltages, &currents);
+	stamp(circuit, &mut mna, &x)?;
+	let (new_voltages, new_currents) = mna.solve()?;
+	if !nonlinear {
+	    return Ok((new_voltages, new_currents));
+	}
+
+	// The initial solution may not cover every node, so the first
+	// iteration never counts as converged
+	let converged = iteration > 0
+	    && within_tolerance(&voltages, &new_voltages, options.reltol, options.vntol)
+	    && within_tolerance(&currents, &new_currents, options.reltol, options.abstol);
+
+	let x = Unknowns::new(&new_voltages, &new_currents);
+	let mut limited = false;
+	for device in circuit.devices_mut() {
+	    limited |= device.limit(&x);
+	}
+
+	voltages = new_voltages;
+	currents = new_currents;
+	if converged && !limited {
+	    return Ok((voltages, currents));
+	}
+    }
+    Err(AcdcError::NoConvergence {
+	iterations: options.max_iterations,
+    })
+}
+
+/// Check that every value has changed by less than the tolerance
+fn within_tolerance(old: &[f64], new: &[f64], reltol: f64, abstol: f64) -> bool {
+    old.len() == new.len()
+	&& old
+	    .iter()
+	    .zip(new.iter())
+	    .all(|(a, b)| (b - a).abs() <= reltol * a.abs().max(b.abs()) + abstol)
+}
+
+/// Limit the change in the voltage across a pn junction between
+/// iterations, so that the exponential does not overflow (SPICE
+/// pnjlim). vt is the thermal voltage (times the emission
+/// coefficient), and vcrit the voltage above which the junction
+/// current starts to grow quickly. Returns the limited voltage, and
+/// whether it was limited.
+pub fn pnjlim(v_new: f64, v_old: f64, vt: f64, vcrit: f64) -> (f64, bool) {
+    if v_new > vcrit && (v_new - v_old).abs() > 2.0 * vt {
+	let v = if v_old > 0.0 {
+	    let arg = 1.0 + (v_new - v_old) / vt;
+	    if arg > 0.0 {
+		v_old + vt * arg.ln()
+	    } else {
+		vcrit
+	    }
+	} else {
+	    vt * (v_new / vt).ln()
+	};
+	(v, true)
+    } else {
+	(v_new, false)
+    }
+}
+
+/// Limit the change in the gate-source voltage of a MOSFET between
+/// iterations, depending on how far it is from the threshold voltage
+/// vto (SPICE fetlim). Returns the limited voltage, and whether it was
+/// limited.
+pub fn fetlim(v_new: f64, v_old: f64, vto: f64) -> (f64, bool) {
+    let vtsthi = (2.0 * (v_old - vto)).abs() + 2.0;
+    let vtstlo = vtsthi / 2.0 + 2.0;
+    let vtox = vto + 3.5;
+    let delv = v_new - v_old;
+    let v = if v_old >= vto {
+	if v_old >= vtox {
+	    if delv <= 0.0 {
+		// Going off
+		if v_new >= vtox {
+		    if -delv > vtstlo {
+			v_old - vtstlo
+		    } else {
+			v_new
+		    }
+		} else {
+		    v_new.max(vto + 2.0)
+		}
+	    } else if delv >= vtsthi {
+		// Staying on
+		v_old + vtsthi
+	    } else {
+		v_new
+	    }
+	} else if delv <= 0.0 {
+	    // Middle region, going off
+	    v_new.max(vto - 0.5)
+	} else {
+	    // Middle region, going on
+	    v_new.min(vto + 4.0)
+	}
+    } else if delv <= 0.0 {
+	// Staying off
+	if -delv > vtsthi {
+	    v_old - vtsthi
+	} else {
+	    v_new
+	}
+    } else {
+	// Going on
+	let vtemp = vto + 0.5;
+	if v_new <= vtemp {
+	    if delv > vtstlo {
+		v_old + vtstlo
+	    } else {
+		v_new
+	    }
+	} else {
+	    vtemp
+	}
+    };
+    (v, v != v_new)
+}
+
+/// Limit the change in the drain-source voltage of a MOSFET between
+/// iterations (SPICE limvds). Returns the limited voltage, and whether
+/// it was limited.
+pub fn limvds(v_new: f64, v_old: f64) -> (f64, bool) {
+    let v = if v_old >= 3.5 {
+	if v_new > v_old {
+	    v_new.min(3.0 * v_old + 2.0)
+	} else if v_new < 3.5 {
+	    v_new.max(2.0)
+	} else {
+	    v_new
+	}
+    } else if v_new > v_old {
+	v_new.min(4.0)
+    } else {
+	v_new.max(-0.5)
+    };
+    (v, v != v_new)
+}
+
+/// Voltage above which a junction with saturation current is and
+/// thermal voltage vt should be limited
+pub fn vcrit(is: f64, vt: f64) -> f64 {
+    vt * (vt / (std::f64::consts::SQRT_2 * is)).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::diode::DiodeModel;
+
+    /// 5 V through 1k into a diode with the default model. Solving
+    /// I = (5 - V) / 1k = is (exp(V / vt) - 1) by hand, with vt =
+    /// kT/q at 300.15 K, gives V = 0.692888 V.
+    fn diode_circuit() -> Circuit {
+	let mut circuit = Circuit::new();
+	circuit.add_independent_voltage_source("v1", "in", "0", 5.0).unwrap();
+	circuit.add_resistor("r1", "in", "a", false, 1e3).unwrap();
+	circuit
+	    .add_diode("d1", "a", "0", &DiodeModel::default(), 1.0)
+	    .unwrap();
+	circuit
+    }
+
+    /// Solve the DC operating point, returning the node voltages
+    fn solve_dc(circuit: &mut Circuit, options: &NewtonOptions) -> Result<Vec<f64>, AcdcError> {
+	let initial = (Vec::new(), Vec::new());
+	let (voltages, _) = solve_circuit(circuit, initial, options, |circuit, mna, x| {
+	    for device in circuit.devices() {
+		device.stamp_dc(mna, x)?;
+	    }
+	    Ok(())
+	})?;
+	Ok(voltages)
+    }
+
+    #[test]
+    fn diode_operating_point() {
+	let mut circuit = diode_circuit();
+	let voltages = solve_dc(&mut circuit, &NewtonOptions::default()).unwrap();
+	let a = circuit.node_map().find_node("a").unwrap();
+	assert!((voltages[a - 1] - 0.692888).abs() < 1e-4);
+    }
+
+    #[test]
+    fn no_convergence() {
+	let mut circuit = diode_circuit();
+	let options = NewtonOptions {
+	    max_iterations: 2,
+	    ..NewtonOptions::default()
+	};
+	assert!(matches!(
+	    solve_dc(&mut circuit, &options),
+	    Err(AcdcError::NoConvergence { iterations: 2 })
+	));
+    }
+
+    #[test]
+    fn pn_junction_limiting() {
+	let vt = 0.025;
+	let vcrit = vcrit(1e-14, vt);
+	assert!((vcrit - 0.70502).abs() < 1e-5);
+	// From zero, the step is cut to the logarithm
+	let (v, limited) = pnjlim(5.0, 0.0, vt, vcrit);
+	assert!(limited);
+	assert!((v - vt * (5.0 / vt).ln()).abs() < 1e-12);
+	// From a forward bias, the current may grow by 1 + dv / vt
+	let (v, limited) = pnjlim(0.8, 0.7, vt, vcrit);
+	assert!(limited);
+	assert!((v - (0.7 + vt * 5.0f64.ln())).abs() < 1e-12);
+	// Below vcrit, or in small steps, the voltage is unchanged
+	assert_eq!(pnjlim(0.5, 0.0, vt, vcrit), (0.5, false));
+	assert_eq!(pnjlim(0.72, 0.71, vt, vcrit), (0.72, false));
+    }
+
+    #[test]
+    fn fet_limiting() {
+	let vto = 1.0;
+	// Turning on from off stops just above the threshold
+	assert_eq!(fetlim(5.0, 0.0, vto), (1.5, true));
+	// Staying off or on, large steps are cut
+	assert_eq!(fetlim(-10.0, 0.0, vto), (-4.0, true));
+	assert_eq!(fetlim(20.0, 5.0, vto), (15.0, true));
+	// Between the threshold and well on, going on
+	assert_eq!(fetlim(10.0, 2.0, vto), (5.0, true));
+	assert_eq!(fetlim(0.5, 0.0, vto), (0.5, false));
+
+	assert_eq!(limvds(10.0, 0.0), (4.0, true));
+	assert_eq!(limvds(30.0, 5.0), (17.0, true));
+	assert_eq!(limvds(1.0, 0.0), (1.0, false));
+    }
+}
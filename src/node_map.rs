@@ -1,8 +1,20 @@
+use std::sync::OnceLock;
+
 use regex::Regex;
 
+use crate::error::AcdcError;
+
+/// Whether a node name is one of the names of ground
+pub(crate) fn is_ground(node_name: &str) -> bool {
+    static GROUND: OnceLock<Regex> = OnceLock::new();
+    GROUND
+        .get_or_init(|| Regex::new(r"^(gnd|GND|0)$").unwrap())
+        .is_match(node_name)
+}
+
 /// Map from node indices to netlist
 /// node names
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NodeMap {
     /// Voltage nodes (including ground at position 0)
     index_to_name: Vec<String>,
@@ -19,7 +31,7 @@ impl NodeMap {
         }
     }
 
-    fn add_ground_node(&mut self, ground_name: &str) {
+    fn add_ground_node(&mut self, ground_name: &str) -> Result<(), AcdcError> {
         if self.index_to_name[0] == "" {
             // If no ground node has been encountered yet,
             // store it here
@@ -28,26 +40,26 @@ impl NodeMap {
             // Else, check the ground name agrees with the
             // previously used name
             if self.index_to_name[0] != ground_name {
-                panic!(
-                    "Ground node name mismatch: expected {}, found {}",
+                return Err(AcdcError::InvalidTopology(format!(
+                    "ground node name mismatch: expected {}, found {}",
                     self.index_to_name[0], ground_name
-                );
+                )));
             }
         }
+        Ok(())
     }
 
     /// Assign a terminal string to a new index, or return the index
     /// if it was already assigned.
-    pub fn node_index(&mut self, node_name: &str) -> usize {
-        let re = Regex::new(r"(gnd|GND|0)").unwrap();
-        if re.is_match(node_name) {
-            self.add_ground_node(node_name);
-            0
+    pub fn node_index(&mut self, node_name: &str) -> Result<usize, AcdcError> {
+        if is_ground(node_name) {
+            self.add_ground_node(node_name)?;
+            Ok(0)
         } else if let Some(result) = self.index_to_name.iter().position(|s| s == node_name) {
-            result
+            Ok(result)
         } else {
             self.index_to_name.push(String::from(node_name));
-            self.index_to_name.len() - 1
+            Ok(self.index_to_name.len() - 1)
         }
     }
 
@@ -62,6 +74,31 @@ impl NodeMap {
         }
     }
     
+    /// Look up the index of a node without assigning a new one. Any
+    /// of the ground node names maps to index 0.
+    pub fn find_node(&self, node_name: &str) -> Option<usize> {
+        if is_ground(node_name) {
+            Some(0)
+        } else {
+            self.index_to_name.iter().position(|s| s == node_name)
+        }
+    }
+
+    /// Look up the index of an edge without assigning a new one
+    pub fn find_edge(&self, edge_name: &str) -> Option<usize> {
+        self.edge_to_name.iter().position(|s| s == edge_name)
+    }
+
+    /// Number of voltage nodes, including ground
+    pub fn num_nodes(&self) -> usize {
+        self.index_to_name.len()
+    }
+
+    /// Number of current edges
+    pub fn num_edges(&self) -> usize {
+        self.edge_to_name.len()
+    }
+
     pub fn node_name(&self, index: usize) -> &String {
         &self.index_to_name[index]
     }
@@ -0,0 +1,325 @@
+//! Noise analysis
+//!
+//! The noise at an output, V(out) - V(ref), is found at each frequency
+//! of a sweep from the noise current sources of every device (see
+//! [Device::noise_sources](crate::device::Device::noise_sources)),
+//! linearised about the DC operating point. Resistances contribute
+//! thermal noise, and junctions and channels contribute shot, thermal
+//! and flicker noise. One solve of the adjoint system at each
+//! frequency gives the transfer function from every noise source to
+//! the output at once, and the gain from an input source to the
+//! output. As in SPICE `.noise`, the output noise is referred to the
+//! input by dividing it by that gain.
+
+use std::f64::consts::PI;
+
+use num::Complex;
+
+use crate::{
+    circuit::Circuit,
+    error::AcdcError,
+    mna::Mna,
+    newton::{self, NewtonOptions},
+    node_map::NodeMap,
+    waveform::SourceWaveform,
+};
+
+/// Noise from one source of one device over a sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseContribution {
+    /// Name of the device
+    pub element: String,
+    /// Name of the noise source within the device (see
+    /// [NoiseSource](crate::device::NoiseSource))
+    pub source: &'static str,
+    /// Power spectral density (V^2/Hz) of the output noise it causes
+    /// at each frequency
+    pub density: Vec<f64>,
+}
+
+/// Output noise, and the gain used to refer it to the input, over the
+/// frequencies of a noise analysis
+#[derive(Debug, Clone)]
+pub struct NoiseSolution {
+    frequencies: Vec<f64>,
+    /// Power spectral density (V^2/Hz) of the output noise at each
+    /// frequency
+    output: Vec<f64>,
+    /// Gain from the input source to the output at each frequency
+    gain: Vec<Complex<f64>>,
+    contributions: Vec<NoiseContribution>,
+}
+
+impl NoiseSolution {
+    /// Frequencies of the sweep, in Hz
+    pub fn frequencies(&self) -> &[f64] {
+	&self.frequencies
+    }
+
+    /// Power spectral density (V^2/Hz) of the output noise at each
+    /// frequency
+    pub fn output_density(&self) -> &[f64] {
+	&self.output
+    }
+
+    /// Gain from the input source to the output at each frequency
+    pub fn gain(&self) -> &[Complex<f64>] {
+	&self.gain
+    }
+
+    /// Power spectral density of the noise referred to the input at
+    /// each frequency: the output density divided by the squared
+    /// magnitude of the gain (V^2/Hz for a voltage source input, or
+    /// A^2/Hz for a current source)
+    pub fn input_density(&self) -> Vec<f64> {
+	self.output
+	    .iter()
+	    .zip(self.gain.iter())
+	    .map(|(output, gain)| output / gain.norm_sqr())
+	    .collect()
+    }
+
+    /// Contribution of every noise source of every device to the
+    /// output noise
+    pub fn contributions(&self) -> &[NoiseContribution] {
+	&self.contributions
+    }
+
+    /// Power spectral density (V^2/Hz) of the output noise caused by
+    /// the named device at each frequency, or None if the device has
+    /// no noise sources
+    pub fn element_density(&self, element: &str) -> Option<Vec<f64>> {
+	let mut density: Option<Vec<f64>> = None;
+	for contribution in self.contributions.iter() {
+	    if !contribution.element.eq_ignore_ascii_case(element) {
+		continue;
+	    }
+	    match density.as_mut() {
+		Some(density) => {
+		    for (total, d) in density.iter_mut().zip(contribution.density.iter()) {
+			*total += d;
+		    }
+		}
+		None => density = Some(contribution.density.clone()),
+	    }
+	}
+	density
+    }
+
+    /// RMS output noise (V) in the band from f_low to f_high (in Hz),
+    /// within the frequencies of the sweep
+    pub fn integrated_output(&self, f_low: f64, f_high: f64) -> f64 {
+	integrate(&self.frequencies, &self.output, f_low, f_high).sqrt()
+    }
+
+    /// RMS noise referred to the input (V or A) in the band from f_low
+    /// to f_high (in Hz), within the frequencies of the sweep
+    pub fn integrated_input(&self, f_low: f64, f_high: f64) -> f64 {
+	integrate(&self.frequencies, &self.input_density(), f_low, f_high).sqrt()
+    }
+
+    /// RMS output noise (V) caused by the named device in the band
+    /// from f_low to f_high (in Hz)
+    pub fn integrated_element(&self, element: &str, f_low: f64, f_high: f64) -> Option<f64> {
+	let density = self.element_density(element)?;
+	Some(integrate(&self.frequencies, &density, f_low, f_high).sqrt())
+    }
+}
+
+/// Integral of a power spectral density over the band from f_low to
+/// f_high, clipped to the frequencies it is known at. Between two
+/// frequencies, the density is taken to follow a power law (so that
+/// white and 1/f noise are integrated exactly), or a straight line if
+/// it is zero at either end.
+fn integrate(frequencies: &[f64], density: &[f64], f_low: f64, f_high: f64) -> f64 {
+    let mut total = 0.0;
+    for k in 1..frequencies.len().min(density.len()) {
+	let (f1, f2) = (frequencies[k - 1], frequencies[k]);
+	let (s1, s2) = (density[k - 1], density[k]);
+	let (fa, fb) = (f1.max(f_low), f2.min(f_high));
+	if fb <= fa {
+	    continue;
+	}
+	total += if f1 > 0.0 && s1 > 0.0 && s2 > 0.0 {
+	    let a = (s2 / s1).ln() / (f2 / f1).ln();
+	    if (a + 1.0).abs() < 1e-9 {
+		s1 * f1 * (fb / fa).ln()
+	    } else {
+		s1 / f1.powf(a) * (fb.powf(a + 1.0) - fa.powf(a + 1.0)) / (a + 1.0)
+	    }
+	} else {
+	    let s = |f: f64| s1 + (s2 - s1) * (f - f1) / (f2 - f1);
+	    (s(fa) + s(fb)) / 2.0 * (fb - fa)
+	};
+    }
+    total
+}
+
+/// Noise analysis of a circuit, which may contain nonlinear devices,
+/// about its DC operating point
+pub struct NoiseAnalysis {
+    circuit: Circuit,
+    options: NewtonOptions,
+    /// Output node and reference node
+    output: (usize, usize),
+    /// Position of the input source among the devices of the circuit
+    input: usize,
+    f: Vec<f64>,
+}
+
+impl NoiseAnalysis {
+    /// New analysis of the noise at V(output) - V(reference), referred
+    /// to the independent source named input, at each frequency (in
+    /// Hz) in f
+    pub fn new(
+	mut circuit: Circuit,
+	output: &str,
+	reference: &str,
+	input: &str,
+	f: Vec<f64>,
+    ) -> Result<Self, AcdcError> {
+	let node = |name: &str| {
+	    circuit.node_map().find_node(name).ok_or_else(|| {
+		AcdcError::InvalidAnalysis(format!("no node {} in the circuit", name))
+	    })
+	};
+	let output = (node(output)?, node(reference)?);
+	let position = circuit
+	    .devices()
+	    .iter()
+	    .position(|device| device.name().eq_ignore_ascii_case(input))
+	    .ok_or_else(|| AcdcError::InvalidAnalysis(format!("no source {} in the circuit", input)))?;
+	if circuit.devices_mut()[position].waveform_mut().is_none() {
+	    return Err(AcdcError::InvalidAnalysis(format!(
+		"noise input {} is not an independent source",
+		input
+	    )));
+	}
+	Ok(Self {
+	    circuit,
+	    options: NewtonOptions::default(),
+	    output,
+	    input: position,
+	    f,
+	})
+    }
+
+    /// Frequencies (in Hz) of the analysis
+    pub fn frequencies(&self) -> &[f64] {
+	&self.f
+    }
+
+    pub fn circuit(&self) -> &Circuit {
+	&self.circuit
+    }
+
+    /// Map between node/edge names and the indices used in the circuit
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
+    /// Newton-Raphson tolerances and limits for the operating point
+    pub fn options(&self) -> &NewtonOptions {
+	&self.options
+    }
+
+    pub fn set_options(&mut self, options: NewtonOptions) {
+	self.options = options;
+    }
+
+    /// Find the operating point, then the noise at each frequency.
+    /// The AC values of the sources are left as they were.
+    pub fn solve(&mut self) -> Result<NoiseSolution, AcdcError> {
+	newton::solve_circuit(
+	    &mut self.circuit,
+	    (Vec::new(), Vec::new()),
+	    &self.options,
+	    |circuit, mna, x| {
+		for device in circuit.devices() {
+		    device.stamp_dc(mna, x)?;
+		}
+		Ok(())
+	    },
+	)?;
+
+	// Excite the circuit with the input alone, at unit magnitude,
+	// so that the output is the gain
+	let mut saved: Vec<Option<SourceWaveform>> = Vec::new();
+	for (k, device) in self.circuit.devices_mut().iter_mut().enumerate() {
+	    let waveform = device.waveform_mut();
+	    saved.push(waveform.as_deref().cloned());
+	    if let Some(waveform) = waveform {
+		let magnitude = if k == self.input { 1.0 } else { 0.0 };
+		waveform.set_ac(magnitude, 0.0);
+	    }
+	}
+	let solution = self.solve_noise();
+	for (device, saved) in self.circuit.devices_mut().iter_mut().zip(saved) {
+	    if let (Some(waveform), Some(saved)) = (device.waveform_mut(), saved) {
+		*waveform = saved;
+	    }
+	}
+	solution
+    }
+
+    /// Solve for the gain and the noise at each frequency, about the
+    /// operating point the devices were last given
+    fn solve_noise(&self) -> Result<NoiseSolution, AcdcError> {
+	let (out_pos, out_neg) = self.output;
+	let temperature = self.circuit.temperature();
+	// Value at node n (excluding ground) of a solution
+	let at = |values: &[Complex<f64>], n: usize| match n {
+	    0 => Complex::new(0.0, 0.0),
+	    n => values.get(n - 1).copied().unwrap_or_default(),
+	};
+
+	let mut solution = NoiseSolution {
+	    frequencies: self.f.clone(),
+	    output: Vec::new(),
+	    gain: Vec::new(),
+	    contributions: Vec::new(),
+	};
+	for freq_hz in self.f.iter() {
+	    let mut mna = Mna::new();
+	    let omega = 2.0 * PI * freq_hz;
+	    for device in self.circuit.devices() {
+		device.stamp_ac(&mut mna, omega)?;
+	    }
+	    // Transfer function from each noise current to the output
+	    let (y, y_edges) = mna.solve_adjoint(out_pos, out_neg)?;
+
+	    // The input alone excites the circuit, so the output y' b is
+	    // the gain: y(n1) - y(n2) for a current source between n1 and
+	    // n2, or y(e) for a voltage source in edge e
+	    let (b, b_edges) = mna.rhs();
+	    let gain: Complex<f64> = y
+		.iter()
+		.zip(b.iter())
+		.chain(y_edges.iter().zip(b_edges.iter()))
+		.map(|(y, b)| y * b)
+		.sum();
+	    solution.gain.push(gain);
+
+	    let mut total = 0.0;
+	    let mut n = 0;
+	    for device in self.circuit.devices() {
+		for source in device.noise_sources(*freq_hz, temperature) {
+		    let h = at(&y, source.term_1) - at(&y, source.term_2);
+		    let density = h.norm_sqr() * source.density;
+		    total += density;
+		    match solution.contributions.get_mut(n) {
+			Some(contribution) => contribution.density.push(density),
+			None => solution.contributions.push(NoiseContribution {
+			    element: String::from(device.name()),
+			    source: source.name,
+			    density: vec![density],
+			}),
+		    }
+		    n += 1;
+		}
+	    }
+	    solution.output.push(total);
+	}
+	Ok(solution)
+    }
+}
@@ -0,0 +1,335 @@
+//! Sensitivity analysis
+//!
+//! The derivative of an output, a voltage or a current, with respect
+//! to every value of every device (see
+//! [Device::params](crate::device::Device::params)): the element
+//! values of linear devices, and those instance and model parameters
+//! of semiconductor devices whose derivatives they stamp. It is
+//! found at the DC operating point, or at each frequency of an AC
+//! sweep with the operating point held fixed.
+//!
+//! At a solution x of the system A x = b, a change dp in a value
+//! changes the output by -y' (dA/dp x - db/dp) dp, where y is the
+//! solution of the adjoint system for the output, so that one adjoint
+//! solve gives the derivative with respect to every value. The change
+//! dA/dp x - db/dp in the residual is the residual at x of the
+//! derivative of the device's stamp (see
+//! [Device::stamp_dc_derivative](crate::device::Device::stamp_dc_derivative)).
+
+use std::{
+    f64::consts::PI,
+    ops::{Mul, Neg, Sub},
+};
+
+use csuperlu::c::value_type::ValueType;
+use num::Complex;
+
+use crate::{
+    circuit::Circuit,
+    device::{Device, Unknowns},
+    error::AcdcError,
+    mna::{Mna, Probe},
+    newton::{self, NewtonOptions},
+    node_map::NodeMap,
+    solution::OutputVariable,
+};
+
+/// Derivative of the DC output with respect to one value of one device
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sensitivity {
+    /// Name of the device
+    pub element: String,
+    /// Name of the value within the device
+    pub param: &'static str,
+    pub value: f64,
+    /// Change in the output per unit change in the value
+    pub absolute: f64,
+    /// Relative change in the output per relative change in the value
+    /// (%/%), which is taken to be zero if the output is zero
+    pub relative: f64,
+}
+
+/// Output of a DC sensitivity analysis, and its derivatives
+#[derive(Debug, Clone)]
+pub struct SensitivitySolution {
+    output: f64,
+    sensitivities: Vec<Sensitivity>,
+}
+
+impl SensitivitySolution {
+    /// Value of the output at the operating point
+    pub fn output(&self) -> f64 {
+	self.output
+    }
+
+    /// Derivative with respect to every value of every device, in the
+    /// order of the devices in the circuit
+    pub fn sensitivities(&self) -> &[Sensitivity] {
+	&self.sensitivities
+    }
+
+    /// Derivative with respect to the named value of the named device
+    pub fn find(&self, element: &str, param: &str) -> Option<&Sensitivity> {
+	self.sensitivities
+	    .iter()
+	    .find(|s| s.element.eq_ignore_ascii_case(element) && s.param == param)
+    }
+}
+
+/// Derivative of the AC output with respect to one value of one device
+/// over a sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcSensitivity {
+    /// Name of the device
+    pub element: String,
+    /// Name of the value within the device
+    pub param: &'static str,
+    pub value: f64,
+    /// Change in the output per unit change in the value, at each
+    /// frequency
+    pub absolute: Vec<Complex<f64>>,
+    /// Relative change in the output per relative change in the value
+    /// at each frequency. The real part is the relative change in the
+    /// magnitude of the output, and the imaginary part the change in
+    /// its phase (in radians). It is taken to be zero where the output
+    /// is zero.
+    pub relative: Vec<Complex<f64>>,
+}
+
+/// Output of an AC sensitivity analysis, and its derivatives, over the
+/// frequencies of a sweep
+#[derive(Debug, Clone)]
+pub struct AcSensitivitySolution {
+    frequencies: Vec<f64>,
+    output: Vec<Complex<f64>>,
+    sensitivities: Vec<AcSensitivity>,
+}
+
+impl AcSensitivitySolution {
+    /// Frequencies of the sweep, in Hz
+    pub fn frequencies(&self) -> &[f64] {
+	&self.frequencies
+    }
+
+    /// Value of the output at each frequency
+    pub fn output(&self) -> &[Complex<f64>] {
+	&self.output
+    }
+
+    /// Derivative with respect to every value of every device, in the
+    /// order of the devices in the circuit
+    pub fn sensitivities(&self) -> &[AcSensitivity] {
+	&self.sensitivities
+    }
+
+    /// Derivative with respect to the named value of the named device
+    pub fn find(&self, element: &str, param: &str) -> Option<&AcSensitivity> {
+	self.sensitivities
+	    .iter()
+	    .find(|s| s.element.eq_ignore_ascii_case(element) && s.param == param)
+    }
+}
+
+/// Sensitivity analysis of a circuit, which may contain nonlinear
+/// devices, about its DC operating point
+pub struct SensitivityAnalysis {
+    circuit: Circuit,
+    options: NewtonOptions,
+    output: OutputVariable,
+    probe: Probe,
+}
+
+impl SensitivityAnalysis {
+    pub fn new(circuit: Circuit, output: OutputVariable) -> Result<Self, AcdcError> {
+	let probe = output.probe(circuit.node_map())?;
+	Ok(Self {
+	    circuit,
+	    options: NewtonOptions::default(),
+	    output,
+	    probe,
+	})
+    }
+
+    pub fn circuit(&self) -> &Circuit {
+	&self.circuit
+    }
+
+    /// Map between node/edge names and the indices used in the circuit
+    pub fn node_map(&self) -> &NodeMap {
+	self.circuit.node_map()
+    }
+
+    pub fn output(&self) -> &OutputVariable {
+	&self.output
+    }
+
+    /// Newton-Raphson tolerances and limits for the operating point
+    pub fn options(&self) -> &NewtonOptions {
+	&self.options
+    }
+
+    pub fn set_options(&mut self, options: NewtonOptions) {
+	self.options = options;
+    }
+
+    /// Node voltages and edge currents at the operating point, about
+    /// which the devices are then linearised
+    fn operating_point(&mut self) -> Result<(Vec<f64>, Vec<f64>), AcdcError> {
+	newton::solve_circuit(
+	    &mut self.circuit,
+	    (Vec::new(), Vec::new()),
+	    &self.options,
+	    |circuit, mna, x| {
+		for device in circuit.devices() {
+		    device.stamp_dc(mna, x)?;
+		}
+		Ok(())
+	    },
+	)
+    }
+
+    /// Find the operating point, then the derivative of the output
+    /// there with respect to every value of every device
+    pub fn solve(&mut self) -> Result<SensitivitySolution, AcdcError> {
+	let (voltages, currents) = self.operating_point()?;
+	let values = (&voltages[..], &currents[..]);
+	let x = Unknowns::new(&voltages, &currents);
+
+	// The system of the last Newton-Raphson iteration, whose matrix
+	// is the Jacobian at the operating point
+	let mut mna = Mna::new();
+	for device in self.circuit.devices() {
+	    device.stamp_dc(&mut mna, &x)?;
+	}
+	let y = mna.solve_adjoint_probes(&[self.probe])?.pop().unwrap();
+	let output = probe_value(self.probe, values);
+
+	let derivatives = derivatives(
+	    self.circuit.devices(),
+	    values,
+	    &y,
+	    |device, param, mna| device.stamp_dc_derivative(param, mna, &x),
+	)?;
+	let sensitivities = derivatives
+	    .into_iter()
+	    .map(|(element, param, value, absolute)| Sensitivity {
+		element,
+		param,
+		value,
+		absolute,
+		relative: if output == 0.0 {
+		    0.0
+		} else {
+		    absolute * value / output
+		},
+	    })
+	    .collect();
+	Ok(SensitivitySolution {
+	    output,
+	    sensitivities,
+	})
+    }
+
+    /// Find the operating point, then the derivative of the small-signal
+    /// output at each frequency (in Hz) in f, excited by the AC values
+    /// of the sources, with respect to every value of every device. The
+    /// operating point is not found again for each change in a value.
+    pub fn solve_ac(&mut self, f: &[f64]) -> Result<AcSensitivitySolution, AcdcError> {
+	self.operating_point()?;
+
+	let mut solution = AcSensitivitySolution {
+	    frequencies: f.to_vec(),
+	    output: Vec::new(),
+	    sensitivities: Vec::new(),
+	};
+	for freq_hz in f.iter() {
+	    let omega = 2.0 * PI * freq_hz;
+	    let mut mna = Mna::new();
+	    for device in self.circuit.devices() {
+		device.stamp_ac(&mut mna, omega)?;
+	    }
+	    let (voltages, currents) = mna.solve()?;
+	    let values = (&voltages[..], &currents[..]);
+	    let y = mna.solve_adjoint_probes(&[self.probe])?.pop().unwrap();
+	    let output = probe_value(self.probe, values);
+	    solution.output.push(output);
+
+	    let derivatives = derivatives(
+		self.circuit.devices(),
+		values,
+		&y,
+		|device, param, mna| device.stamp_ac_derivative(param, mna, omega),
+	    )?;
+	    for (n, (element, param, value, absolute)) in derivatives.into_iter().enumerate() {
+		let relative = if output.norm() == 0.0 {
+		    Complex::new(0.0, 0.0)
+		} else {
+		    absolute * value / output
+		};
+		match solution.sensitivities.get_mut(n) {
+		    Some(sensitivity) => {
+			sensitivity.absolute.push(absolute);
+			sensitivity.relative.push(relative);
+		    }
+		    None => solution.sensitivities.push(AcSensitivity {
+			element,
+			param,
+			value,
+			absolute: vec![absolute],
+			relative: vec![relative],
+		    }),
+		}
+	    }
+	}
+	Ok(solution)
+    }
+}
+
+/// Value of the output probe in the solution x
+fn probe_value<P>(probe: Probe, (voltages, currents): (&[P], &[P])) -> P
+where
+    P: ValueType + Sub<Output = P>,
+{
+    let at = |n: usize| match n {
+	0 => P::zero(),
+	n => voltages.get(n - 1).copied().unwrap_or_else(P::zero),
+    };
+    match probe {
+	Probe::Voltage(pos, neg) => at(pos) - at(neg),
+	Probe::Current(e) => currents.get(e).copied().unwrap_or_else(P::zero),
+    }
+}
+
+/// Sum of the products of the values, up to the shorter of the two
+fn dot<P: ValueType + Mul<Output = P>>(a: &[P], b: &[P]) -> P {
+    a.iter()
+	.zip(b.iter())
+	.fold(P::zero(), |sum, (a, b)| sum + *a * *b)
+}
+
+/// Element name, value name, value, and the derivative of the output,
+/// for every value of every device, given the solution x and the
+/// adjoint solution y for the output. stamp stamps the derivative of
+/// one device with respect to one of its values.
+fn derivatives<P, S>(
+    devices: &[Box<dyn Device>],
+    x: (&[P], &[P]),
+    y: &(Vec<P>, Vec<P>),
+    stamp: S,
+) -> Result<Vec<(String, &'static str, f64, P)>, AcdcError>
+where
+    P: ValueType + Neg<Output = P> + Mul<Output = P>,
+    S: Fn(&dyn Device, &str, &mut Mna<P>) -> Result<(), AcdcError>,
+{
+    let mut derivatives = Vec::new();
+    for device in devices.iter() {
+	for (param, value) in device.params() {
+	    let mut mna = Mna::new();
+	    stamp(device.as_ref(), param, &mut mna)?;
+	    let (nodes, edges) = mna.residual(x)?;
+	    let derivative = -(dot(&y.0, &nodes) + dot(&y.1, &edges));
+	    derivatives.push((String::from(device.name()), param, value, derivative));
+	}
+    }
+    Ok(derivatives)
+}
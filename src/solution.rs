@@ -0,0 +1,140 @@
+//! Named results
+//!
+//! A [Solution] holds the node voltages and edge currents found by an
+//! analysis at one point (a DC operating point, or one frequency of an
+//! AC analysis), together with the [NodeMap] that names them, so that
+//! results are looked up by name rather than by matrix index. An
+//! [OutputVariable] names the voltage or current that an analysis such
+//! as a transfer function or sensitivity analysis reports.
+
+use std::ops::Sub;
+
+use num::Zero;
+
+use crate::{error::AcdcError, mna::Probe, node_map::NodeMap};
+
+/// Voltage or current that an analysis reports, by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputVariable {
+    /// Voltage between two named nodes, V(pos) - V(neg)
+    Voltage(String, String),
+    /// Current in the named edge
+    Current(String),
+}
+
+impl OutputVariable {
+    /// The output as a [Probe] of a system whose nodes and edges are
+    /// named by node_map
+    pub(crate) fn probe(&self, node_map: &NodeMap) -> Result<Probe, AcdcError> {
+	match self {
+	    OutputVariable::Voltage(pos, neg) => {
+		let node = |name: &str| {
+		    node_map.find_node(name).ok_or_else(|| {
+			AcdcError::InvalidAnalysis(format!("no node {} in the circuit", name))
+		    })
+		};
+		Ok(Probe::Voltage(node(pos)?, node(neg)?))
+	    }
+	    OutputVariable::Current(name) => {
+		let e = find_edge(node_map, name).ok_or_else(|| {
+		    AcdcError::InvalidAnalysis(format!("no edge {} in the circuit", name))
+		})?;
+		Ok(Probe::Current(e))
+	    }
+	}
+    }
+}
+
+/// Node voltages and edge currents, by name. See
+/// [DcSolution](crate::dc::DcSolution) and
+/// [AcSolution](crate::ac::AcSolution).
+#[derive(Debug, Clone)]
+pub struct Solution<P> {
+    node_map: NodeMap,
+    /// Voltage at each node, including ground at position 0
+    voltages: Vec<P>,
+    /// Current in each edge
+    currents: Vec<P>,
+}
+
+impl<P: Copy + Zero + Sub<Output = P>> Solution<P> {
+    /// Make a solution from the node voltages (without ground) and
+    /// edge currents returned by [Mna::solve](crate::mna::Mna::solve).
+    /// Nodes and edges that are not part of the system are at zero.
+    pub(crate) fn new(node_map: NodeMap, (voltages, currents): (Vec<P>, Vec<P>)) -> Self {
+	let mut voltages: Vec<P> = std::iter::once(P::zero()).chain(voltages).collect();
+	voltages.resize(node_map.num_nodes(), P::zero());
+	let mut currents = currents;
+	currents.resize(node_map.num_edges(), P::zero());
+	Self {
+	    node_map,
+	    voltages,
+	    currents,
+	}
+    }
+
+    /// Voltage at the named node, where ground is at zero
+    pub fn v(&self, node: &str) -> Option<P> {
+	let n = self.node_map.find_node(node)?;
+	Some(self.voltages[n])
+    }
+
+    /// Voltage between two named nodes, V(pos) - V(neg)
+    pub fn v_diff(&self, pos: &str, neg: &str) -> Option<P> {
+	Some(self.v(pos)? - self.v(neg)?)
+    }
+
+    /// Current in the named edge, which for a circuit read from a
+    /// netlist is the name of its element
+    pub fn i(&self, name: &str) -> Option<P> {
+	let e = find_edge(&self.node_map, name)?;
+	Some(self.currents[e])
+    }
+
+    /// Value of the output, or None if it names a node or edge that
+    /// is not in the solution
+    pub fn output(&self, output: &OutputVariable) -> Option<P> {
+	match output {
+	    OutputVariable::Voltage(pos, neg) => self.v_diff(pos, neg),
+	    OutputVariable::Current(name) => self.i(name),
+	}
+    }
+
+    /// Name and voltage of every node, starting with ground
+    pub fn voltages(&self) -> impl Iterator<Item = (&str, P)> + '_ {
+	self.voltages
+	    .iter()
+	    .enumerate()
+	    .map(|(n, v)| (node_name(&self.node_map, n), *v))
+    }
+
+    /// Name and current of every edge
+    pub fn currents(&self) -> impl Iterator<Item = (&str, P)> + '_ {
+	self.currents
+	    .iter()
+	    .enumerate()
+	    .map(|(e, i)| (self.node_map.edge_name(e).as_str(), *i))
+    }
+
+    /// Map between node/edge names and the indices used by the analysis
+    pub fn node_map(&self) -> &NodeMap {
+	&self.node_map
+    }
+}
+
+/// Name of node n, where ground is called `0` if the circuit did not
+/// name it
+pub(crate) fn node_name(node_map: &NodeMap, n: usize) -> &str {
+    match node_map.node_name(n).as_str() {
+	"" => "0",
+	name => name,
+    }
+}
+
+/// Index of the named edge. Netlist element names are stored in lower
+/// case, so that is tried too.
+pub(crate) fn find_edge(node_map: &NodeMap, name: &str) -> Option<usize> {
+    node_map
+	.find_edge(name)
+	.or_else(|| node_map.find_edge(&name.to_ascii_lowercase()))
+}
@@ -6,58 +6,56 @@
 
 use csuperlu::{sparse_matrix::SparseMat, dense::DenseMatrix, simple_driver::{SimpleSystem, SimpleSolution}, c::{stat::CSuperluStat, options::ColumnPermPolicy, value_type::ValueType}};
 
+use crate::error::AcdcError;
+
 /// Assumes the matrix is square
 pub fn plus_equals<P: ValueType>(mat: &mut SparseMat<P>, row: usize, col: usize, val: P) {
     let old_val = mat.get_unbounded(row, col);
     mat.insert_unbounded(row, col, old_val + val);
 }
 
-/// Assumes that the two matrices use the same row indices, even if heights disagree. The
-/// smaller matrix is assumed to have zero rows up to the size of the larger matrix. The
-/// matrices are concatenated with horizontal padding, which adds h_pad all-zero columns between
-/// a and b  
-pub fn concat_horizontal<P: ValueType>(mut a: SparseMat<P>, b: &SparseMat<P>) -> SparseMat<P> {
-    if a.num_rows() != b.num_rows() {
-        panic!("Cannot concatenate matrices horizontally with different numbers of rows");
-    }
-    let a_cols = a.num_cols();
-    for ((row, col), value) in b.non_zero_vals().iter() {
-        a.insert_unbounded(*row, a_cols + *col, *value);
+/// Transpose of a matrix
+pub fn transpose<P: ValueType>(a: &SparseMat<P>) -> SparseMat<P> {
+    let mut t = SparseMat::empty();
+    for ((row, col), value) in a.non_zero_vals().iter() {
+        t.insert_unbounded(*col, *row, *value);
     }
-    a.resize_cols(a_cols + b.num_cols());
-    a
+    t.resize(a.num_cols(), a.num_rows());
+    t
 }
 
-pub fn concat_vertical<P: ValueType>(mut a: SparseMat<P>, b: &SparseMat<P>) -> SparseMat<P> {
-    if a.num_cols() != b.num_cols() {
-        panic!(
-            "Cannot concatenate matrices vertically with different numbers of columns {} and {}",
-            a.num_cols(),
-            b.num_cols()
-        );
-    }
-    let a_rows = a.num_rows();
-    for ((row, col), value) in b.non_zero_vals().iter() {
-        a.insert_unbounded(a_rows + *row, *col, *value);
-    }
-    a.resize_rows(a_rows + b.num_rows());
-    a
+/// Solve the square system ax = b, returning x
+pub fn solve<P: ValueType>(a: SparseMat<P>, b: Vec<P>) -> Result<Vec<P>, AcdcError> {
+    let mut x = solve_many(a, vec![b])?;
+    Ok(x.pop().unwrap_or_default())
 }
 
-pub fn solve<P: ValueType>(a: SparseMat<P>, b: Vec<P>) -> Vec<P> {
-    if a.num_rows() != b.len() {
-        panic!("Cannot solve system; incompatible dimensions");
+/// Solve the square system ax = b for each right-hand side b, using
+/// one factorization of a
+pub fn solve_many<P: ValueType>(a: SparseMat<P>, bs: Vec<Vec<P>>) -> Result<Vec<Vec<P>>, AcdcError> {
+    let num_rows = a.num_rows();
+    for b in bs.iter() {
+        if num_rows != b.len() {
+            return Err(AcdcError::DimensionMismatch {
+                expected: num_rows,
+                found: b.len(),
+            });
+        }
     }
+    let num_cols = bs.len();
     let a = a.compressed_column_format();
-    a.print("a");
-    let b = DenseMatrix::from_vectors(b.len(), 1, b);
+    let b = DenseMatrix::from_vectors(num_rows, num_cols, bs.concat());
     let system = SimpleSystem { a, b };
     let mut stat = CSuperluStat::new();
     let SimpleSolution {
 	mut x,
 	..
     }= system.solve(&mut stat, ColumnPermPolicy::ColAMD)
-        .expect("Failed to solve system");
+        .map_err(|_| AcdcError::SingularMatrix)?;
 
-    x.column_major_values().to_vec()
+    Ok(x.column_major_values()
+       .chunks(num_rows.max(1))
+       .take(num_cols)
+       .map(|x| x.to_vec())
+       .collect())
 }
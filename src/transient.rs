@@ -0,0 +1,583 @@
+//! Transient analysis
+//!
+//! Solves a [Circuit] in the time domain using companion models for
+//! capacitors and inductors. At each timestep, the derivative of each
+//! charge (or flux) state variable q is approximated by an integration
+//! formula of the form
+//!
+//! $$ \frac{dq}{dt} \approx a_0 q_n + h_n $$
+//!
+//! where $a_0$ depends on the timestep and $h_n$ on the history of q.
+//! A capacitor then becomes a conductance $a_0 C$ in parallel with a
+//! current source, and an inductor an impedance $a_0 L$ in series with a
+//! voltage source. Nonlinear circuits are solved at each timestep by
+//! Newton-Raphson iteration, starting from the last solution.
+//!
+//! The timestep is chosen adaptively. After each step, the local
+//! truncation error (LTE) of every node voltage and edge current is
+//! estimated from divided differences of the last few solutions. The
+//! step is rejected and retried with a shorter timestep if the error
+//! is larger than the tolerance, and otherwise the next timestep is
+//! scaled up or down to keep the error near the tolerance.
+//!
+//! Steps always finish exactly on breakpoints (such as the corners of
+//! a pulse source). The first step, and the first step after each
+//! breakpoint, is taken with backward Euler, because the derivative
+//! of q is not known there.
+
+use std::collections::VecDeque;
+
+use crate::{
+    circuit::Circuit,
+    device::Unknowns,
+    error::AcdcError,
+    newton::{self, NewtonOptions},
+    node_map::NodeMap,
+    solution::find_edge,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegrationMethod {
+    BackwardEuler,
+    Trapezoidal,
+    /// Second order backward differentiation formula (BDF2)
+    Gear2,
+}
+
+impl IntegrationMethod {
+    /// Order of accuracy of the method
+    pub fn order(&self) -> usize {
+	match self {
+	    IntegrationMethod::BackwardEuler => 1,
+	    IntegrationMethod::Trapezoidal => 2,
+	    IntegrationMethod::Gear2 => 2,
+	}
+    }
+
+    /// Constant C such that the LTE is C h^(k+1) q^(k+1), where k is
+    /// the order
+    fn error_constant(&self) -> f64 {
+	match self {
+	    IntegrationMethod::BackwardEuler => 1.0 / 2.0,
+	    IntegrationMethod::Trapezoidal => 1.0 / 12.0,
+	    IntegrationMethod::Gear2 => 2.0 / 9.0,
+	}
+    }
+}
+
+/// Tolerances and limits for a transient analysis, named after the
+/// corresponding SPICE `.options`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransientOptions {
+    /// Relative error tolerance
+    pub reltol: f64,
+    /// Absolute current error tolerance (A)
+    pub abstol: f64,
+    /// Absolute voltage error tolerance (V)
+    pub vntol: f64,
+    /// Factor by which the LTE may exceed the tolerance
+    pub trtol: f64,
+    /// Shortest timestep allowed before the analysis fails. Defaults
+    /// to 1e-9 times the maximum timestep.
+    pub min_step: Option<f64>,
+    /// Longest timestep allowed. Defaults to the smaller of the
+    /// suggested timestep and 1/50 of the stop time, as in SPICE.
+    pub max_step: Option<f64>,
+    pub method: IntegrationMethod,
+    /// Take every step at the suggested timestep, without truncation
+    /// error control. Steps are still shortened to finish on a
+    /// breakpoint, or when Newton-Raphson iteration fails to converge.
+    pub fixed_step: bool,
+    /// Newton-Raphson iterations allowed for the operating point
+    pub dc_iterations: usize,
+    /// Newton-Raphson iterations allowed at each timestep before the
+    /// step is retried with a shorter timestep
+    pub max_iterations: usize,
+}
+
+impl TransientOptions {
+    /// Newton-Raphson options with the same tolerances
+    fn newton(&self, max_iterations: usize) -> NewtonOptions {
+	NewtonOptions {
+	    reltol: self.reltol,
+	    abstol: self.abstol,
+	    vntol: self.vntol,
+	    max_iterations,
+	}
+    }
+}
+
+impl Default for TransientOptions {
+    fn default() -> Self {
+	Self {
+	    reltol: 1e-3,
+	    abstol: 1e-12,
+	    vntol: 1e-6,
+	    trtol: 7.0,
+	    min_step: None,
+	    max_step: None,
+	    method: IntegrationMethod::Trapezoidal,
+	    fixed_step: false,
+	    dc_iterations: 100,
+	    max_iterations: 10,
+	}
+    }
+}
+
+/// Charge (or flux) state variable at the last two accepted
+/// timepoints, along with its time derivative at the last one
+#[derive(Debug, Clone, Default)]
+pub struct StateHistory {
+    q: f64,
+    dq: f64,
+    q_prev: f64,
+}
+
+impl StateHistory {
+    pub fn new(q: f64, dq: f64) -> Self {
+	Self { q, dq, q_prev: q }
+    }
+
+    /// Value at the last accepted timepoint
+    pub fn q(&self) -> f64 {
+	self.q
+    }
+
+    /// Record the value and derivative at a newly accepted timepoint
+    pub fn accept(&mut self, q: f64, dq: f64) {
+	self.q_prev = self.q;
+	self.q = q;
+	self.dq = dq;
+    }
+}
+
+/// Timestep being solved in a transient analysis
+#[derive(Debug, Clone)]
+pub struct TimeStep {
+    /// Time at the end of the step
+    pub time: f64,
+    /// Length of the step
+    pub h: f64,
+    /// Length of the previous accepted step (zero for the first step)
+    pub h_prev: f64,
+    pub method: IntegrationMethod,
+}
+
+impl TimeStep {
+    /// Returns $(a_0, h_n)$ such that the derivative of the state variable
+    /// at the end of the step is $a_0 q_n + h_n$
+    pub fn integrate(&self, state: &StateHistory) -> (f64, f64) {
+	match self.method {
+	    IntegrationMethod::BackwardEuler => {
+		let a0 = 1.0 / self.h;
+		(a0, -a0 * state.q)
+	    }
+	    IntegrationMethod::Trapezoidal => {
+		let a0 = 2.0 / self.h;
+		(a0, -a0 * state.q - state.dq)
+	    }
+	    IntegrationMethod::Gear2 => {
+		// Variable step BDF2, where w is the ratio of this step
+		// to the previous one
+		let w = self.h / self.h_prev;
+		let a0 = (1.0 + 2.0 * w) / ((1.0 + w) * self.h);
+		let history = (-(1.0 + w) * state.q + w * w / (1.0 + w) * state.q_prev) / self.h;
+		(a0, history)
+	    }
+	}
+    }
+}
+
+/// Node voltages and edge currents over time
+#[derive(Debug, Clone)]
+pub struct Waveforms {
+    node_map: NodeMap,
+    time: Vec<f64>,
+    /// Voltage at each node (including ground) at each time
+    voltages: Vec<Vec<f64>>,
+    /// Current in each edge at each time
+    currents: Vec<Vec<f64>>,
+}
+
+impl Waveforms {
+    fn new(node_map: NodeMap) -> Self {
+	let voltages = vec![Vec::new(); node_map.num_nodes()];
+	let currents = vec![Vec::new(); node_map.num_edges()];
+	Self {
+	    node_map,
+	    time: Vec::new(),
+	    voltages,
+	    currents,
+	}
+    }
+
+    fn push(&mut self, time: f64, x: &Unknowns) {
+	self.time.push(time);
+	for (n, v) in self.voltages.iter_mut().enumerate() {
+	    v.push(x.v(n));
+	}
+	for (e, i) in self.currents.iter_mut().enumerate() {
+	    i.push(x.i(e));
+	}
+    }
+
+    pub fn time(&self) -> &[f64] {
+	&self.time
+    }
+
+    /// Voltage waveform at the named node, where ground is at zero
+    pub fn v(&self, node: &str) -> Option<&[f64]> {
+	let n = self.node_map.find_node(node)?;
+	Some(&self.voltages[n])
+    }
+
+    /// Voltage waveform between two named nodes, V(pos) - V(neg)
+    pub fn v_diff(&self, pos: &str, neg: &str) -> Option<Vec<f64>> {
+	let (pos, neg) = (self.v(pos)?, self.v(neg)?);
+	Some(pos.iter().zip(neg).map(|(p, n)| p - n).collect())
+    }
+
+    /// Current waveform in the named edge, which for a circuit read
+    /// from a netlist is the name of its element
+    pub fn i(&self, name: &str) -> Option<&[f64]> {
+	let e = find_edge(&self.node_map, name)?;
+	Some(&self.currents[e])
+    }
+
+    pub fn node_map(&self) -> &NodeMap {
+	&self.node_map
+    }
+}
+
+/// Accepted solution at one timepoint, used to estimate the LTE
+struct TimePoint {
+    time: f64,
+    voltages: Vec<f64>,
+    currents: Vec<f64>,
+}
+
+pub struct TransientAnalysis {
+    circuit: Circuit,
+    step: f64,
+    stop: f64,
+    /// Time from which the solution is recorded
+    start: f64,
+    options: TransientOptions,
+    /// Times at which a step must finish (in addition to those
+    /// reported by the devices)
+    breakpoints: Vec<f64>,
+    /// If set, skip the operating point and start from the initial
+    /// conditions (SPICE UIC)
+    use_initial_conditions: bool,
+    /// Initial node voltages, as (node index, voltage)
+    initial_conditions: Vec<(usize, f64)>,
+}
+
+impl TransientAnalysis {
+    /// New transient analysis from time zero to stop. The step is a
+    /// suggested timestep, which limits every step of the analysis
+    /// unless a longer maximum timestep is given, and is the timestep
+    /// itself if [TransientOptions::fixed_step] is set.
+    pub fn new(circuit: Circuit, step: f64, stop: f64) -> Self {
+	Self {
+	    circuit,
+	    step,
+	    stop,
+	    start: 0.0,
+	    options: TransientOptions::default(),
+	    breakpoints: Vec::new(),
+	    use_initial_conditions: false,
+	    initial_conditions: Vec::new(),
+	}
+    }
+
+    pub fn circuit(&self) -> &Circuit {
+	&self.circuit
+    }
+
+    pub fn options(&self) -> &TransientOptions {
+	&self.options
+    }
+
+    pub fn set_options(&mut self, options: TransientOptions) {
+	self.options = options;
+    }
+
+    /// Only record the solution from time start onwards. The analysis
+    /// still starts from time zero.
+    pub fn set_start(&mut self, start: f64) {
+	self.start = start;
+    }
+
+    pub fn set_method(&mut self, method: IntegrationMethod) {
+	self.options.method = method;
+    }
+
+    /// Force a step to finish at time
+    pub fn add_breakpoint(&mut self, time: f64) {
+	self.breakpoints.push(time);
+    }
+
+    /// Start from the initial conditions instead of the operating point.
+    /// Nodes without an initial condition start at 0 V, and inductors
+    /// start with zero current.
+    pub fn use_initial_conditions(&mut self, uic: bool) {
+	self.use_initial_conditions = uic;
+    }
+
+    /// Set the initial voltage of a node. Unless initial conditions are
+    /// used directly, the node is held at this voltage while the
+    /// operating point is found. Ground is always at 0 V, so it cannot
+    /// be given one.
+    pub fn set_initial_condition(&mut self, node: &str, voltage: f64) -> Result<(), AcdcError> {
+	let n = self.circuit.node_map().find_node(node).ok_or_else(|| {
+	    AcdcError::InvalidAnalysis(format!("initial condition for unknown node {}", node))
+	})?;
+	if n == 0 {
+	    return Err(AcdcError::InvalidAnalysis(String::from(
+		"initial condition for ground",
+	    )));
+	}
+	self.initial_conditions.push((n, voltage));
+	Ok(())
+    }
+
+    /// Operating point at time zero, with nodes held at their
+    /// initial conditions
+    fn operating_point(&mut self) -> Result<(Vec<f64>, Vec<f64>), AcdcError> {
+	let num_edges = self.circuit.node_map().num_edges();
+	let initial_conditions = &self.initial_conditions;
+	let options = self.options.newton(self.options.dc_iterations);
+	let (voltages, mut currents) = newton::solve_circuit(
+	    &mut self.circuit,
+	    (Vec::new(), Vec::new()),
+	    &options,
+	    |circuit, mna, x| {
+		for device in circuit.devices() {
+		    device.stamp_initial(mna, x)?;
+		}
+		for (k, (n, v)) in initial_conditions.iter().enumerate() {
+		    mna.add_independent_voltage_source(*n, 0, num_edges + k, *v)?;
+		}
+		Ok(())
+	    },
+	)?;
+	currents.truncate(num_edges);
+	Ok((voltages, currents))
+    }
+
+    /// Initial solution made from the initial conditions
+    fn initial_solution(&self) -> (Vec<f64>, Vec<f64>) {
+	let node_map = self.circuit.node_map();
+	let mut voltages = vec![0.0; node_map.num_nodes() - 1];
+	for (n, v) in self.initial_conditions.iter() {
+	    if *n != 0 {
+		voltages[n - 1] = *v;
+	    }
+	}
+	(voltages, vec![0.0; node_map.num_edges()])
+    }
+
+    /// Sorted breakpoints after time zero, ending with the stop time
+    fn all_breakpoints(&self) -> Vec<f64> {
+	let mut breakpoints = self.breakpoints.clone();
+	for device in self.circuit.devices() {
+	    breakpoints.extend(device.breakpoints(self.stop));
+	}
+	if self.start > 0.0 {
+	    breakpoints.push(self.start);
+	}
+	breakpoints.retain(|t| *t > 0.0 && *t < self.stop);
+	breakpoints.push(self.stop);
+	breakpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	breakpoints.dedup();
+	breakpoints
+    }
+
+    /// Ratio of the estimated LTE of the new solution to the tolerance,
+    /// for the worst node voltage or edge current. Returns None if there
+    /// are not yet enough accepted points to estimate the error.
+    fn truncation_error(
+	&self,
+	history: &VecDeque<TimePoint>,
+	new: &TimePoint,
+	step: &TimeStep,
+    ) -> Option<f64> {
+	let order = step.method.order();
+	if history.len() < order + 1 {
+	    return None;
+	}
+	// Points from newest to oldest
+	let points: Vec<&TimePoint> = std::iter::once(new)
+	    .chain(history.iter().rev().take(order + 1))
+	    .collect();
+	let times: Vec<f64> = points.iter().map(|p| p.time).collect();
+
+	// The (k+1)th derivative is (k+1)! times the divided difference
+	let factorial: f64 = (1..=order + 1).map(|n| n as f64).product();
+	let scale = step.method.error_constant() * step.h.powi(order as i32 + 1) * factorial;
+
+	let options = &self.options;
+	let mut worst: f64 = 0.0;
+	let num_voltages = new.voltages.len();
+	for k in 0..num_voltages + new.currents.len() {
+	    let (values, abs_tol): (Vec<f64>, f64) = if k < num_voltages {
+		(points.iter().map(|p| p.voltages[k]).collect(), options.vntol)
+	    } else {
+		let e = k - num_voltages;
+		(points.iter().map(|p| p.currents[e]).collect(), options.abstol)
+	    };
+	    let lte = scale * divided_difference(&times, &values).abs();
+	    let tol = options.reltol * values[0].abs().max(values[1].abs()) + abs_tol;
+	    worst = worst.max(lte / (options.trtol * tol));
+	}
+	Some(worst)
+    }
+
+    pub fn solve(&mut self) -> Result<Waveforms, AcdcError> {
+	if !(self.step > 0.0) || !(self.stop > 0.0) {
+	    return Err(AcdcError::InvalidAnalysis(String::from(
+		"transient analysis needs a positive timestep and stop time",
+	    )));
+	}
+	let fixed_step = self.options.fixed_step;
+	let max_step = self.options.max_step.unwrap_or(self.step.min(self.stop / 50.0));
+	let min_step = self.options.min_step.unwrap_or(1e-9 * max_step);
+	let first_step = if fixed_step {
+	    self.step
+	} else {
+	    self.step.min(max_step) / 10.0
+	};
+	let breakpoints = self.all_breakpoints();
+	let newton_options = self.options.newton(self.options.max_iterations);
+
+	let (mut voltages, mut currents) = if self.use_initial_conditions {
+	    self.initial_solution()
+	} else {
+	    self.operating_point()?
+	};
+
+	let mut waveforms = Waveforms::new(self.circuit.node_map().clone());
+	let x = Unknowns::new(&voltages, &currents);
+	for device in self.circuit.devices_mut() {
+	    device.initialise_transient(&x);
+	}
+	if self.start <= 0.0 {
+	    waveforms.push(0.0, &x);
+	}
+
+	let mut history = VecDeque::new();
+	history.push_back(TimePoint {
+	    time: 0.0,
+	    voltages: voltages.clone(),
+	    currents: currents.clone(),
+	});
+
+	let mut time = 0.0;
+	let mut h = first_step;
+	let mut h_prev = 0.0;
+	let mut method = IntegrationMethod::BackwardEuler;
+	let mut next_breakpoint = 0;
+	while next_breakpoint < breakpoints.len() {
+	    // Finish exactly on the next breakpoint if it is close
+	    let breakpoint = breakpoints[next_breakpoint];
+	    let at_breakpoint = time + h >= breakpoint - min_step;
+	    if at_breakpoint {
+		h = breakpoint - time;
+	    }
+	    let step = TimeStep {
+		time: if at_breakpoint { breakpoint } else { time + h },
+		h,
+		h_prev,
+		method,
+	    };
+
+	    let result = newton::solve_circuit(
+		&mut self.circuit,
+		(voltages.clone(), currents.clone()),
+		&newton_options,
+		|circuit, mna, x| {
+		    for device in circuit.devices() {
+			device.stamp_transient(mna, x, &step)?;
+		    }
+		    Ok(())
+		},
+	    );
+	    let (new_voltages, new_currents) = match result {
+		Err(AcdcError::NoConvergence { .. }) => {
+		    // Retry with a much shorter step
+		    h /= 8.0;
+		    if h < min_step {
+			return Err(AcdcError::TimestepTooSmall { time, step: h });
+		    }
+		    continue;
+		}
+		result => result?,
+	    };
+	    let point = TimePoint {
+		time: step.time,
+		voltages: new_voltages,
+		currents: new_currents,
+	    };
+
+	    // Scale factor for the timestep from the error estimate
+	    let error = self.truncation_error(&history, &point, &step);
+	    let factor = error.map_or(2.0, |error| {
+		0.9 * error.powf(-1.0 / (method.order() as f64 + 1.0))
+	    });
+	    if !fixed_step && error.map_or(false, |error| error > 1.0) {
+		h *= factor.max(0.25);
+		if h < min_step {
+		    return Err(AcdcError::TimestepTooSmall { time, step: h });
+		}
+		continue;
+	    }
+
+	    voltages = point.voltages.clone();
+	    currents = point.currents.clone();
+	    let x = Unknowns::new(&voltages, &currents);
+	    for device in self.circuit.devices_mut() {
+		device.accept_transient(&x, &step);
+	    }
+	    if step.time >= self.start {
+		waveforms.push(step.time, &x);
+	    }
+
+	    time = step.time;
+	    h_prev = step.h;
+	    h = if fixed_step {
+		self.step
+	    } else {
+		(h * factor.min(2.0)).min(max_step)
+	    };
+	    if at_breakpoint {
+		// Derivatives may be discontinuous at a breakpoint, so
+		// restart with a short backward Euler step
+		next_breakpoint += 1;
+		history.clear();
+		method = IntegrationMethod::BackwardEuler;
+		h = h.min(first_step);
+	    } else {
+		method = self.options.method;
+	    }
+	    history.push_back(point);
+	    if history.len() > 3 {
+		history.pop_front();
+	    }
+	}
+
+	Ok(waveforms)
+    }
+}
+
+/// Divided difference of values at times (of order one less than the
+/// number of points)
+fn divided_difference(times: &[f64], values: &[f64]) -> f64 {
+    let mut dd = values.to_vec();
+    for order in 1..dd.len() {
+	for k in 0..dd.len() - order {
+	    dd[k] = (dd[k] - dd[k + 1]) / (times[k] - times[k + order]);
+	}
+    }
+    dd[0]
+}
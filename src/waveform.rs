@@ -0,0 +1,278 @@
+//! Independent source waveforms
+//!
+//! A [SourceWaveform] describes the value of an independent voltage
+//! or current source in every kind of analysis: a DC value, an AC
+//! magnitude and phase, and optionally a time-varying function for
+//! transient analysis, following the SPICE source forms
+//!
+//! ```text
+//! PULSE(v1 v2 [td [tr [tf [pw [per]]]]])
+//! SIN(vo va freq [td [theta [phase]]])
+//! EXP(v1 v2 td1 tau1 [td2 [tau2]])
+//! PWL(t1 v1 t2 v2 ...)
+//! SFFM(vo va fc mdi fs)
+//! ```
+//!
+//! Times are in seconds, frequencies in Hz and phases in degrees.
+//! Where SPICE would default a rise or fall time to the timestep, it
+//! is zero here (an ideal step), and a missing pulse width or period
+//! (or EXP fall delay) is infinite.
+
+use num::Complex;
+use std::f64::consts::PI;
+
+/// Time-varying part of a source waveform
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransientFunction {
+    /// Trapezoidal pulse from v1 to v2, repeating every period
+    Pulse {
+	v1: f64,
+	v2: f64,
+	delay: f64,
+	rise: f64,
+	fall: f64,
+	width: f64,
+	period: f64,
+    },
+    /// Damped sine wave, starting after delay
+    Sin {
+	offset: f64,
+	amplitude: f64,
+	freq: f64,
+	delay: f64,
+	damping: f64,
+	phase: f64,
+    },
+    /// Exponential rise from v1 towards v2 from rise_delay, then
+    /// fall back towards v1 from fall_delay
+    Exp {
+	v1: f64,
+	v2: f64,
+	rise_delay: f64,
+	rise_tau: f64,
+	fall_delay: f64,
+	fall_tau: f64,
+    },
+    /// Piecewise linear through (time, value) points, in order of
+    /// increasing time
+    Pwl(Vec<(f64, f64)>),
+    /// Single-frequency FM
+    Sffm {
+	offset: f64,
+	amplitude: f64,
+	carrier_freq: f64,
+	modulation_index: f64,
+	signal_freq: f64,
+    },
+}
+
+impl TransientFunction {
+    pub fn value_at(&self, t: f64) -> f64 {
+	match self {
+	    TransientFunction::Pulse {
+		v1,
+		v2,
+		delay,
+		rise,
+		fall,
+		width,
+		period,
+	    } => {
+		if t <= *delay {
+		    return *v1;
+		}
+		let mut tt = t - delay;
+		if period.is_finite() && *period > 0.0 {
+		    tt %= period;
+		}
+		if tt <= *rise {
+		    if *rise > 0.0 {
+			v1 + (v2 - v1) * tt / rise
+		    } else {
+			*v1
+		    }
+		} else if tt <= rise + width {
+		    *v2
+		} else if tt <= rise + width + fall {
+		    v2 + (v1 - v2) * (tt - rise - width) / fall
+		} else {
+		    *v1
+		}
+	    }
+	    TransientFunction::Sin {
+		offset,
+		amplitude,
+		freq,
+		delay,
+		damping,
+		phase,
+	    } => {
+		let phase = phase.to_radians();
+		if t <= *delay {
+		    offset + amplitude * phase.sin()
+		} else {
+		    let tt = t - delay;
+		    offset + amplitude * (-tt * damping).exp() * (2.0 * PI * freq * tt + phase).sin()
+		}
+	    }
+	    TransientFunction::Exp {
+		v1,
+		v2,
+		rise_delay,
+		rise_tau,
+		fall_delay,
+		fall_tau,
+	    } => {
+		let mut value = *v1;
+		if t > *rise_delay {
+		    value += (v2 - v1) * (1.0 - (-(t - rise_delay) / rise_tau).exp());
+		}
+		if t > *fall_delay {
+		    value += (v1 - v2) * (1.0 - (-(t - fall_delay) / fall_tau).exp());
+		}
+		value
+	    }
+	    TransientFunction::Pwl(points) => {
+		let (t_first, v_first) = points[0];
+		if t <= t_first {
+		    return v_first;
+		}
+		for pair in points.windows(2) {
+		    let (t1, v1) = pair[0];
+		    let (t2, v2) = pair[1];
+		    if t <= t2 {
+			return v1 + (v2 - v1) * (t - t1) / (t2 - t1);
+		    }
+		}
+		points[points.len() - 1].1
+	    }
+	    TransientFunction::Sffm {
+		offset,
+		amplitude,
+		carrier_freq,
+		modulation_index,
+		signal_freq,
+	    } => {
+		let carrier = 2.0 * PI * carrier_freq * t;
+		let signal = 2.0 * PI * signal_freq * t;
+		offset + amplitude * (carrier + modulation_index * signal.sin()).sin()
+	    }
+	}
+    }
+
+    /// Times before stop where the function or its slope changes
+    /// abruptly
+    pub fn breakpoints(&self, stop: f64) -> Vec<f64> {
+	match self {
+	    TransientFunction::Pulse {
+		delay,
+		rise,
+		fall,
+		width,
+		period,
+		..
+	    } => {
+		let corners = [0.0, *rise, rise + width, rise + width + fall];
+		let mut breakpoints = Vec::new();
+		let mut start = *delay;
+		while start < stop {
+		    breakpoints.extend(corners.iter().map(|c| start + c).filter(|t| t.is_finite()));
+		    if !(period.is_finite() && *period > 0.0) {
+			break;
+		    }
+		    start += period;
+		}
+		breakpoints.retain(|t| *t < stop);
+		breakpoints
+	    }
+	    TransientFunction::Sin { delay, .. } => vec![*delay],
+	    TransientFunction::Exp {
+		rise_delay,
+		fall_delay,
+		..
+	    } => vec![*rise_delay, *fall_delay],
+	    TransientFunction::Pwl(points) => points.iter().map(|(t, _)| *t).collect(),
+	    TransientFunction::Sffm { .. } => Vec::new(),
+	}
+    }
+}
+
+/// Value of an independent source in each kind of analysis
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceWaveform {
+    /// DC value. If not given, the transient value at time zero is used.
+    dc: Option<f64>,
+    ac_magnitude: f64,
+    /// AC phase in degrees
+    ac_phase: f64,
+    function: Option<TransientFunction>,
+}
+
+impl SourceWaveform {
+    /// Source with a constant value (and no AC part)
+    pub fn dc(value: f64) -> Self {
+	Self {
+	    dc: Some(value),
+	    ..Self::default()
+	}
+    }
+
+    pub fn set_dc(&mut self, value: f64) {
+	self.dc = Some(value);
+    }
+
+    /// Set the AC magnitude and phase (in degrees)
+    pub fn set_ac(&mut self, magnitude: f64, phase: f64) {
+	self.ac_magnitude = magnitude;
+	self.ac_phase = phase;
+    }
+
+    pub fn set_function(&mut self, function: TransientFunction) {
+	self.function = Some(function);
+    }
+
+    pub fn function(&self) -> Option<&TransientFunction> {
+	self.function.as_ref()
+    }
+
+    /// Value used in DC analyses, and for the operating point of an AC
+    /// analysis. The transient operating point uses the value at time
+    /// zero instead (see [value_at](Self::value_at)).
+    pub fn dc_value(&self) -> f64 {
+	match (self.dc, &self.function) {
+	    (Some(dc), _) => dc,
+	    (None, Some(function)) => function.value_at(0.0),
+	    (None, None) => 0.0,
+	}
+    }
+
+    /// Magnitude of the AC value
+    pub fn ac_magnitude(&self) -> f64 {
+	self.ac_magnitude
+    }
+
+    /// Phase of the AC value, in degrees
+    pub fn ac_phase(&self) -> f64 {
+	self.ac_phase
+    }
+
+    /// Phasor used in AC analyses
+    pub fn ac_value(&self) -> Complex<f64> {
+	Complex::from_polar(self.ac_magnitude, self.ac_phase.to_radians())
+    }
+
+    /// Value at time t in a transient analysis
+    pub fn value_at(&self, t: f64) -> f64 {
+	match &self.function {
+	    Some(function) => function.value_at(t),
+	    None => self.dc_value(),
+	}
+    }
+
+    /// Times before stop at which a transient step must finish
+    pub fn breakpoints(&self, stop: f64) -> Vec<f64> {
+	self.function
+	    .as_ref()
+	    .map_or(Vec::new(), |function| function.breakpoints(stop))
+    }
+}
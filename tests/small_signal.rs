@@ -0,0 +1,144 @@
+//! Transfer function, sensitivity and noise analyses of small
+//! circuits, checked against values worked out by hand
+
+use libacdc::{
+    device::{BOLTZMANN, NOMINAL_TEMPERATURE},
+    netlist::Netlist,
+};
+
+/// Check that a value is within a relative tolerance of the expected
+/// value
+fn assert_close(value: f64, expected: f64, tolerance: f64) {
+    assert!(
+	(value - expected).abs() <= tolerance * expected.abs(),
+	"{} is not within {} of {}",
+	value,
+	tolerance,
+	expected
+    );
+}
+
+/// A divider driving a VCVS, whose output goes through a second
+/// divider. V(a) = 3/4 V1 and V(b) = 10 V(a), so V(out) = 3.75 V1.
+/// The source sees R1 + R2, and the output sees R3 || R4 because the
+/// VCVS holds b.
+#[test]
+fn transfer_function_of_divider_and_vcvs() {
+    let netlist = Netlist::parse(
+	"V1 in 0 1
+	 R1 in a 1k
+	 R2 a 0 3k
+	 E1 b 0 a 0 10
+	 R3 b out 2k
+	 R4 out 0 2k
+	 .tf V(out) V1",
+    )
+    .unwrap();
+    let tf = netlist.transfer_function().unwrap();
+    assert_close(tf.gain, 3.75, 1e-9);
+    assert_close(tf.input_resistance, 4e3, 1e-9);
+    assert_close(tf.output_resistance, 1e3, 1e-9);
+}
+
+/// A current source into a resistor, whose current is the output.
+/// The source sees R1 + R2, and the output current is all of it. The
+/// output is in series with the ideal source, so it sees an infinite
+/// resistance.
+#[test]
+fn transfer_function_to_current() {
+    let netlist = Netlist::parse(
+	"I1 0 a 1m
+	 R1 a b 1k
+	 R2 b 0 2k
+	 .tf I(R2) I1",
+    )
+    .unwrap();
+    let tf = netlist.transfer_function().unwrap();
+    assert_close(tf.gain, 1.0, 1e-9);
+    assert_close(tf.input_resistance, 3e3, 1e-9);
+    assert!((1.0 / tf.output_resistance).abs() < 1e-12);
+}
+
+/// V(out) = V1 R2 / (R1 + R2), so dV/dR1 = -V1 R2 / (R1 + R2)^2,
+/// dV/dR2 = V1 R1 / (R1 + R2)^2 and dV/dV1 = R2 / (R1 + R2)
+#[test]
+fn sensitivity_of_divider() {
+    let netlist = Netlist::parse(
+	"V1 in 0 10
+	 R1 in out 1k
+	 R2 out 0 3k
+	 .sens V(out)",
+    )
+    .unwrap();
+    let solution = netlist.sensitivity_analysis().unwrap().solve().unwrap();
+    assert_close(solution.output(), 7.5, 1e-9);
+
+    let r1 = solution.find("r1", "r").unwrap();
+    assert_close(r1.absolute, -10.0 * 3e3 / 16e6, 1e-9);
+    assert_close(r1.relative, -10.0 * 3e3 / 16e6 * 1e3 / 7.5, 1e-9);
+    let r2 = solution.find("r2", "r").unwrap();
+    assert_close(r2.absolute, 10.0 * 1e3 / 16e6, 1e-9);
+    let v1 = solution.find("v1", "dc").unwrap();
+    assert_close(v1.absolute, 0.75, 1e-9);
+}
+
+/// With the source at zero the output is zero, so the relative
+/// sensitivities are zero rather than NaN
+#[test]
+fn sensitivity_of_zero_output() {
+    let netlist = Netlist::parse(
+	"V1 in 0 0
+	 R1 in out 1k
+	 R2 out 0 3k
+	 .sens V(out)",
+    )
+    .unwrap();
+    let solution = netlist.sensitivity_analysis().unwrap().solve().unwrap();
+    assert_eq!(solution.output(), 0.0);
+    let v1 = solution.find("v1", "dc").unwrap();
+    assert_close(v1.absolute, 0.75, 1e-9);
+    for sensitivity in solution.sensitivities() {
+	assert_eq!(sensitivity.relative, 0.0);
+    }
+}
+
+/// The thermal noise current 4kT/R of each resistor flows through
+/// R1 || R2, so the output noise is 4kT (R1 || R2). The gain from V1
+/// is R2 / (R1 + R2).
+#[test]
+fn noise_of_divider() {
+    let netlist = Netlist::parse(
+	"V1 in 0 DC 0 AC 1
+	 R1 in out 1k
+	 R2 out 0 1k
+	 .noise V(out) V1 lin 3 1k 3k",
+    )
+    .unwrap();
+    let solution = netlist.noise_analysis().unwrap().solve().unwrap();
+    let four_kt = 4.0 * BOLTZMANN * NOMINAL_TEMPERATURE;
+    for (k, density) in solution.output_density().iter().enumerate() {
+	assert_close(*density, four_kt * 500.0, 1e-9);
+	assert_close(solution.gain()[k].norm(), 0.5, 1e-9);
+	assert_close(solution.input_density()[k], four_kt * 2e3, 1e-9);
+    }
+    let r1 = solution.element_density("r1").unwrap();
+    assert_close(r1[0], four_kt * 250.0, 1e-9);
+}
+
+/// A resistor alone, driven by a current source, has output noise
+/// 4kTR and a gain of R from the source
+#[test]
+fn noise_of_resistor() {
+    let netlist = Netlist::parse(
+	"I1 0 out DC 0 AC 1
+	 R1 out 0 10k
+	 .noise V(out) I1 dec 1 1k 10k",
+    )
+    .unwrap();
+    let solution = netlist.noise_analysis().unwrap().solve().unwrap();
+    let four_kt = 4.0 * BOLTZMANN * NOMINAL_TEMPERATURE;
+    for (k, density) in solution.output_density().iter().enumerate() {
+	assert_close(*density, four_kt * 10e3, 1e-9);
+	assert_close(solution.gain()[k].norm(), 10e3, 1e-9);
+    }
+}
@@ -0,0 +1,110 @@
+//! Transient analysis of an RC circuit, checked against its step
+//! response worked out by hand
+
+use libacdc::{
+    circuit::Circuit,
+    error::AcdcError,
+    transient::{IntegrationMethod, TransientAnalysis, TransientOptions, Waveforms},
+};
+
+const R: f64 = 1e3;
+const C: f64 = 1e-6;
+const TAU: f64 = R * C;
+
+/// A 1 V source charging C through R, with the capacitor starting
+/// discharged, so that V(out) = 1 - exp(-t/RC)
+fn rc_step(step: f64, stop: f64, options: TransientOptions) -> TransientAnalysis {
+    let mut circuit = Circuit::new();
+    circuit.add_independent_voltage_source("v1", "in", "0", 1.0).unwrap();
+    circuit.add_resistor("r1", "in", "out", false, R).unwrap();
+    circuit.add_capacitor("c1", "out", "0", false, C).unwrap();
+    let mut tran = TransientAnalysis::new(circuit, step, stop);
+    tran.set_options(options);
+    tran.use_initial_conditions(true);
+    tran
+}
+
+/// Largest difference between V(out) and 1 - exp(-t/RC)
+fn max_error(waveforms: &Waveforms) -> f64 {
+    let v = waveforms.v("out").unwrap();
+    waveforms
+	.time()
+	.iter()
+	.zip(v)
+	.map(|(t, v)| (v - (1.0 - (-t / TAU).exp())).abs())
+	.fold(0.0, f64::max)
+}
+
+#[test]
+fn rc_step_fixed_step() {
+    let step = TAU / 1000.0;
+    for (method, tolerance) in [
+	(IntegrationMethod::BackwardEuler, 5e-4),
+	(IntegrationMethod::Trapezoidal, 1e-5),
+	(IntegrationMethod::Gear2, 1e-5),
+    ] {
+	let options = TransientOptions {
+	    method,
+	    fixed_step: true,
+	    ..TransientOptions::default()
+	};
+	let waveforms = rc_step(step, 5.0 * TAU, options).solve().unwrap();
+	// Every step is the suggested timestep, ending on the stop time
+	let time = waveforms.time();
+	assert!(time.windows(2).all(|t| (t[1] - t[0] - step).abs() < 1e-6 * step));
+	assert_eq!(*time.last().unwrap(), 5.0 * TAU);
+	let error = max_error(&waveforms);
+	assert!(error < tolerance, "{:?} error {}", method, error);
+    }
+}
+
+/// Tighter tolerances make the truncation error control take shorter
+/// steps, and the steps grow as the response settles
+#[test]
+fn rc_step_truncation_error_control() {
+    for method in [IntegrationMethod::Trapezoidal, IntegrationMethod::Gear2] {
+	let loose = TransientOptions {
+	    method,
+	    ..TransientOptions::default()
+	};
+	let tight = TransientOptions {
+	    reltol: 1e-6,
+	    vntol: 1e-9,
+	    ..loose.clone()
+	};
+	let loose = rc_step(TAU, 10.0 * TAU, loose).solve().unwrap();
+	let tight = rc_step(TAU, 10.0 * TAU, tight).solve().unwrap();
+	assert!(tight.time().len() > loose.time().len());
+	assert!(max_error(&tight) < 1e-3, "{:?} error {}", method, max_error(&tight));
+
+	let steps: Vec<f64> = tight.time().windows(2).map(|t| t[1] - t[0]).collect();
+	assert!(steps.iter().any(|h| *h > 2.0 * steps[0]));
+    }
+}
+
+#[test]
+fn rc_step_breakpoints() {
+    let breakpoint = 0.37 * TAU;
+    let mut tran = rc_step(TAU / 10.0, 2.0 * TAU, TransientOptions::default());
+    tran.add_breakpoint(breakpoint);
+    let waveforms = tran.solve().unwrap();
+    let time = waveforms.time();
+    assert!(time.contains(&breakpoint));
+    assert_eq!(*time.last().unwrap(), 2.0 * TAU);
+    assert!(time.windows(2).all(|t| t[1] > t[0]));
+    assert!(max_error(&waveforms) < 1e-3);
+}
+
+#[test]
+fn invalid_analysis() {
+    let mut tran = rc_step(0.0, TAU, TransientOptions::default());
+    assert!(matches!(tran.solve(), Err(AcdcError::InvalidAnalysis(_))));
+    assert!(matches!(
+	tran.set_initial_condition("0", 1.0),
+	Err(AcdcError::InvalidAnalysis(_))
+    ));
+    assert!(matches!(
+	tran.set_initial_condition("nowhere", 1.0),
+	Err(AcdcError::InvalidAnalysis(_))
+    ));
+}